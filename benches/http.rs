@@ -4,7 +4,7 @@ use bencher::Bencher;
 
 use parsed::stream::ByteStream;
 use parsed::http::parse_http_request;
-use parsed::ws::parse_frame;
+use parsed::ws::{parse_frame, Frame};
 
 fn http_request(b: &mut Bencher) {
     let text = "GET /chat HTTP/1.1\r\nHost: example.com:8000\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
@@ -26,7 +26,35 @@ fn ws_frame(b: &mut Bencher) {
     b.bytes = bytes.len() as u64;
 }
 
+fn masked_64k() -> Frame {
+    let mut frame = Frame::text("");
+    frame.body = (0..65536u32).map(|x| x as u8).collect();
+    frame.len = frame.body.len() as u32;
+    frame.mask = Some([87, 35, 230, 82]);
+    frame
+}
+
+fn ws_unmask_bytewise(b: &mut Bencher) {
+    let frame = masked_64k();
+    let mask = frame.mask.unwrap();
+    b.iter(|| {
+        let mut body = frame.body.clone();
+        for i in 0..body.len() {
+            body[i] ^= mask[i % 4];
+        }
+        body
+    });
+    b.bytes = frame.body.len() as u64;
+}
 
+fn ws_unmask_in_place(b: &mut Bencher) {
+    let mut frame = masked_64k();
+    b.iter(|| {
+        frame.mask = Some([87, 35, 230, 82]);
+        frame.unmask_in_place();
+    });
+    b.bytes = frame.body.len() as u64;
+}
 
-benchmark_group!(http, http_request, ws_frame);
+benchmark_group!(http, http_request, ws_frame, ws_unmask_bytewise, ws_unmask_in_place);
 benchmark_main!(http);
@@ -0,0 +1,54 @@
+#[macro_use]
+extern crate bencher;
+use bencher::Bencher;
+
+use parsed::stream::ByteStream;
+
+const SIZES: [u64; 8] = [0, 7, 42, 1024, 65535, 1_000_000, 123_456_789, u64::MAX];
+
+fn hex_format(b: &mut Bencher) {
+    let mut bs = ByteStream::with_capacity(256);
+    b.iter(|| {
+        bs.clear();
+        for n in SIZES.iter() {
+            bs.put_str(&format!("{:x}", n));
+            bs.put_crlf();
+        }
+    });
+}
+
+fn hex_put(b: &mut Bencher) {
+    let mut bs = ByteStream::with_capacity(256);
+    b.iter(|| {
+        bs.clear();
+        for n in SIZES.iter() {
+            bs.put_hex(*n);
+            bs.put_crlf();
+        }
+    });
+}
+
+fn decimal_format(b: &mut Bencher) {
+    let mut bs = ByteStream::with_capacity(256);
+    b.iter(|| {
+        bs.clear();
+        for n in SIZES.iter() {
+            bs.put_str(&format!("{}", n));
+            bs.put_crlf();
+        }
+    });
+}
+
+fn decimal_put(b: &mut Bencher) {
+    let mut bs = ByteStream::with_capacity(256);
+    b.iter(|| {
+        bs.clear();
+        for n in SIZES.iter() {
+            bs.put_decimal_u64(*n);
+            bs.put_crlf();
+        }
+    });
+}
+
+benchmark_group!(stream, hex_format, hex_put, decimal_format, decimal_put);
+benchmark_main!(stream);
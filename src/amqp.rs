@@ -0,0 +1,355 @@
+// AMQP 0-9-1 frame layer: the protocol header, frame envelopes and field tables.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{
+    bytes, exact, get_f32, get_f64, get_i16, get_i32, get_i64, get_i8, get_u16, get_u32, get_u64, get_u8,
+    lazy, length_value,
+};
+use crate::stream::ByteStream;
+use std::convert::TryFrom;
+
+pub const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+
+pub const FRAME_END: u8 = 0xCE;
+
+// RabbitMQ's default for the negotiated frame-max, see `frame_max` for other limits
+pub const FRAME_MAX: u32 = 131_072;
+
+// nesting of tables and arrays in field values
+pub const MAX_DEPTH: usize = 64;
+
+// "AMQP" 0 major minor revision, the version is returned so a server can answer a
+// mismatch with its own header before closing
+pub fn protocol_header() -> impl Matcher<(u8, u8, u8)> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = (exact(b"AMQP"), exact([0u8]), get_u8(), get_u8(), get_u8())
+            .do_match(bs)
+            .map(|(_, _, major, minor, revision)| (major, minor, revision));
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameType {
+    Method,
+    Header,
+    Body,
+    Heartbeat,
+}
+
+impl FrameType {
+    pub fn from_u8(b: u8) -> Option<FrameType> {
+        match b {
+            1 => Some(FrameType::Method),
+            2 => Some(FrameType::Header),
+            3 => Some(FrameType::Body),
+            8 => Some(FrameType::Heartbeat),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameType,
+    pub channel: u16,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MethodId {
+    pub class: u16,
+    pub method: u16,
+}
+
+impl MethodId {
+    pub const CONNECTION_START: MethodId = MethodId { class: 10, method: 10 };
+    pub const CONNECTION_START_OK: MethodId = MethodId { class: 10, method: 11 };
+    pub const CONNECTION_TUNE: MethodId = MethodId { class: 10, method: 30 };
+    pub const CONNECTION_OPEN: MethodId = MethodId { class: 10, method: 40 };
+    pub const CONNECTION_CLOSE: MethodId = MethodId { class: 10, method: 50 };
+    pub const CHANNEL_OPEN: MethodId = MethodId { class: 20, method: 10 };
+    pub const CHANNEL_CLOSE: MethodId = MethodId { class: 20, method: 40 };
+    pub const QUEUE_DECLARE: MethodId = MethodId { class: 50, method: 10 };
+    pub const BASIC_PUBLISH: MethodId = MethodId { class: 60, method: 40 };
+    pub const BASIC_DELIVER: MethodId = MethodId { class: 60, method: 60 };
+    pub const BASIC_ACK: MethodId = MethodId { class: 60, method: 80 };
+}
+
+impl Frame {
+    // class and method ids of a method frame, with the undecoded arguments
+    pub fn method(&self) -> Result<(MethodId, &[u8]), MatchError> {
+        if self.kind != FrameType::Method {
+            return Err(MatchError::unexpected(0, format!("{:?} frame", self.kind), "method frame".to_string()));
+        }
+        match self.payload.get(..4) {
+            Some(id) => {
+                let class = u16::from_be_bytes([id[0], id[1]]);
+                let method = u16::from_be_bytes([id[2], id[3]]);
+                Ok((MethodId { class, method }, &self.payload[4..]))
+            }
+            None => Err(MatchError::unexpected(7, "short method frame".to_string(), "class and method id".to_string())),
+        }
+    }
+}
+
+// type, channel, size, payload and the frame-end octet; nothing is consumed on failure
+pub fn frame() -> impl Matcher<Frame> {
+    frame_max(FRAME_MAX)
+}
+
+// as `frame`, with payloads over `max` bytes rejected before anything is buffered for them
+pub fn frame_max(max: u32) -> impl Matcher<Frame> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = (get_u8(), get_u16(), get_u32())
+            .do_match(bs)
+            .and_then(|(kind, channel, size)| {
+                let kind = FrameType::from_u8(kind).ok_or_else(|| {
+                    MatchError::unexpected(pos, format!("frame type {}", kind), "frame type 1, 2, 3 or 8".to_string())
+                })?;
+                if size > max {
+                    return Err(MatchError::over_capacity(pos + 3, max as usize, size as usize));
+                }
+                let (payload, end) = (bytes(size as usize), get_u8()).do_match(bs)?;
+                if end != FRAME_END {
+                    let offset = pos + 7 + size as usize;
+                    return Err(MatchError::unexpected(offset, format!("byte {}", end), "frame-end octet".to_string()));
+                }
+                Ok(Frame { kind, channel, payload })
+            });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// the size field at offset 3 is 32 bits wide
+fn frame_size(len: usize) -> Result<u32, MatchError> {
+    u32::try_from(len).map_err(|_| MatchError::over_capacity(3, u32::MAX as usize, len))
+}
+
+// fails, writing nothing, when the payload does not fit the size field
+pub fn put_frame(out: &mut Vec<u8>, kind: FrameType, channel: u16, payload: &[u8]) -> Result<(), MatchError> {
+    let size = frame_size(payload.len())?;
+    let kind = match kind {
+        FrameType::Method => 1u8,
+        FrameType::Header => 2,
+        FrameType::Body => 3,
+        FrameType::Heartbeat => 8,
+    };
+    out.push(kind);
+    out.extend_from_slice(&channel.to_be_bytes());
+    out.extend_from_slice(&size.to_be_bytes());
+    out.extend_from_slice(payload);
+    out.push(FRAME_END);
+    Ok(())
+}
+
+// field values as RabbitMQ encodes them (which differs from the 0-9-1 spec for 's' and 'u')
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Decimal { scale: u8, value: u32 },
+    LongString(Vec<u8>),
+    Bytes(Vec<u8>),
+    Array(Vec<FieldValue>),
+    Timestamp(u64),
+    Table(Vec<(String, FieldValue)>),
+    Void,
+}
+
+pub fn short_string() -> impl Matcher<String> {
+    get_u8()
+        .then_with(|n| bytes(*n as usize))
+        .map(|(_, vec)| String::from_utf8_lossy(&vec).to_string())
+}
+
+pub fn long_string() -> impl Matcher<Vec<u8>> {
+    get_u32().then_with(|n| bytes(*n as usize)).map(|(_, vec)| vec)
+}
+
+pub fn field_value() -> impl Matcher<FieldValue> {
+    value_at(0)
+}
+
+fn value_at(depth: usize) -> impl Matcher<FieldValue> {
+    move |bs: &mut ByteStream| {
+        if depth > MAX_DEPTH {
+            return Err(MatchError::custom(bs.pos(), format!("nesting deeper than {}", MAX_DEPTH)));
+        }
+        let pos = bs.pos();
+        let tag = get_u8().do_match(bs)?;
+        let value = match tag {
+            b't' => FieldValue::Bool(get_u8().do_match(bs)? != 0),
+            b'b' => FieldValue::I8(get_i8().do_match(bs)?),
+            b'B' => FieldValue::U8(get_u8().do_match(bs)?),
+            b's' => FieldValue::I16(get_i16().do_match(bs)?),
+            b'u' => FieldValue::U16(get_u16().do_match(bs)?),
+            b'I' => FieldValue::I32(get_i32().do_match(bs)?),
+            b'i' => FieldValue::U32(get_u32().do_match(bs)?),
+            b'l' => FieldValue::I64(get_i64().do_match(bs)?),
+            b'f' => FieldValue::F32(get_f32().do_match(bs)?),
+            b'd' => FieldValue::F64(get_f64().do_match(bs)?),
+            b'D' => {
+                let (scale, value) = (get_u8(), get_u32()).do_match(bs)?;
+                FieldValue::Decimal { scale, value }
+            }
+            b'S' => FieldValue::LongString(long_string().do_match(bs)?),
+            b'x' => FieldValue::Bytes(long_string().do_match(bs)?),
+            b'A' => {
+                let items = to_end(lazy(move || value_at(depth + 1).boxed()));
+                FieldValue::Array(length_value(get_u32(), items).do_match(bs)?)
+            }
+            b'T' => FieldValue::Timestamp(get_u64().do_match(bs)?),
+            b'F' => FieldValue::Table(table_at(depth + 1).do_match(bs)?),
+            b'V' => FieldValue::Void,
+            _ => {
+                let got = format!("field type '{}'", tag as char);
+                return Err(MatchError::unexpected(pos, got, "AMQP field value type".to_string()));
+            }
+        };
+        Ok(value)
+    }
+}
+
+// a u32 byte length followed by (short string name, field value) pairs
+pub fn field_table() -> impl Matcher<Vec<(String, FieldValue)>> {
+    table_at(0)
+}
+
+fn table_at(depth: usize) -> impl Matcher<Vec<(String, FieldValue)>> {
+    length_value(get_u32(), to_end((short_string(), lazy(move || value_at(depth).boxed()))))
+}
+
+// every item until the (length-limited) stream ends, the first malformed item fails it
+fn to_end<T: 'static>(item: impl Matcher<T>) -> impl Matcher<Vec<T>> {
+    move |bs: &mut ByteStream| {
+        let mut acc = vec![];
+        while bs.remaining() > 0 {
+            acc.push(item.do_match(bs)?);
+        }
+        Ok(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::MatchErrorKind;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn header_and_frames() {
+        let mut out = PROTOCOL_HEADER.to_vec();
+        put_frame(&mut out, FrameType::Method, 0, &[0, 10, 0, 30, 0, 0, 0, 2, 0, 0, 0, 60]).unwrap();
+        put_frame(&mut out, FrameType::Heartbeat, 0, &[]).unwrap();
+        let mut bs = ByteStream::wrap(out);
+        assert_eq!(bs.apply(protocol_header()).unwrap(), (0, 9, 1));
+
+        let tune = bs.apply(frame()).unwrap();
+        let (id, args) = tune.method().unwrap();
+        assert_eq!(id, MethodId::CONNECTION_TUNE);
+        assert_eq!(args.len(), 8);
+        assert_eq!(bs.apply(frame()).unwrap().kind, FrameType::Heartbeat);
+        assert!(bs.apply(frame()).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn frame_size_limit() {
+        assert_eq!(frame_size(u32::MAX as usize).unwrap(), u32::MAX);
+        if let Some(len) = (u32::MAX as usize).checked_add(1) {
+            assert_eq!(frame_size(len).unwrap_err().kind(), MatchErrorKind::OverCapacity);
+        }
+    }
+
+    #[test]
+    fn malformed_frames() {
+        let mut bs = ByteStream::wrap(vec![8, 0, 0, 0, 0, 0, 0, 0xAB]);
+        let err = bs.apply(frame()).unwrap_err();
+        assert_eq!(err.offset(), 7);
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(vec![4, 0, 0, 0, 0, 0, 0, FRAME_END]);
+        assert!(bs.apply(frame()).is_err());
+        let mut bs = ByteStream::wrap(vec![3, 0, 1, 0, 0, 0, 10, 1, 2]);
+        assert!(bs.apply(frame()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(b"HTTP/1.1".to_vec());
+        assert!(bs.apply(protocol_header()).is_err());
+
+        // the size is checked before waiting for the payload
+        let mut bs = ByteStream::wrap(vec![3, 0, 1, 0x7F, 0xFF, 0xFF, 0xFF]);
+        let err = bs.apply(frame()).unwrap_err();
+        assert!(!err.is_incomplete());
+        assert_eq!(err.offset(), 3);
+        let mut bs = ByteStream::wrap(vec![3, 0, 1, 0, 0, 0, 2, 1, 2, FRAME_END]);
+        assert!(bs.apply(frame_max(1)).is_err());
+        assert_eq!(bs.apply(frame_max(2)).unwrap().payload, vec![1, 2]);
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // each level is a length and a field of type `kind` holding the next level
+        fn nested(levels: usize, kind: &[u8]) -> Vec<u8> {
+            let mut data = vec![];
+            for i in 0..levels {
+                let len = ((levels - 1 - i) * (4 + kind.len())) as u32;
+                data.extend_from_slice(&len.to_be_bytes());
+                if i + 1 < levels {
+                    data.extend_from_slice(kind);
+                }
+            }
+            data
+        }
+        let err = ByteStream::wrap(nested(10_000, b"\x00F")).apply(field_table()).unwrap_err();
+        assert!(err.to_string().contains("nesting"));
+        assert!(ByteStream::wrap(nested(MAX_DEPTH, b"\x00F")).apply(field_table()).is_ok());
+
+        let mut array = vec![b'A'];
+        array.extend_from_slice(&nested(10_000, b"A"));
+        let err = ByteStream::wrap(array).apply(field_value()).unwrap_err();
+        assert!(err.to_string().contains("nesting"));
+    }
+
+    #[test]
+    fn tables() {
+        let mut body = vec![];
+        body.extend_from_slice(b"\x07product");
+        body.extend_from_slice(b"S\x00\x00\x00\x08RabbitMQ");
+        body.extend_from_slice(b"\x0ccapabilities");
+        body.extend_from_slice(b"F\x00\x00\x00\x0d\x0apublisherst\x01");
+        body.extend_from_slice(b"\x04list");
+        body.extend_from_slice(b"A\x00\x00\x00\x06I\xff\xff\xff\xffV");
+        let mut table = (body.len() as u32).to_be_bytes().to_vec();
+        table.extend_from_slice(&body);
+
+        let mut bs = ByteStream::wrap(table.clone());
+        let fields = bs.apply(field_table()).unwrap();
+        assert_eq!(fields, vec![
+            ("product".to_string(), FieldValue::LongString(b"RabbitMQ".to_vec())),
+            ("capabilities".to_string(), FieldValue::Table(vec![("publishers".to_string(), FieldValue::Bool(true))])),
+            ("list".to_string(), FieldValue::Array(vec![FieldValue::I32(-1), FieldValue::Void])),
+        ]);
+        assert_eq!(bs.remaining(), 0);
+
+        let n = table.len();
+        table[n - 6] = b'?';
+        assert!(ByteStream::wrap(table).apply(field_table()).is_err());
+    }
+}
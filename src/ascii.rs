@@ -0,0 +1,45 @@
+// locale-independent: only ASCII letters are folded, all other bytes compare as-is
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.eq_ignore_ascii_case(b)
+}
+
+pub fn to_lower(s: &str) -> String {
+    s.chars().map(|c| c.to_ascii_lowercase()).collect()
+}
+
+// "content-length" -> "Content-Length", "x-FORWARDED-for" -> "X-Forwarded-For"
+pub fn canonical_header_name(name: &str) -> String {
+    let mut upper = true;
+    name.chars()
+        .map(|c| {
+            let r = if upper {
+                c.to_ascii_uppercase()
+            } else {
+                c.to_ascii_lowercase()
+            };
+            upper = c == '-';
+            r
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_case() {
+        assert!(eq_ignore_case("Content-Length", "content-LENGTH"));
+        assert!(!eq_ignore_case("Content-Length", "Content-Lengt"));
+        assert!(!eq_ignore_case("ä", "Ä"));
+        assert_eq!(to_lower("HoSt"), "host");
+    }
+
+    #[test]
+    fn canonical() {
+        assert_eq!(canonical_header_name("content-length"), "Content-Length");
+        assert_eq!(canonical_header_name("x-FORWARDED-for"), "X-Forwarded-For");
+        assert_eq!(canonical_header_name("WWW-Authenticate"), "Www-Authenticate");
+        assert_eq!(canonical_header_name(""), "");
+    }
+}
@@ -0,0 +1,66 @@
+// Parsing many independent buffers (log lines, captured packets) with one matcher.
+use crate::matcher::{MatchError, Matcher};
+use crate::stream::ByteStream;
+
+// one result per buffer, in input order; each buffer is parsed from its start
+pub fn parse_many<'a, T, M, I>(buffers: I, matcher: M) -> Vec<Result<T, MatchError>>
+where
+    M: Matcher<T>,
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    buffers.into_iter().map(|buf| parse_one(buf, &matcher)).collect()
+}
+
+fn parse_one<T, M: Matcher<T>>(buf: &[u8], matcher: &M) -> Result<T, MatchError> {
+    let mut bs = ByteStream::wrap(buf.to_vec());
+    matcher.do_match(&mut bs)
+}
+
+// same as `parse_many`, with the buffers split in contiguous runs over scoped threads
+#[cfg(feature = "parallel")]
+pub fn parse_many_parallel<'a, T, M, I>(buffers: I, matcher: M) -> Vec<Result<T, MatchError>>
+where
+    T: Send,
+    M: Matcher<T> + Sync,
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let buffers = buffers.into_iter().collect::<Vec<&[u8]>>();
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk = buffers.len().div_ceil(threads).max(1);
+    let matcher = &matcher;
+    std::thread::scope(|scope| {
+        let handles = buffers
+            .chunks(chunk)
+            .map(|run| scope.spawn(move || run.iter().map(|buf| parse_one(buf, matcher)).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::get_u16;
+
+    #[test]
+    fn results_in_order() {
+        let buffers: Vec<&[u8]> = vec![&[0, 1], &[2], &[1, 0]];
+        let results = parse_many(buffers, get_u16());
+        assert_eq!(results[0].as_ref().unwrap(), &1);
+        assert!(results[1].as_ref().unwrap_err().is_incomplete());
+        assert_eq!(results[2].as_ref().unwrap(), &256);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_sequential() {
+        use crate::parser::get_u8;
+        let data = (0..1000u32).map(|x| vec![(x % 256) as u8; (x % 3) as usize]).collect::<Vec<Vec<u8>>>();
+        let sequential = parse_many(data.iter().map(|v| v.as_slice()), get_u8());
+        let parallel = parse_many_parallel(data.iter().map(|v| v.as_slice()), get_u8());
+        assert_eq!(sequential.len(), parallel.len());
+        for (a, b) in sequential.iter().zip(parallel.iter()) {
+            assert_eq!(a.as_ref().ok(), b.as_ref().ok());
+        }
+    }
+}
@@ -0,0 +1,480 @@
+// CBOR (RFC 8949) data items; the "preferred serialization" is used for encoding, and
+// `encode_canonical_into` adds the map key ordering of core deterministic encoding.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{bytes, get_u16, get_u32, get_u64, get_u8};
+use crate::stream::ByteStream;
+use std::convert::TryFrom;
+
+pub const MAX_DEPTH: usize = 128;
+
+const BREAK: u8 = 0xFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    // major types 0 and 1: -2^64 ..= 2^64 - 1
+    Integer(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    // in input order, duplicate keys are kept
+    Map(Vec<(Value, Value)>),
+    Tag(u64, Box<Value>),
+    Bool(bool),
+    Null,
+    Undefined,
+    // simple values other than false, true, null and undefined
+    Simple(u8),
+    Float(f64),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, Value::Text(t) if t == key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_i128(&self) -> Option<i128> {
+        match self {
+            Value::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // shortest argument and float forms, definite lengths, maps in their own order; fails
+    // (writing nothing) on an Integer outside -2^64 ..= 2^64 - 1
+    pub fn encode_into(&self, out: &mut Vec<u8>) -> Result<(), MatchError> {
+        self.encode_from(out, false)
+    }
+
+    // as `encode_into` with map entries sorted by the bytes of their encoded keys
+    pub fn encode_canonical_into(&self, out: &mut Vec<u8>) -> Result<(), MatchError> {
+        self.encode_from(out, true)
+    }
+
+    fn encode_from(&self, out: &mut Vec<u8>, canonical: bool) -> Result<(), MatchError> {
+        let start = out.len();
+        let result = self.encode(out, canonical, start);
+        if result.is_err() {
+            out.truncate(start);
+        }
+        result
+    }
+
+    // error offsets are from `start`, a bad key of a canonical map is reported where its
+    // entries begin as they are not sorted yet
+    fn encode(&self, out: &mut Vec<u8>, canonical: bool, start: usize) -> Result<(), MatchError> {
+        match self {
+            Value::Integer(n) => {
+                let (major, arg) = if *n >= 0 { (0, *n) } else { (1, -1 - *n) };
+                let arg = u64::try_from(arg).map_err(|_| {
+                    let expected = "integer within -2^64 ..= 2^64 - 1".to_string();
+                    MatchError::unexpected(out.len() - start, format!("integer {}", n), expected)
+                })?;
+                head(out, major, arg);
+            }
+            Value::Bytes(b) => {
+                head(out, 2, b.len() as u64);
+                out.extend_from_slice(b);
+            }
+            Value::Text(s) => {
+                head(out, 3, s.len() as u64);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Value::Array(items) => {
+                head(out, 4, items.len() as u64);
+                for item in items {
+                    item.encode(out, canonical, start)?;
+                }
+            }
+            Value::Map(entries) => {
+                head(out, 5, entries.len() as u64);
+                if !canonical {
+                    for (key, value) in entries {
+                        key.encode(out, canonical, start)?;
+                        value.encode(out, canonical, start)?;
+                    }
+                    return Ok(());
+                }
+                let at = out.len() - start;
+                let mut encoded = entries
+                    .iter()
+                    .map(|(k, v)| {
+                        let mut key = vec![];
+                        k.encode(&mut key, canonical, 0).map_err(|e| {
+                            let offset = at + e.offset();
+                            e.with_offset(offset)
+                        })?;
+                        Ok((key, v))
+                    })
+                    .collect::<Result<Vec<_>, MatchError>>()?;
+                encoded.sort_by(|a, b| a.0.cmp(&b.0));
+                for (key, value) in encoded {
+                    out.extend_from_slice(&key);
+                    value.encode(out, canonical, start)?;
+                }
+            }
+            Value::Tag(tag, inner) => {
+                head(out, 6, *tag);
+                inner.encode(out, canonical, start)?;
+            }
+            Value::Bool(false) => out.push(0xF4),
+            Value::Bool(true) => out.push(0xF5),
+            Value::Null => out.push(0xF6),
+            Value::Undefined => out.push(0xF7),
+            Value::Simple(n) if *n < 24 => out.push(0xE0 | n),
+            Value::Simple(n) => out.extend_from_slice(&[0xF8, *n]),
+            Value::Float(f) => float(out, *f),
+        }
+        Ok(())
+    }
+}
+
+fn head(out: &mut Vec<u8>, major: u8, arg: u64) {
+    let major = major << 5;
+    if arg < 24 {
+        out.push(major | arg as u8);
+    } else if arg <= u8::MAX as u64 {
+        out.extend_from_slice(&[major | 24, arg as u8]);
+    } else if arg <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(arg as u16).to_be_bytes());
+    } else if arg <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(arg as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&arg.to_be_bytes());
+    }
+}
+
+// the shortest of half, single and double precision that keeps the value, NaN as 0xf97e00
+fn float(out: &mut Vec<u8>, f: f64) {
+    if f.is_nan() {
+        out.extend_from_slice(&[0xF9, 0x7E, 0x00]);
+        return;
+    }
+    if let Some(half) = to_half(f) {
+        out.push(0xF9);
+        out.extend_from_slice(&half.to_be_bytes());
+    } else if (f as f32) as f64 == f {
+        out.push(0xFA);
+        out.extend_from_slice(&(f as f32).to_bits().to_be_bytes());
+    } else {
+        out.push(0xFB);
+        out.extend_from_slice(&f.to_bits().to_be_bytes());
+    }
+}
+
+fn to_half(f: f64) -> Option<u16> {
+    let bits = f.to_bits();
+    let sign = ((bits >> 48) & 0x8000) as u16;
+    let exp = ((bits >> 52) & 0x7FF) as i32 - 1023;
+    let mant = bits & ((1 << 52) - 1);
+    let half = if f == 0.0 {
+        sign
+    } else if f.is_infinite() {
+        sign | 0x7C00
+    } else if (-14..=15).contains(&exp) {
+        sign | ((exp + 15) as u16) << 10 | (mant >> 42) as u16
+    } else if (-24..-14).contains(&exp) {
+        sign | (((1 << 52) | mant) >> (28 - exp)) as u16
+    } else {
+        return None;
+    };
+    if from_half(half) == f {
+        Some(half)
+    } else {
+        None
+    }
+}
+
+fn from_half(half: u16) -> f64 {
+    let exp = (half >> 10) & 0x1F;
+    let mant = (half & 0x3FF) as f64;
+    let value = match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1024.0 + mant) * 2f64.powi(exp as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+// a single data item, nothing is consumed on failure
+pub fn value() -> impl Matcher<Value> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = item(bs, 0).and_then(|v| {
+            v.ok_or_else(|| MatchError::unexpected(pos, "break".to_string(), "data item".to_string()))
+        });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// the argument of a head, None for an indefinite length (additional info 31)
+fn argument(bs: &mut ByteStream, pos: usize, info: u8) -> Result<Option<u64>, MatchError> {
+    match info {
+        0..=23 => Ok(Some(info as u64)),
+        24 => get_u8().map(|n| Some(n as u64)).do_match(bs),
+        25 => get_u16().map(|n| Some(n as u64)).do_match(bs),
+        26 => get_u32().map(|n| Some(n as u64)).do_match(bs),
+        27 => get_u64().map(Some).do_match(bs),
+        31 => Ok(None),
+        _ => Err(MatchError::unexpected(pos, format!("additional info {}", info), "0 to 27 or 31".to_string())),
+    }
+}
+
+// None for the break stop code
+fn item(bs: &mut ByteStream, depth: usize) -> Result<Option<Value>, MatchError> {
+    if depth > MAX_DEPTH {
+        return Err(MatchError::custom(bs.pos(), format!("nesting deeper than {}", MAX_DEPTH)));
+    }
+    let pos = bs.pos();
+    let initial = get_u8().do_match(bs)?;
+    let (major, info) = (initial >> 5, initial & 0x1F);
+    if initial == BREAK {
+        return Ok(None);
+    }
+    if major == 7 {
+        return simple(bs, pos, info).map(Some);
+    }
+    let arg = argument(bs, pos, info)?;
+    let value = match (major, arg) {
+        (0, Some(n)) => Value::Integer(n as i128),
+        (1, Some(n)) => Value::Integer(-1 - n as i128),
+        (2, Some(n)) => Value::Bytes(content(bs, pos, n)?),
+        (3, Some(n)) => Value::Text(text(pos, content(bs, pos, n)?)?),
+        (2, None) | (3, None) => {
+            let mut joined = vec![];
+            while let Some(chunk) = chunk(bs, major)? {
+                joined.extend_from_slice(&chunk);
+            }
+            if major == 2 {
+                Value::Bytes(joined)
+            } else {
+                Value::Text(text(pos, joined)?)
+            }
+        }
+        (4, n) => {
+            let mut items = vec![];
+            while n.map(|n| (items.len() as u64) < n).unwrap_or(true) {
+                match item(bs, depth + 1)? {
+                    Some(v) => items.push(v),
+                    None if n.is_none() => break,
+                    None => return Err(unexpected_break(bs)),
+                }
+            }
+            Value::Array(items)
+        }
+        (5, n) => {
+            let mut entries = vec![];
+            while n.map(|n| (entries.len() as u64) < n).unwrap_or(true) {
+                let key = match item(bs, depth + 1)? {
+                    Some(k) => k,
+                    None if n.is_none() => break,
+                    None => return Err(unexpected_break(bs)),
+                };
+                let value = item(bs, depth + 1)?.ok_or_else(|| unexpected_break(bs))?;
+                entries.push((key, value));
+            }
+            Value::Map(entries)
+        }
+        (6, Some(tag)) => {
+            let inner = item(bs, depth + 1)?.ok_or_else(|| unexpected_break(bs))?;
+            Value::Tag(tag, Box::new(inner))
+        }
+        _ => {
+            return Err(MatchError::unexpected(pos, format!("major type {} of indefinite length", major), "definite length".to_string()));
+        }
+    };
+    Ok(Some(value))
+}
+
+// a definite-length chunk of an indefinite byte or text string, None for the break
+fn chunk(bs: &mut ByteStream, major: u8) -> Result<Option<Vec<u8>>, MatchError> {
+    let pos = bs.pos();
+    let initial = get_u8().do_match(bs)?;
+    if initial == BREAK {
+        return Ok(None);
+    }
+    match argument(bs, pos, initial & 0x1F)? {
+        Some(n) if initial >> 5 == major => Ok(Some(content(bs, pos, n)?)),
+        _ => Err(MatchError::unexpected(pos, format!("{:#04x}", initial), format!("definite chunk of major type {}", major))),
+    }
+}
+
+// string content of `n` bytes, a length from the input is checked before anything is read
+fn content(bs: &mut ByteStream, pos: usize, n: u64) -> Result<Vec<u8>, MatchError> {
+    match usize::try_from(n) {
+        Ok(n) if n <= bs.remaining() => bytes(n).do_match(bs),
+        Ok(n) => Err(MatchError::incomplete(bs.pos(), n - bs.remaining())),
+        Err(_) => Err(MatchError::unexpected(pos, format!("length {}", n), "length within usize".to_string())),
+    }
+}
+
+fn simple(bs: &mut ByteStream, pos: usize, info: u8) -> Result<Value, MatchError> {
+    let value = match info {
+        20 => Value::Bool(false),
+        21 => Value::Bool(true),
+        22 => Value::Null,
+        23 => Value::Undefined,
+        0..=19 => Value::Simple(info),
+        24 => match get_u8().do_match(bs)? {
+            n if n >= 32 => Value::Simple(n),
+            n => return Err(MatchError::unexpected(pos, format!("simple value {} in two bytes", n), "simple value 32 to 255".to_string())),
+        },
+        25 => Value::Float(from_half(get_u16().do_match(bs)?)),
+        26 => Value::Float(f32::from_bits(get_u32().do_match(bs)?) as f64),
+        27 => Value::Float(f64::from_bits(get_u64().do_match(bs)?)),
+        _ => return Err(MatchError::unexpected(pos, format!("additional info {}", info), "simple value or float".to_string())),
+    };
+    Ok(value)
+}
+
+fn text(pos: usize, data: Vec<u8>) -> Result<String, MatchError> {
+    String::from_utf8(data).map_err(|e| MatchError::unexpected(pos, "invalid UTF-8".to_string(), "text string".to_string()).with_source(e))
+}
+
+fn unexpected_break(bs: &ByteStream) -> MatchError {
+    MatchError::unexpected(bs.pos() - 1, "break".to_string(), "data item".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    fn decode(data: &[u8]) -> Value {
+        let mut bs = ByteStream::wrap(data.to_vec());
+        let v = bs.apply(value()).unwrap();
+        assert_eq!(bs.remaining(), 0);
+        v
+    }
+
+    fn encode(v: &Value) -> Vec<u8> {
+        let mut out = vec![];
+        v.encode_into(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 8949, appendix A
+        let cases: Vec<(&[u8], Value)> = vec![
+            (&[0x17], Value::Integer(23)),
+            (&[0x19, 0x03, 0xE8], Value::Integer(1000)),
+            (&[0x1B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], Value::Integer(u64::MAX as i128)),
+            (&[0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], Value::Integer(-(1i128 << 64))),
+            (&[0x38, 0x63], Value::Integer(-100)),
+            (&[0xF9, 0x3C, 0x00], Value::Float(1.0)),
+            (&[0xF9, 0x80, 0x00], Value::Float(-0.0)),
+            (&[0xF9, 0x00, 0x01], Value::Float(5.960464477539063e-8)),
+            (&[0xFA, 0x47, 0xC3, 0x50, 0x00], Value::Float(100000.0)),
+            (&[0xFB, 0x3F, 0xF1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9A], Value::Float(1.1)),
+            (&[0xF9, 0x7C, 0x00], Value::Float(f64::INFINITY)),
+            (&[0xF8, 0xFF], Value::Simple(255)),
+            (&[0xC1, 0x1A, 0x51, 0x4B, 0x67, 0xB0], Value::Tag(1, Box::new(Value::Integer(1363896240)))),
+            (&[0x44, 0x01, 0x02, 0x03, 0x04], Value::Bytes(vec![1, 2, 3, 4])),
+            (&[0x62, 0xC3, 0xBC], Value::Text("\u{fc}".to_string())),
+            (&[0x82, 0x01, 0x82, 0x02, 0x03], Value::Array(vec![
+                Value::Integer(1),
+                Value::Array(vec![Value::Integer(2), Value::Integer(3)]),
+            ])),
+            (&[0xA2, 0x61, 0x61, 0x01, 0x61, 0x62, 0xF6], Value::Map(vec![
+                (Value::Text("a".to_string()), Value::Integer(1)),
+                (Value::Text("b".to_string()), Value::Null),
+            ])),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(decode(data), expected);
+            assert_eq!(encode(&expected), data);
+        }
+        assert_eq!(encode(&Value::Float(f64::NAN)), vec![0xF9, 0x7E, 0x00]);
+        assert!(matches!(decode(&[0xFA, 0x7F, 0xC0, 0x00, 0x00]), Value::Float(f) if f.is_nan()));
+    }
+
+    #[test]
+    fn indefinite_lengths() {
+        assert_eq!(decode(&[0x5F, 0x42, 0x01, 0x02, 0x43, 0x03, 0x04, 0x05, 0xFF]), Value::Bytes(vec![1, 2, 3, 4, 5]));
+        assert_eq!(decode(&[0x7F, 0x65, b's', b't', b'r', b'e', b'a', 0x64, b'm', b'i', b'n', b'g', 0xFF]).as_str(), Some("streaming"));
+        let v = decode(&[0xBF, 0x63, b'F', b'u', b'n', 0xF5, 0x63, b'A', b'm', b't', 0x9F, 0x21, 0xFF, 0xFF]);
+        assert_eq!(v.get("Fun"), Some(&Value::Bool(true)));
+        assert_eq!(v.get("Amt"), Some(&Value::Array(vec![Value::Integer(-2)])));
+        // re-encoded with definite lengths
+        assert_eq!(encode(&v), vec![0xA2, 0x63, b'F', b'u', b'n', 0xF5, 0x63, b'A', b'm', b't', 0x81, 0x21]);
+    }
+
+    #[test]
+    fn canonical_map_order() {
+        let v = Value::Map(vec![
+            (Value::Text("aa".to_string()), Value::Integer(0)),
+            (Value::Integer(-1), Value::Integer(1)),
+            (Value::Text("b".to_string()), Value::Integer(2)),
+            (Value::Integer(10), Value::Integer(3)),
+        ]);
+        let mut out = vec![];
+        v.encode_canonical_into(&mut out).unwrap();
+        assert_eq!(out, vec![0xA4, 0x0A, 0x03, 0x20, 0x01, 0x61, b'b', 0x02, 0x62, b'a', b'a', 0x00]);
+    }
+
+    #[test]
+    fn integer_range() {
+        let mut out = vec![0x00];
+        let v = Value::Array(vec![Value::Integer(1), Value::Integer(1 << 70)]);
+        assert_eq!(v.encode_into(&mut out).unwrap_err().offset(), 2);
+        assert_eq!(out, vec![0x00]);
+
+        let v = Value::Map(vec![(Value::Text("a".to_string()), Value::Integer(-(1 << 64) - 1))]);
+        assert_eq!(v.encode_canonical_into(&mut out).unwrap_err().offset(), 3);
+        let v = Value::Map(vec![(Value::Integer(i128::MIN), Value::Null)]);
+        assert_eq!(v.encode_canonical_into(&mut out).unwrap_err().offset(), 1);
+        assert_eq!(out, vec![0x00]);
+    }
+
+    #[test]
+    fn malformed() {
+        let cases: Vec<(&[u8], usize)> = vec![
+            (&[0x1C], 0),
+            (&[0xFF], 0),
+            (&[0x82, 0x01, 0xFF], 2),
+            (&[0x5F, 0x61, b'a', 0xFF], 1),
+            (&[0x62, 0xFF, 0xFE], 0),
+            (&[0xF8, 0x10], 0),
+            (&[0x1F], 0),
+        ];
+        for (data, offset) in cases {
+            let mut bs = ByteStream::wrap(data.to_vec());
+            assert_eq!(bs.apply(value()).unwrap_err().offset(), offset, "{:x?}", data);
+            assert_eq!(bs.pos(), 0);
+        }
+        let mut bs = ByteStream::wrap(vec![0x83, 0x01, 0x02]);
+        assert!(bs.apply(value()).unwrap_err().is_incomplete());
+        for data in [vec![0x5B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF], vec![0x7F, 0x7B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]] {
+            let mut bs = ByteStream::wrap(data);
+            assert!(bs.apply(value()).is_err());
+            assert_eq!(bs.pos(), 0);
+        }
+        let mut bs = ByteStream::wrap(vec![0x81; MAX_DEPTH + 2]);
+        assert!(bs.apply(value()).unwrap_err().to_string().contains("nesting"));
+    }
+}
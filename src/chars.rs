@@ -0,0 +1,215 @@
+use crate::matcher::{Matcher, MatchError};
+use crate::stream::ByteStream;
+
+const REPLACEMENT: char = '\u{FFFD}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    // invalid sequences are decoded as U+FFFD and skipped
+    Replace,
+    // the first invalid sequence produces an error, is left in the stream and ends the iteration
+    Strict,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded {
+    Char(char, usize),
+    Invalid(usize),
+    Incomplete,
+}
+
+// decode a single UTF-8 encoded char from the start of the slice; an invalid sequence
+// reports the length of its maximal ill-formed subpart, so that each is replaced only once
+pub fn decode(buf: &[u8]) -> Decoded {
+    let b0 = match buf.first() {
+        Some(b) => *b,
+        None => return Decoded::Incomplete,
+    };
+    // the second byte range excludes overlong forms, surrogates and code points over U+10FFFF
+    let (len, init, second) = match b0 {
+        0x00..=0x7F => return Decoded::Char(b0 as char, 1),
+        0xC2..=0xDF => (2, (b0 & 0x1F) as u32, 0x80..=0xBF),
+        0xE0 => (3, (b0 & 0x0F) as u32, 0xA0..=0xBF),
+        0xED => (3, (b0 & 0x0F) as u32, 0x80..=0x9F),
+        0xE1..=0xEF => (3, (b0 & 0x0F) as u32, 0x80..=0xBF),
+        0xF0 => (4, (b0 & 0x07) as u32, 0x90..=0xBF),
+        0xF4 => (4, (b0 & 0x07) as u32, 0x80..=0x8F),
+        0xF1..=0xF3 => (4, (b0 & 0x07) as u32, 0x80..=0xBF),
+        _ => return Decoded::Invalid(1),
+    };
+
+    let mut code = init;
+    for i in 1..len {
+        let range = if i == 1 { second.clone() } else { 0x80..=0xBF };
+        match buf.get(i) {
+            Some(b) if range.contains(b) => code = (code << 6) | (b & 0x3F) as u32,
+            Some(_) => return Decoded::Invalid(i),
+            None => return Decoded::Incomplete,
+        }
+    }
+
+    match std::char::from_u32(code) {
+        Some(c) => Decoded::Char(c, len),
+        None => Decoded::Invalid(len),
+    }
+}
+
+// iterates over chars decoded from the stream, stops at the end of the buffer; on a
+// partial stream also before a truncated sequence (so it can be resumed once more bytes
+// arrive), while on a final one the truncated sequence is invalid
+pub struct CharStream<'a> {
+    bs: &'a mut ByteStream,
+    policy: Policy,
+    failed: bool,
+}
+
+impl<'a> CharStream<'a> {
+    pub fn new(bs: &'a mut ByteStream, policy: Policy) -> CharStream<'a> {
+        CharStream { bs, policy, failed: false }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.bs.pos()
+    }
+
+    fn invalid(&mut self, len: usize) -> Result<char, MatchError> {
+        match self.policy {
+            Policy::Replace => {
+                self.bs.get(len);
+                Ok(REPLACEMENT)
+            }
+            Policy::Strict => {
+                self.failed = true;
+                Err(MatchError::unexpected(
+                    self.bs.pos(),
+                    "invalid UTF-8".to_string(),
+                    "char".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for CharStream<'a> {
+    type Item = Result<char, MatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.bs.remaining() == 0 {
+            return None;
+        }
+        match decode(self.bs.as_ref()) {
+            Decoded::Char(c, len) => {
+                self.bs.get(len);
+                Some(Ok(c))
+            }
+            Decoded::Invalid(len) => Some(self.invalid(len)),
+            Decoded::Incomplete if self.bs.is_partial() => None,
+            Decoded::Incomplete => {
+                let len = self.bs.remaining();
+                Some(self.invalid(len))
+            }
+        }
+    }
+}
+
+pub fn char_where<F: Fn(char) -> bool + 'static>(f: F) -> impl Matcher<char> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        match decode(bs.as_ref()) {
+            Decoded::Char(c, len) if f(c) => {
+                bs.get(len);
+                Ok(c)
+            }
+            Decoded::Char(c, _) => Err(MatchError::unexpected(
+                pos,
+                format!("char '{}'", c),
+                "matching char".to_string(),
+            )),
+            Decoded::Invalid(_) => Err(MatchError::unexpected(
+                pos,
+                "invalid UTF-8".to_string(),
+                "matching char".to_string(),
+            )),
+            Decoded::Incomplete => Err(MatchError::incomplete(pos, 1)),
+        }
+    }
+}
+
+pub fn single_char(chr: char) -> impl Matcher<char> {
+    char_where(move |c| c == chr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{repeat, Applicator};
+
+    #[test]
+    fn decode_multibyte() {
+        assert_eq!(decode(b"a"), Decoded::Char('a', 1));
+        assert_eq!(decode("é".as_bytes()), Decoded::Char('é', 2));
+        assert_eq!(decode("€".as_bytes()), Decoded::Char('€', 3));
+        assert_eq!(decode("🦀".as_bytes()), Decoded::Char('🦀', 4));
+        assert_eq!(decode(&[0xE2, 0x82]), Decoded::Incomplete);
+        assert_eq!(decode(&[0xC0, 0x80]), Decoded::Invalid(1));
+        assert_eq!(decode(&[0xE2, 0x41]), Decoded::Invalid(1));
+        assert_eq!(decode(&[0xED, 0xA0, 0x80]), Decoded::Invalid(1));
+        assert_eq!(decode(&[0xE0, 0x80, 0x80]), Decoded::Invalid(1));
+        assert_eq!(decode(&[0xF4, 0x90, 0x80, 0x80]), Decoded::Invalid(1));
+        assert_eq!(decode(&[0xF0, 0x90, 0x80, 0x41]), Decoded::Invalid(3));
+    }
+
+    #[test]
+    fn maximal_subparts() {
+        // one U+FFFD per maximal ill-formed subsequence (Unicode 3.9, table 3-8)
+        let mut bs = ByteStream::wrap(vec![
+            0x61, 0xF1, 0x80, 0x80, 0xE1, 0x80, 0xC2, 0x62, 0x80, 0x63, 0x80, 0xBF, 0x64,
+        ]);
+        let chars = CharStream::new(&mut bs, Policy::Replace)
+            .collect::<Result<String, _>>()
+            .unwrap();
+        assert_eq!(chars, "a\u{FFFD}\u{FFFD}\u{FFFD}b\u{FFFD}c\u{FFFD}\u{FFFD}d");
+    }
+
+    #[test]
+    fn char_stream_policies() {
+        let mut bs = ByteStream::wrap(vec![b'a', 0xFF, 0xC3, 0xA9, 0xE2, 0x82]);
+        bs.set_partial(true);
+        let chars = CharStream::new(&mut bs, Policy::Replace)
+            .collect::<Result<String, _>>()
+            .unwrap();
+        assert_eq!(chars, "a\u{FFFD}é");
+        assert_eq!(bs.pos(), 4);
+
+        // at the end of a final stream the truncated sequence is invalid
+        bs.set_partial(false);
+        let chars = CharStream::new(&mut bs, Policy::Replace)
+            .collect::<Result<String, _>>()
+            .unwrap();
+        assert_eq!(chars, "\u{FFFD}");
+        assert_eq!(bs.pos(), 6);
+
+        let mut bs = ByteStream::wrap(vec![b'a', 0xFF, b'b']);
+        let mut cs = CharStream::new(&mut bs, Policy::Strict);
+        assert_eq!(cs.next().unwrap().unwrap(), 'a');
+        assert!(cs.next().unwrap().is_err());
+        assert!(cs.next().is_none());
+        assert_eq!(cs.pos(), 1);
+
+        let mut bs = ByteStream::wrap(vec![b'a', 0xE2, 0x82]);
+        let mut cs = CharStream::new(&mut bs, Policy::Strict);
+        assert_eq!(cs.next().unwrap().unwrap(), 'a');
+        assert!(cs.next().unwrap().is_err());
+        assert!(cs.next().is_none());
+    }
+
+    #[test]
+    fn char_matchers() {
+        let mut bs: ByteStream = "ñandú!".to_string().into();
+        assert_eq!(bs.apply(single_char('ñ')).unwrap(), 'ñ');
+        let word = bs.apply(repeat(char_where(char::is_alphabetic))).unwrap();
+        assert_eq!(word.into_iter().collect::<String>(), "andú");
+        assert!(bs.apply(single_char('?')).is_err());
+        assert_eq!(bs.apply(single_char('!')).unwrap(), '!');
+    }
+}
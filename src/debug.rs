@@ -0,0 +1,132 @@
+use crate::matcher::Matcher;
+use crate::stream::ByteStream;
+use std::ops::Range;
+
+const ROW: usize = 16;
+
+// label and byte range of the input that produced a field
+pub type Span = (String, Range<usize>);
+
+// run the matcher over the input and render a hexdump with markers under every reported span
+pub fn annotate<M: Matcher<Vec<Span>>>(input: &[u8], matcher: M) -> String {
+    let mut bs = ByteStream::wrap(input.to_vec());
+    match matcher.do_match(&mut bs) {
+        Ok(spans) => {
+            let mut out = hexdump(input, &spans);
+            out.push_str(&format!("consumed {} of {} bytes\n", bs.pos(), input.len()));
+            out
+        }
+        Err(e) => {
+            let mut out = hexdump(input, &[(String::from("error"), e.offset()..e.offset() + 1)]);
+            out.push_str(&format!("{}\n", e));
+            out
+        }
+    }
+}
+
+pub fn hexdump(input: &[u8], spans: &[Span]) -> String {
+    let mut out = String::new();
+    for (row, chunk) in input.chunks(ROW).enumerate() {
+        let lo = row * ROW;
+        let hi = lo + chunk.len();
+
+        out.push_str(&format!("{:08x}  ", lo));
+        for i in 0..ROW {
+            match chunk.get(i) {
+                Some(b) => out.push_str(&format!("{:02x} ", b)),
+                None => out.push_str("   "),
+            }
+            if i == ROW / 2 - 1 {
+                out.push(' ');
+            }
+        }
+        out.push_str(" |");
+        out.extend(chunk.iter().map(|b| printable(*b)));
+        out.push_str("|\n");
+
+        for (label, range) in spans {
+            if range.start >= hi || range.end <= lo || range.start >= range.end {
+                continue;
+            }
+            out.push_str(&marker(lo, range));
+            out.push_str(label);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn marker(lo: usize, range: &Range<usize>) -> String {
+    let mut line = String::from("          ");
+    for i in 0..ROW {
+        let at = lo + i;
+        let inside = range.start <= at && at < range.end;
+        let next = at + 1 < range.end && i + 1 < ROW;
+        let cell = match (inside, at == range.start, at + 1 == range.end) {
+            (false, _, _) => "  ",
+            (true, true, true) => "[]",
+            (true, true, false) => "[-",
+            (true, false, true) => "-]",
+            (true, false, false) => "--",
+        };
+        line.push_str(cell);
+        line.push(if inside && next { '-' } else { ' ' });
+        if i == ROW / 2 - 1 {
+            line.push(if inside && next { '-' } else { ' ' });
+        }
+    }
+    line.push(' ');
+    line
+}
+
+fn printable(b: u8) -> char {
+    if b.is_ascii_graphic() || b == b' ' {
+        b as char
+    } else {
+        '.'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{before, single, unit};
+
+    #[test]
+    fn hexdump_spans() {
+        let input = b"GET / HTTP/1.1\r\n";
+        let spans = vec![("method".to_string(), 0..3), ("path".to_string(), 4..5)];
+        let out = hexdump(input, &spans);
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|"
+        );
+        assert_eq!(lines[1].trim_end(), "          [------]                                          method");
+        assert_eq!(lines[2].trim_end(), "                      []                                    path");
+    }
+
+    #[test]
+    fn annotate_matcher() {
+        let m = unit(Vec::new)
+            .then(before(' '))
+            .map(|(mut spans, method): (Vec<Span>, Vec<u8>)| {
+                spans.push(("method".to_string(), 0..method.len()));
+                spans
+            })
+            .then(single(' '))
+            .map(|(spans, _)| spans);
+
+        let out = annotate(b"GET /", m);
+        assert!(out.contains("method"));
+        assert!(out.ends_with("consumed 4 of 5 bytes\n"));
+    }
+
+    #[test]
+    fn annotate_error() {
+        let out = annotate(b"abc", single('x').map(|_| vec![]));
+        assert!(out.contains("error"));
+        assert!(out.contains("offset 0"));
+    }
+}
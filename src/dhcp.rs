@@ -0,0 +1,349 @@
+// DHCP (RFC 2131, 2132) messages: the fixed BOOTP header, the magic cookie and the options,
+// including option overload into the file/sname fields and long options split in parts (RFC 3396).
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{exact, get_array, get_u16, get_u32, get_u8};
+use crate::stream::ByteStream;
+use std::convert::TryInto;
+
+pub const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+pub const BOOTREQUEST: u8 = 1;
+pub const BOOTREPLY: u8 = 2;
+
+pub const DHCPDISCOVER: u8 = 1;
+pub const DHCPOFFER: u8 = 2;
+pub const DHCPREQUEST: u8 = 3;
+pub const DHCPDECLINE: u8 = 4;
+pub const DHCPACK: u8 = 5;
+pub const DHCPNAK: u8 = 6;
+pub const DHCPRELEASE: u8 = 7;
+pub const DHCPINFORM: u8 = 8;
+
+const PAD: u8 = 0;
+const END: u8 = 255;
+const OVERLOAD: u8 = 52;
+
+pub type Addr = [u8; 4];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpOption {
+    SubnetMask(Addr),
+    Router(Vec<Addr>),
+    DnsServers(Vec<Addr>),
+    HostName(String),
+    DomainName(String),
+    RequestedIp(Addr),
+    LeaseTime(u32),
+    MessageType(u8),
+    ServerId(Addr),
+    ParameterList(Vec<u8>),
+    ClientId(Vec<u8>),
+    // anything else, and known options with a value of the wrong shape
+    Other(u8, Vec<u8>),
+}
+
+impl DhcpOption {
+    pub fn code(&self) -> u8 {
+        match self {
+            DhcpOption::SubnetMask(_) => 1,
+            DhcpOption::Router(_) => 3,
+            DhcpOption::DnsServers(_) => 6,
+            DhcpOption::HostName(_) => 12,
+            DhcpOption::DomainName(_) => 15,
+            DhcpOption::RequestedIp(_) => 50,
+            DhcpOption::LeaseTime(_) => 51,
+            DhcpOption::MessageType(_) => 53,
+            DhcpOption::ServerId(_) => 54,
+            DhcpOption::ParameterList(_) => 55,
+            DhcpOption::ClientId(_) => 61,
+            DhcpOption::Other(code, _) => *code,
+        }
+    }
+
+    pub fn value(&self) -> Vec<u8> {
+        match self {
+            DhcpOption::SubnetMask(a) | DhcpOption::RequestedIp(a) | DhcpOption::ServerId(a) => a.to_vec(),
+            DhcpOption::Router(addrs) | DhcpOption::DnsServers(addrs) => addrs.concat(),
+            DhcpOption::HostName(s) | DhcpOption::DomainName(s) => s.as_bytes().to_vec(),
+            DhcpOption::LeaseTime(t) => t.to_be_bytes().to_vec(),
+            DhcpOption::MessageType(t) => vec![*t],
+            DhcpOption::ParameterList(v) | DhcpOption::ClientId(v) | DhcpOption::Other(_, v) => v.clone(),
+        }
+    }
+
+    fn typed(code: u8, value: Vec<u8>) -> DhcpOption {
+        let addr = |v: &[u8]| -> Option<Addr> { v.try_into().ok() };
+        let addrs = |v: &[u8]| -> Option<Vec<Addr>> {
+            if v.is_empty() || !v.len().is_multiple_of(4) {
+                return None;
+            }
+            Some(v.chunks(4).filter_map(addr).collect())
+        };
+        let text = |v: &[u8]| String::from_utf8(v.to_vec()).ok();
+        let typed = match code {
+            1 => addr(&value).map(DhcpOption::SubnetMask),
+            3 => addrs(&value).map(DhcpOption::Router),
+            6 => addrs(&value).map(DhcpOption::DnsServers),
+            12 => text(&value).map(DhcpOption::HostName),
+            15 => text(&value).map(DhcpOption::DomainName),
+            50 => addr(&value).map(DhcpOption::RequestedIp),
+            51 => value.as_slice().try_into().ok().map(|b| DhcpOption::LeaseTime(u32::from_be_bytes(b))),
+            53 if value.len() == 1 => Some(DhcpOption::MessageType(value[0])),
+            54 => addr(&value).map(DhcpOption::ServerId),
+            55 => Some(DhcpOption::ParameterList(value.clone())),
+            61 => Some(DhcpOption::ClientId(value.clone())),
+            _ => None,
+        };
+        typed.unwrap_or(DhcpOption::Other(code, value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub op: u8,
+    pub htype: u8,
+    pub hlen: u8,
+    pub hops: u8,
+    pub xid: u32,
+    pub secs: u16,
+    pub flags: u16,
+    pub ciaddr: Addr,
+    pub yiaddr: Addr,
+    pub siaddr: Addr,
+    pub giaddr: Addr,
+    pub chaddr: [u8; 16],
+    // empty when the field carried overloaded options
+    pub sname: Vec<u8>,
+    pub file: Vec<u8>,
+    // merged from all option areas, the overload option itself is not kept
+    pub options: Vec<DhcpOption>,
+}
+
+impl Message {
+    pub fn option(&self, code: u8) -> Option<&DhcpOption> {
+        self.options.iter().find(|o| o.code() == code)
+    }
+
+    pub fn message_type(&self) -> Option<u8> {
+        match self.option(53) {
+            Some(DhcpOption::MessageType(t)) => Some(*t),
+            _ => None,
+        }
+    }
+
+    // the hardware address, `hlen` bytes of `chaddr`
+    pub fn client_hardware_address(&self) -> &[u8] {
+        &self.chaddr[..(self.hlen as usize).min(16)]
+    }
+
+    // options go into the options area only, values over 255 bytes are split in parts
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.op, self.htype, self.hlen, self.hops]);
+        out.extend_from_slice(&self.xid.to_be_bytes());
+        out.extend_from_slice(&self.secs.to_be_bytes());
+        out.extend_from_slice(&self.flags.to_be_bytes());
+        for addr in [self.ciaddr, self.yiaddr, self.siaddr, self.giaddr] {
+            out.extend_from_slice(&addr);
+        }
+        out.extend_from_slice(&self.chaddr);
+        for (field, len) in [(&self.sname, 64), (&self.file, 128)] {
+            let n = field.len().min(len);
+            out.extend_from_slice(&field[..n]);
+            out.resize(out.len() + len - n, 0);
+        }
+        out.extend_from_slice(&MAGIC_COOKIE);
+        for option in &self.options {
+            let value = option.value();
+            if value.is_empty() {
+                out.extend_from_slice(&[option.code(), 0]);
+            }
+            for part in value.chunks(255) {
+                out.push(option.code());
+                out.push(part.len() as u8);
+                out.extend_from_slice(part);
+            }
+        }
+        out.push(END);
+    }
+}
+
+// code/length/value options up to END, merging repeated codes; the fixed-size file and
+// sname fields may also simply run out
+fn read_options(bs: &mut ByteStream, require_end: bool, acc: &mut Vec<(u8, Vec<u8>)>) -> Result<(), MatchError> {
+    loop {
+        if !require_end && bs.remaining() == 0 {
+            return Ok(());
+        }
+        let code = get_u8().do_match(bs)?;
+        match code {
+            PAD => continue,
+            END => return Ok(()),
+            _ => (),
+        }
+        let len = get_u8().do_match(bs)? as usize;
+        let pos = bs.pos();
+        let value = bs.get(len).ok_or_else(|| MatchError::incomplete(pos, len - bs.remaining()))?;
+        match acc.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, v)) => v.extend_from_slice(&value),
+            None => acc.push((code, value)),
+        }
+    }
+}
+
+fn read_message(bs: &mut ByteStream) -> Result<Message, MatchError> {
+    let start = bs.pos();
+    let (op, htype, hlen, hops, xid, secs, flags) =
+        (get_u8(), get_u8(), get_u8(), get_u8(), get_u32(), get_u16(), get_u16()).do_match(bs)?;
+    let a = get_array::<4>;
+    let (ciaddr, yiaddr, siaddr, giaddr) = (a(), a(), a(), a()).do_match(bs)?;
+    let (chaddr, sname, file) = (get_array::<16>(), get_array::<64>(), get_array::<128>()).do_match(bs)?;
+    exact(MAGIC_COOKIE).do_match(bs)?;
+
+    let mut raw = vec![];
+    read_options(bs, true, &mut raw)?;
+    let overload = raw.iter().position(|(c, _)| *c == OVERLOAD).map(|idx| raw.remove(idx).1);
+    let overload = overload.and_then(|v| v.first().copied()).unwrap_or(0);
+    // RFC 2131, section 4.1: the file field is read before sname
+    let mut fields = [(1, 108, "file", file.to_vec()), (2, 44, "sname", sname.to_vec())];
+    for (bit, at, name, field) in fields.iter_mut() {
+        if overload & *bit != 0 {
+            // the field is complete, an option running past its end is malformed
+            let mut area = ByteStream::wrap(std::mem::take(field));
+            read_options(&mut area, false, &mut raw).map_err(|e| {
+                let offset = start + *at + e.offset();
+                MatchError::unexpected(offset, "truncated option".to_string(), format!("options within {}", name)).with_source(e)
+            })?;
+        } else {
+            let n = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+            field.truncate(n);
+        }
+    }
+    let [(_, _, _, file), (_, _, _, sname)] = fields;
+
+    Ok(Message {
+        op,
+        htype,
+        hlen,
+        hops,
+        xid,
+        secs,
+        flags,
+        ciaddr,
+        yiaddr,
+        siaddr,
+        giaddr,
+        chaddr,
+        sname,
+        file,
+        options: raw.into_iter().map(|(code, value)| DhcpOption::typed(code, value)).collect(),
+    })
+}
+
+// one message up to and including the END option; nothing is consumed on failure
+pub fn message() -> impl Matcher<Message> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = read_message(bs);
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::MatchErrorKind;
+    use crate::parser::Applicator;
+
+    fn discover() -> Message {
+        Message {
+            op: BOOTREQUEST,
+            htype: 1,
+            hlen: 6,
+            hops: 0,
+            xid: 0x3903_F326,
+            secs: 0,
+            flags: 0x8000,
+            ciaddr: [0; 4],
+            yiaddr: [0; 4],
+            siaddr: [0; 4],
+            giaddr: [0; 4],
+            chaddr: [0x00, 0x0c, 0x29, 0x8b, 0x11, 0x22, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            sname: vec![],
+            file: b"pxelinux.0".to_vec(),
+            options: vec![
+                DhcpOption::MessageType(DHCPDISCOVER),
+                DhcpOption::ParameterList(vec![1, 3, 6, 15]),
+                DhcpOption::HostName("x".repeat(300)),
+                DhcpOption::Router(vec![[10, 0, 0, 1], [10, 0, 0, 2]]),
+            ],
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let msg = discover();
+        let mut out = vec![];
+        msg.encode_into(&mut out);
+        assert_eq!(&out[236..240], &MAGIC_COOKIE);
+        out.extend_from_slice(&[0, 0, 0]);
+
+        let mut bs = ByteStream::wrap(out);
+        let parsed = bs.apply(message()).unwrap();
+        assert_eq!(parsed, msg);
+        assert_eq!(parsed.message_type(), Some(DHCPDISCOVER));
+        assert_eq!(parsed.client_hardware_address(), &[0x00, 0x0c, 0x29, 0x8b, 0x11, 0x22]);
+        assert_eq!(bs.remaining(), 3);
+    }
+
+    #[test]
+    fn overload() {
+        let mut msg = discover();
+        msg.options = vec![DhcpOption::MessageType(DHCPOFFER), DhcpOption::Other(OVERLOAD, vec![3])];
+        msg.file = vec![51, 4, 0, 0, 0x0e, 0x10, END];
+        msg.sname = vec![PAD, 1, 4, 255, 255, 255, 0];
+        let mut out = vec![];
+        msg.encode_into(&mut out);
+
+        let mut bs = ByteStream::wrap(out);
+        let parsed = bs.apply(message()).unwrap();
+        assert!(parsed.file.is_empty() && parsed.sname.is_empty());
+        assert_eq!(parsed.options, vec![
+            DhcpOption::MessageType(DHCPOFFER),
+            DhcpOption::LeaseTime(3600),
+            DhcpOption::SubnetMask([255, 255, 255, 0]),
+        ]);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut out = vec![];
+        discover().encode_into(&mut out);
+        let mut bad = out.clone();
+        bad[236] = 0;
+        let mut bs = ByteStream::wrap(bad);
+        assert_eq!(bs.apply(message()).unwrap_err().offset(), 236);
+        assert_eq!(bs.pos(), 0);
+
+        out.pop();
+        let mut bs = ByteStream::wrap(out);
+        assert!(bs.apply(message()).unwrap_err().is_incomplete());
+
+        assert_eq!(DhcpOption::typed(1, vec![255, 0]), DhcpOption::Other(1, vec![255, 0]));
+
+        // an overloaded sname whose last option claims more bytes than the field has left
+        let mut msg = discover();
+        msg.options = vec![DhcpOption::Other(OVERLOAD, vec![2])];
+        msg.sname = vec![0; 64];
+        msg.sname[60..].copy_from_slice(&[1, 4, 255, 255]);
+        let mut out = vec![];
+        msg.encode_into(&mut out);
+        let mut bs = ByteStream::wrap(out);
+        let err = bs.apply(message()).unwrap_err();
+        assert!(!err.is_incomplete());
+        assert_eq!(err.kind(), MatchErrorKind::Unexpected);
+        assert_eq!(err.offset(), 44 + 62);
+    }
+}
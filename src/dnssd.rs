@@ -0,0 +1,226 @@
+// DNS-SD (RFC 6763) helpers for the record data of service discovery answers: TXT
+// attributes, SRV targets and service instance names.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{get_u16, get_u8};
+use crate::stream::ByteStream;
+use std::convert::TryInto;
+
+// TXT rdata: length-prefixed strings of `key=value`, or a bare `key` for a boolean attribute
+// (None); keys compare case-insensitively and only the first occurrence counts (section 6.4)
+pub type TxtEntry = (String, Option<Vec<u8>>);
+
+pub fn txt_entries(rdata: &[u8]) -> Result<Vec<TxtEntry>, MatchError> {
+    let mut entries: Vec<TxtEntry> = vec![];
+    let mut pos = 0;
+    while pos < rdata.len() {
+        let len = rdata[pos] as usize;
+        let item = rdata.get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| MatchError::incomplete(rdata.len(), pos + 1 + len - rdata.len()))?;
+        pos += 1 + len;
+        let (key, value) = match item.iter().position(|b| *b == b'=') {
+            Some(idx) => (&item[..idx], Some(item[idx + 1..].to_vec())),
+            None => (item, None),
+        };
+        // an empty string, or one starting with '=', carries no attribute
+        if key.is_empty() {
+            continue;
+        }
+        if !key.iter().all(|b| (0x20..=0x7E).contains(b)) {
+            let got = String::from_utf8_lossy(key).to_string();
+            return Err(MatchError::unexpected(pos - len, got, "printable ASCII key".to_string()));
+        }
+        let key = String::from_utf8_lossy(key).to_string();
+        if !entries.iter().any(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+            entries.push((key, value));
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    // dotted, without the trailing root label
+    pub target: String,
+}
+
+// SRV rdata with an uncompressed target name; a compression pointer is rejected, it can
+// only be followed with the whole DNS message at hand
+pub fn srv() -> impl Matcher<Srv> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = (get_u16(), get_u16(), get_u16()).do_match(bs).and_then(|(priority, weight, port)| {
+            let target = name(bs)?;
+            Ok(Srv { priority, weight, port, target })
+        });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// labels are joined with '.', dots and backslashes inside a label are escaped as in
+// presentation format, so `split_instance_name` can take the result apart again
+fn name(bs: &mut ByteStream) -> Result<String, MatchError> {
+    let mut labels = vec![];
+    loop {
+        let pos = bs.pos();
+        let len = get_u8().do_match(bs)?;
+        if len == 0 {
+            return Ok(labels.join("."));
+        }
+        if len & 0xC0 != 0 {
+            return Err(MatchError::unexpected(pos, format!("label type {:#x}", len & 0xC0), "uncompressed label".to_string()));
+        }
+        let label = bs.get(len as usize).ok_or_else(|| MatchError::incomplete(pos + 1, len as usize - bs.remaining()))?;
+        labels.push(escape(&label));
+    }
+}
+
+fn escape(label: &[u8]) -> String {
+    let mut out = String::new();
+    for b in label {
+        match b {
+            b'.' | b'\\' => {
+                out.push('\\');
+                out.push(*b as char);
+            }
+            0x21..=0x7E => out.push(*b as char),
+            _ => out.push_str(&format!("\\{:03}", b)),
+        }
+    }
+    out
+}
+
+// `\.`, `\\` and `\DDD` (decimal) escapes of a presentation-format label; instance names
+// are UTF-8 and may contain dots and any punctuation (section 4.3)
+pub fn unescape_label(label: &str) -> Result<String, MatchError> {
+    let bytes = label.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let digits = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(u8::is_ascii_digit));
+        match (digits, bytes.get(i + 1)) {
+            (Some(d), _) => {
+                let n = d.iter().fold(0u32, |acc, d| acc * 10 + (d - b'0') as u32);
+                let b = n.try_into().map_err(|_| {
+                    MatchError::unexpected(i, format!("\\{}", n), "escape of at most \\255".to_string())
+                })?;
+                out.push(b);
+                i += 4;
+            }
+            (None, Some(c)) => {
+                out.push(*c);
+                i += 2;
+            }
+            (None, None) => return Err(MatchError::incomplete(bytes.len(), 1)),
+        }
+    }
+    String::from_utf8(out)
+        .map_err(|e| MatchError::unexpected(0, "invalid UTF-8".to_string(), "instance name".to_string()).with_source(e))
+}
+
+// "My Printer\.2._ipp._tcp.local" into the unescaped instance ("My Printer.2"), the service
+// ("_ipp._tcp") and the domain ("local"); the service is the last pair of labels starting
+// with '_', whatever comes before it is the instance, itself a single label
+pub fn split_instance_name(name: &str) -> Result<(String, String, String), MatchError> {
+    // (offset, label)
+    let mut labels = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in name.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '.' => {
+                labels.push((start, &name[start..i]));
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    labels.push((start, &name[start..]));
+    let proto = labels.iter().rposition(|(_, l)| l.starts_with('_'));
+    let domain = proto.map(|idx| {
+        labels[idx + 1..].iter().map(|(_, l)| *l).filter(|l| !l.is_empty()).collect::<Vec<_>>()
+    });
+    match (proto, domain) {
+        (Some(idx), Some(domain)) if idx >= 2 && labels[idx - 1].1.starts_with('_') && !domain.is_empty() => {
+            let instance = &name[..labels[idx - 1].0 - 1];
+            if instance.is_empty() {
+                return Err(MatchError::unexpected(0, "empty label".to_string(), "instance name".to_string()));
+            }
+            Ok((
+                unescape_label(instance)?,
+                format!("{}.{}", labels[idx - 1].1, labels[idx].1),
+                domain.join("."),
+            ))
+        }
+        _ => Err(MatchError::unexpected(0, name.to_string(), "<instance>.<_service>.<_proto>.<domain>".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn txt() {
+        let rdata = b"\x09txtvers=1\x07Color=F\x00\x05color\x06=dummy\x05paper\x08note=a=b";
+        let entries = txt_entries(rdata).unwrap();
+        assert_eq!(entries, vec![
+            ("txtvers".to_string(), Some(b"1".to_vec())),
+            ("Color".to_string(), Some(b"F".to_vec())),
+            ("paper".to_string(), None),
+            ("note".to_string(), Some(b"a=b".to_vec())),
+        ]);
+        assert!(txt_entries(b"\x05abc").unwrap_err().is_incomplete());
+        assert!(txt_entries(b"\x03a\x01b").is_err());
+    }
+
+    #[test]
+    fn srv_record() {
+        let mut rdata = vec![0, 0, 0, 0, 0x02, 0x77];
+        rdata.extend_from_slice(b"\x0bprinter.lan\x05local\x00");
+        let mut bs = ByteStream::wrap(rdata);
+        let srv = bs.apply(srv()).unwrap();
+        assert_eq!(srv.port, 631);
+        assert_eq!(srv.target, "printer\\.lan.local");
+
+        let mut bs = ByteStream::wrap(vec![0, 0, 0, 0, 0, 1, 0xC0, 0x0C]);
+        assert_eq!(bs.apply(super::srv()).unwrap_err().offset(), 6);
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn instance_names() {
+        let (instance, service, domain) = split_instance_name("My Printer\\.2 \\226\\152\\131._ipp._tcp.local.").unwrap();
+        assert_eq!(instance, "My Printer.2 \u{2603}");
+        assert_eq!(service, "_ipp._tcp");
+        assert_eq!(domain, "local");
+
+        assert_eq!(unescape_label("a\\\\b\\.c").unwrap(), "a\\b.c");
+        assert!(unescape_label("\\300").is_err());
+        assert!(split_instance_name("_ipp._tcp.local").is_err());
+        assert!(split_instance_name("._ipp._tcp.local").is_err());
+        assert!(split_instance_name("Printer._ipp._tcp.").is_err());
+
+        let (instance, service, domain) = split_instance_name("_hidden._ipp._tcp.example.com").unwrap();
+        assert_eq!(instance, "_hidden");
+        assert_eq!(service, "_ipp._tcp");
+        assert_eq!(domain, "example.com");
+
+        let (instance, service, _) = split_instance_name("Office v2.1._http._tcp.local").unwrap();
+        assert_eq!(instance, "Office v2.1");
+        assert_eq!(service, "_http._tcp");
+    }
+}
@@ -0,0 +1,291 @@
+// Precedence climbing (Pratt parsing) over prefix, infix and postfix operators, the tree is
+// built by the fold callback.
+use crate::matcher::{MatchError, Matcher};
+use crate::stream::ByteStream;
+
+// nested prefix operators and right-associative operands, each level is a recursive call
+pub const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+// operator matcher (producing a value handed to the fold callback), precedence and associativity,
+// higher precedence binds tighter; operators are tried in order, so list longer ones first
+pub type Op<O> = (Box<dyn Matcher<O>>, u8, Assoc);
+
+// what the fold callback of `pratt` is asked to combine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Apply<T, O> {
+    Prefix(O, T),
+    Infix(T, O, T),
+    Postfix(T, O),
+}
+
+// operator table for `pratt`; a prefix operator takes as its operand everything that binds
+// tighter than its own precedence, so with `-` below `^`, `-2^2` is `-(2^2)`
+pub struct Operators<O> {
+    prefix: Vec<Op<O>>,
+    infix: Vec<Op<O>>,
+    postfix: Vec<Op<O>>,
+}
+
+impl<O> Default for Operators<O> {
+    fn default() -> Self {
+        Operators::new()
+    }
+}
+
+impl<O> Operators<O> {
+    pub fn new() -> Operators<O> {
+        Operators {
+            prefix: vec![],
+            infix: vec![],
+            postfix: vec![],
+        }
+    }
+
+    pub fn prefix(mut self, m: impl Matcher<O> + 'static, prec: u8) -> Self {
+        self.prefix.push((m.boxed(), prec, Assoc::Right));
+        self
+    }
+
+    pub fn infix(mut self, m: impl Matcher<O> + 'static, prec: u8, assoc: Assoc) -> Self {
+        self.infix.push((m.boxed(), prec, assoc));
+        self
+    }
+
+    pub fn postfix(mut self, m: impl Matcher<O> + 'static, prec: u8) -> Self {
+        self.postfix.push((m.boxed(), prec, Assoc::Left));
+        self
+    }
+}
+
+// binary operators only
+pub fn expr<T, O, A, F>(atom: A, ops: Vec<Op<O>>, fold: F) -> impl Matcher<T>
+where
+    A: Matcher<T>,
+    F: Fn(T, O, T) -> T + 'static,
+{
+    let table = Operators {
+        prefix: vec![],
+        infix: ops,
+        postfix: vec![],
+    };
+    pratt(atom, table, move |apply| match apply {
+        Apply::Infix(lhs, op, rhs) => fold(lhs, op, rhs),
+        _ => unreachable!("no unary operators"),
+    })
+}
+
+pub fn pratt<T, O, A, F>(atom: A, ops: Operators<O>, fold: F) -> impl Matcher<T>
+where
+    A: Matcher<T>,
+    F: Fn(Apply<T, O>) -> T + 'static,
+{
+    move |bs: &mut ByteStream| climb(bs, &atom, &ops, &fold, 0, 0)
+}
+
+fn climb<T, O>(
+    bs: &mut ByteStream,
+    atom: &dyn Matcher<T>,
+    ops: &Operators<O>,
+    fold: &dyn Fn(Apply<T, O>) -> T,
+    min: u8,
+    depth: usize,
+) -> Result<T, MatchError> {
+    if depth > MAX_DEPTH {
+        // fatal, so that no enclosing level backtracks and tries again
+        return Err(MatchError::custom(bs.pos(), format!("nesting deeper than {}", MAX_DEPTH)).into_fatal());
+    }
+    let mut lhs = operand(bs, atom, ops, fold, depth)?;
+    loop {
+        let mark = bs.mark();
+        match operator(bs, &ops.postfix)? {
+            Some((op, prec, _)) if prec >= min => {
+                lhs = fold(Apply::Postfix(lhs, op));
+                continue;
+            }
+            _ => bs.reset(mark),
+        }
+        let (op, prec, assoc) = match operator(bs, &ops.infix)? {
+            Some((op, prec, assoc)) if prec >= min => (op, prec, assoc),
+            _ => {
+                bs.reset(mark);
+                return Ok(lhs);
+            }
+        };
+        let next = match assoc {
+            Assoc::Left => prec.saturating_add(1),
+            Assoc::Right => prec,
+        };
+        match climb(bs, atom, ops, fold, next, depth + 1) {
+            Ok(rhs) => lhs = fold(Apply::Infix(lhs, op, rhs)),
+            Err(e) if e.is_committed(bs) => return Err(e),
+            // a trailing operator is left unconsumed
+            Err(_) => {
+                bs.reset(mark);
+                return Ok(lhs);
+            }
+        }
+    }
+}
+
+// a prefix operator without an operand is given to the atom instead, e.g. a signed literal
+fn operand<T, O>(
+    bs: &mut ByteStream,
+    atom: &dyn Matcher<T>,
+    ops: &Operators<O>,
+    fold: &dyn Fn(Apply<T, O>) -> T,
+    depth: usize,
+) -> Result<T, MatchError> {
+    let mark = bs.mark();
+    if let Some((op, prec, _)) = operator(bs, &ops.prefix)? {
+        match climb(bs, atom, ops, fold, prec, depth + 1) {
+            Ok(rhs) => return Ok(fold(Apply::Prefix(op, rhs))),
+            Err(e) if e.is_committed(bs) => return Err(e),
+            Err(_) => bs.reset(mark),
+        }
+    }
+    atom.do_match(bs)
+}
+
+fn operator<O>(bs: &mut ByteStream, ops: &[Op<O>]) -> Result<Option<(O, u8, Assoc)>, MatchError> {
+    let mark = bs.mark();
+    for (m, prec, assoc) in ops {
+        match m.do_match(bs) {
+            Ok(op) => return Ok(Some((op, *prec, *assoc))),
+            Err(e) if e.is_committed(bs) => return Err(e),
+            Err(_) => bs.reset(mark),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{get_u8, many1, maybe, single, space, Applicator};
+
+    fn num() -> impl Matcher<i64> {
+        many1(get_u8().verify(|b| b.is_ascii_digit()))
+            .map(|ds| ds.into_iter().fold(0i64, |acc, d| acc * 10 + (d - b'0') as i64))
+    }
+
+    fn op(c: char) -> Box<dyn Matcher<char>> {
+        maybe(space()).then(single(c)).then(maybe(space())).map(|((_, c), _)| c).boxed()
+    }
+
+    fn calc() -> impl Matcher<i64> {
+        let ops = vec![
+            (op('+'), 1, Assoc::Left),
+            (op('-'), 1, Assoc::Left),
+            (op('*'), 2, Assoc::Left),
+            (op('/'), 2, Assoc::Left),
+            (op('^'), 3, Assoc::Right),
+        ];
+        expr(num(), ops, |a, op, b| match op {
+            '+' => a + b,
+            '-' => a - b,
+            '*' => a * b,
+            '/' => a / b,
+            _ => a.pow(b as u32),
+        })
+    }
+
+    #[test]
+    fn precedence() {
+        let mut bs = ByteStream::wrap(b"1 + 2 * 3 - 4".to_vec());
+        assert_eq!(bs.apply(calc()).unwrap(), 3);
+
+        let mut bs = ByteStream::wrap(b"100/10/5".to_vec());
+        assert_eq!(bs.apply(calc()).unwrap(), 2);
+
+        let mut bs = ByteStream::wrap(b"2^3^2*2".to_vec());
+        assert_eq!(bs.apply(calc()).unwrap(), 1024);
+    }
+
+    #[test]
+    fn trailing_operator() {
+        let mut bs = ByteStream::wrap(b"1+2+;".to_vec());
+        assert_eq!(bs.apply(calc()).unwrap(), 3);
+        assert_eq!(bs.pos(), 3);
+
+        let mut bs = ByteStream::wrap(b"+1".to_vec());
+        assert!(bs.apply(calc()).is_err());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Q {
+        Field(char),
+        Not(Box<Q>),
+        Exists(Box<Q>),
+        And(Box<Q>, Box<Q>),
+        Or(Box<Q>, Box<Q>),
+    }
+
+    fn query() -> impl Matcher<Q> {
+        let field = get_u8().verify(|b| b.is_ascii_lowercase()).map(|b| Q::Field(b as char));
+        let ops = Operators::new()
+            .prefix(op('!'), 3)
+            .postfix(op('?'), 4)
+            .infix(op('&'), 2, Assoc::Left)
+            .infix(op('|'), 1, Assoc::Left);
+        pratt(field, ops, |apply| match apply {
+            Apply::Prefix(_, q) => Q::Not(Box::new(q)),
+            Apply::Postfix(q, _) => Q::Exists(Box::new(q)),
+            Apply::Infix(a, '&', b) => Q::And(Box::new(a), Box::new(b)),
+            Apply::Infix(a, _, b) => Q::Or(Box::new(a), Box::new(b)),
+        })
+    }
+
+    fn show(q: &Q) -> String {
+        match q {
+            Q::Field(c) => c.to_string(),
+            Q::Not(q) => format!("!{}", show(q)),
+            Q::Exists(q) => format!("{}?", show(q)),
+            Q::And(a, b) => format!("({} & {})", show(a), show(b)),
+            Q::Or(a, b) => format!("({} | {})", show(a), show(b)),
+        }
+    }
+
+    #[test]
+    fn unary_operators() {
+        let mut bs = ByteStream::wrap(b"!a | b & !!c? & d".to_vec());
+        assert_eq!(show(&bs.apply(query()).unwrap()), "(!a | ((b & !!c?) & d))");
+
+        let mut bs = ByteStream::wrap(b"a??".to_vec());
+        assert_eq!(bs.apply(query()).unwrap(), Q::Exists(Box::new(Q::Exists(Box::new(Q::Field('a'))))));
+
+        let mut bs = ByteStream::wrap(b"!".to_vec());
+        assert!(bs.apply(query()).is_err());
+
+        // the prefix minus falls back to the atom, which reads a signed literal
+        let signed = maybe(single('-')).then(num()).map(|(s, n)| if s.is_some() { -n } else { n });
+        let ops = Operators::new().prefix(op('-'), 3).infix(op('-'), 1, Assoc::Left);
+        let m = pratt(signed, ops, |apply| match apply {
+            Apply::Prefix(_, n) => -n,
+            Apply::Infix(a, _, b) => a - b,
+            Apply::Postfix(n, _) => n,
+        });
+        let mut bs = ByteStream::wrap(b"--5 - 2".to_vec());
+        assert_eq!(bs.apply(m).unwrap(), 3);
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let mut input = vec![b'!'; 200_000];
+        input.push(b'a');
+        let err = ByteStream::wrap(input).apply(query()).unwrap_err();
+        assert!(err.to_string().contains("nesting"));
+
+        let mut input = vec![b'!'; 100];
+        input.push(b'a');
+        assert!(ByteStream::wrap(input).apply(query()).is_ok());
+
+        let input = "2^".repeat(100_000) + "2";
+        assert!(ByteStream::wrap(input.into_bytes()).apply(calc()).is_err());
+    }
+}
@@ -0,0 +1,538 @@
+use crate::inflate::{deflate_stored, inflate};
+use crate::parser::MatchError;
+use crate::ws::Frame;
+
+const RSV1: u8 = 0b100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offer {
+    pub name: String,
+    pub params: Vec<Param>,
+}
+
+impl Offer {
+    pub fn new(name: &str) -> Offer {
+        Offer {
+            name: name.to_string(),
+            params: vec![],
+        }
+    }
+
+    pub fn with(mut self, name: &str, value: Option<&str>) -> Offer {
+        self.params.push(Param {
+            name: name.to_string(),
+            value: value.map(|v| v.to_string()),
+        });
+        self
+    }
+
+    pub fn param(&self, name: &str) -> Option<&Param> {
+        self.params.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+}
+
+// Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits, x-foo; a="b"
+pub fn parse_extensions(value: &str) -> Vec<Offer> {
+    value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().filter(|n| !n.is_empty())?;
+            let params = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    let mut kv = p.splitn(2, '=');
+                    Param {
+                        name: kv.next().unwrap_or("").trim().to_string(),
+                        value: kv.next().map(|v| v.trim().trim_matches('"').to_string()),
+                    }
+                })
+                .collect();
+            Some(Offer {
+                name: name.to_string(),
+                params,
+            })
+        })
+        .collect()
+}
+
+pub fn format_extensions(offers: &[Offer]) -> String {
+    offers
+        .iter()
+        .map(|offer| {
+            let mut s = offer.name.clone();
+            for p in &offer.params {
+                s.push_str("; ");
+                s.push_str(&p.name);
+                if let Some(v) = &p.value {
+                    s.push('=');
+                    s.push_str(v);
+                }
+            }
+            s
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub trait Extension {
+    fn name(&self) -> &str;
+
+    // server side: accept an offer by returning the response parameters, None declines it
+    fn negotiate(&mut self, offer: &Offer) -> Option<Offer>;
+
+    // applied to a complete outgoing message frame
+    fn encode(&mut self, _frame: &mut Frame) -> Result<(), MatchError> {
+        Ok(())
+    }
+
+    // applied to a complete incoming message frame
+    fn decode(&mut self, _frame: &mut Frame) -> Result<(), MatchError> {
+        Ok(())
+    }
+}
+
+// registered extensions, of which the negotiated ones are applied to messages:
+// in negotiation order when encoding, in reverse order when decoding
+#[derive(Default)]
+pub struct Extensions {
+    registered: Vec<Box<dyn Extension>>,
+    active: Vec<usize>,
+}
+
+impl Extensions {
+    pub fn new() -> Extensions {
+        Extensions {
+            registered: vec![],
+            active: vec![],
+        }
+    }
+
+    pub fn with<E: Extension + 'static>(mut self, ext: E) -> Extensions {
+        self.registered.push(Box::new(ext));
+        self
+    }
+
+    pub fn is_active(&self, name: &str) -> bool {
+        self.active.iter().any(|idx| self.registered[*idx].name() == name)
+    }
+
+    // returns the Sec-WebSocket-Extensions response value, None when nothing was accepted
+    pub fn negotiate(&mut self, header: &str) -> Option<String> {
+        self.active.clear();
+        let mut accepted = vec![];
+        for offer in parse_extensions(header) {
+            let found = self.registered.iter().position(|ext| ext.name().eq_ignore_ascii_case(&offer.name));
+            let idx = match found {
+                Some(idx) if !self.active.contains(&idx) => idx,
+                _ => continue,
+            };
+            if let Some(response) = self.registered[idx].negotiate(&offer) {
+                self.active.push(idx);
+                accepted.push(response);
+            }
+        }
+        if accepted.is_empty() {
+            None
+        } else {
+            Some(format_extensions(&accepted))
+        }
+    }
+
+    pub fn encode(&mut self, frame: &mut Frame) -> Result<(), MatchError> {
+        for idx in self.active.iter() {
+            self.registered[*idx].encode(frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn decode(&mut self, frame: &mut Frame) -> Result<(), MatchError> {
+        for idx in self.active.iter().rev() {
+            self.registered[*idx].decode(frame)?;
+        }
+        Ok(())
+    }
+}
+
+// default limit on the size of an inflated incoming message
+pub const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+// permessage-deflate (RFC 7692) without context takeover in either direction,
+// outgoing messages are sent as stored (uncompressed) deflate blocks
+#[derive(Debug)]
+pub struct PerMessageDeflate {
+    max_message: usize,
+}
+
+impl PerMessageDeflate {
+    pub fn new() -> PerMessageDeflate {
+        PerMessageDeflate { max_message: MAX_MESSAGE }
+    }
+
+    pub fn with_max_message(mut self, max: usize) -> PerMessageDeflate {
+        self.max_message = max;
+        self
+    }
+}
+
+impl Default for PerMessageDeflate {
+    fn default() -> PerMessageDeflate {
+        PerMessageDeflate::new()
+    }
+}
+
+// RFC 7692, section 7.1.2: a value is 8 to 15, only client_max_window_bits may omit it
+fn window_bits(param: &Param) -> bool {
+    match &param.value {
+        Some(v) => matches!(v.parse::<u8>(), Ok(8..=15)),
+        None => param.name.eq_ignore_ascii_case("client_max_window_bits"),
+    }
+}
+
+impl Extension for PerMessageDeflate {
+    fn name(&self) -> &str {
+        "permessage-deflate"
+    }
+
+    fn negotiate(&mut self, offer: &Offer) -> Option<Offer> {
+        let known = ["server_no_context_takeover", "client_no_context_takeover",
+            "server_max_window_bits", "client_max_window_bits"];
+        if offer.params.iter().any(|p| !known.contains(&p.name.as_str())) {
+            return None;
+        }
+        let mut bits = offer.params.iter().filter(|p| p.name.ends_with("_max_window_bits"));
+        if bits.any(|p| !window_bits(p)) {
+            return None;
+        }
+        let mut names = offer.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        if names.len() != offer.params.len() {
+            return None;
+        }
+        let mut response = Offer::new("permessage-deflate")
+            .with("server_no_context_takeover", None)
+            .with("client_no_context_takeover", None);
+        // stored blocks need no window at all, so any requested limit can be echoed back
+        if let Some(bits) = offer.param("server_max_window_bits") {
+            response.params.push(bits.clone());
+        }
+        Some(response)
+    }
+
+    fn encode(&mut self, frame: &mut Frame) -> Result<(), MatchError> {
+        if frame.opcode >= 8 {
+            return Ok(());
+        }
+        // RSV1 marks the first frame of a compressed message only (RFC 7692, section 6.1)
+        if frame.opcode == 0 {
+            return Err(MatchError::unexpected(
+                0,
+                "continuation frame".to_string(),
+                "whole message to compress".to_string(),
+            ));
+        }
+        let mut body = deflate_stored(&frame.body);
+        body.truncate(body.len() - 4);
+        frame.len = body.len() as u32;
+        frame.body = body;
+        frame.rsv |= RSV1;
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: &mut Frame) -> Result<(), MatchError> {
+        if frame.rsv & RSV1 == 0 {
+            return Ok(());
+        }
+        let mut body = frame.body.clone();
+        body.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+        let body = inflate(&body, self.max_message)
+            .map_err(|e| MatchError::unexpected(0, e, "deflate stream".to_string()))?;
+        frame.len = body.len() as u32;
+        frame.body = body;
+        frame.rsv &= !RSV1;
+        Ok(())
+    }
+}
+
+// reassembles fragmented incoming messages and fragments outgoing ones, applying the
+// negotiated extensions to whole messages: of the frames of a message only the first one
+// carries the RSV bits
+pub struct MessageAssembler {
+    extensions: Extensions,
+    max_message: usize,
+    message: Option<Frame>,
+}
+
+impl MessageAssembler {
+    pub fn new(extensions: Extensions) -> MessageAssembler {
+        MessageAssembler {
+            extensions,
+            max_message: MAX_MESSAGE,
+            message: None,
+        }
+    }
+
+    // limit on the size of an incoming message before the extensions are applied
+    pub fn with_max_message(mut self, max: usize) -> MessageAssembler {
+        self.max_message = max;
+        self
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    // takes the next incoming frame: control frames are returned right away, as they may
+    // arrive between the frames of a message, and a data message once its last frame arrived
+    pub fn push(&mut self, mut frame: Frame) -> Result<Option<Frame>, MatchError> {
+        frame.unmask_in_place();
+        if frame.opcode >= 8 {
+            return Ok(Some(frame));
+        }
+        let mut message = match (self.message.take(), frame.opcode) {
+            (None, 0) => {
+                return Err(MatchError::unexpected(
+                    0,
+                    "continuation frame".to_string(),
+                    "first frame of a message".to_string(),
+                ))
+            }
+            (None, _) => frame,
+            (Some(_), 0) if frame.rsv != 0 => {
+                return Err(MatchError::unexpected(
+                    0,
+                    format!("RSV bits {:#05b}", frame.rsv),
+                    "continuation frame without RSV bits".to_string(),
+                ))
+            }
+            (Some(mut message), 0) => {
+                message.body.extend_from_slice(&frame.body);
+                message.fin = frame.fin;
+                message
+            }
+            (Some(_), code) => {
+                return Err(MatchError::unexpected(
+                    0,
+                    format!("opcode {}", code),
+                    "continuation frame".to_string(),
+                ))
+            }
+        };
+        if message.body.len() > self.max_message {
+            return Err(MatchError::over_capacity(0, self.max_message, message.body.len()));
+        }
+        if !message.fin {
+            self.message = Some(message);
+            return Ok(None);
+        }
+        message.len = message.body.len() as u32;
+        self.extensions.decode(&mut message)?;
+        Ok(Some(message))
+    }
+
+    // applies the extensions to an outgoing message and splits it into frames of at most
+    // `max_frame` payload bytes, continuation frames go without the RSV bits
+    pub fn fragment(&mut self, mut message: Frame, max_frame: usize) -> Result<Vec<Frame>, MatchError> {
+        assert!(max_frame > 0, "max_frame must not be zero");
+        self.extensions.encode(&mut message)?;
+        if message.opcode >= 8 || message.body.len() <= max_frame {
+            return Ok(vec![message]);
+        }
+        let last = (message.body.len() - 1) / max_frame;
+        let frames = message
+            .body
+            .chunks(max_frame)
+            .enumerate()
+            .map(|(idx, chunk)| Frame {
+                fin: idx == last && message.fin,
+                rsv: if idx == 0 { message.rsv } else { 0 },
+                opcode: if idx == 0 { message.opcode } else { 0 },
+                len: chunk.len() as u32,
+                mask: None,
+                body: chunk.to_vec(),
+            })
+            .collect();
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_offers() {
+        let offers = parse_extensions("permessage-deflate; client_max_window_bits, x-foo; a=\"b\" ;c, ");
+        assert_eq!(offers.len(), 2);
+        assert_eq!(offers[0], Offer::new("permessage-deflate").with("client_max_window_bits", None));
+        assert_eq!(offers[1], Offer::new("x-foo").with("a", Some("b")).with("c", None));
+        assert_eq!(
+            format_extensions(&offers),
+            "permessage-deflate; client_max_window_bits, x-foo; a=b; c"
+        );
+        assert!(offers[1].param("A").is_some());
+    }
+
+    #[test]
+    fn negotiate_deflate() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new());
+        assert_eq!(exts.negotiate("x-unknown"), None);
+        assert!(!exts.is_active("permessage-deflate"));
+
+        let header = "permessage-deflate; x-bogus, permessage-deflate; client_max_window_bits";
+        assert_eq!(
+            exts.negotiate(header),
+            Some("permessage-deflate; server_no_context_takeover; client_no_context_takeover".to_string())
+        );
+        assert!(exts.is_active("permessage-deflate"));
+
+        assert_eq!(
+            exts.negotiate("permessage-deflate; server_max_window_bits=10"),
+            Some("permessage-deflate; server_no_context_takeover; client_no_context_takeover; server_max_window_bits=10".to_string())
+        );
+    }
+
+    #[test]
+    fn negotiate_window_bits() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new());
+        for header in [
+            "permessage-deflate; server_max_window_bits=7",
+            "permessage-deflate; server_max_window_bits=16",
+            "permessage-deflate; server_max_window_bits",
+            "permessage-deflate; client_max_window_bits=x",
+            "permessage-deflate; client_max_window_bits=0",
+            "permessage-deflate; server_no_context_takeover; server_no_context_takeover",
+        ] {
+            assert_eq!(exts.negotiate(header), None, "{}", header);
+        }
+        assert!(exts.negotiate("permessage-deflate; server_max_window_bits=8; client_max_window_bits=15").is_some());
+    }
+
+    #[test]
+    fn deflate_frames() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new());
+        exts.negotiate("permessage-deflate");
+
+        // "Hello" from RFC 7692, section 7.2.3.1
+        let mut frame = Frame::text("");
+        frame.rsv = RSV1;
+        frame.body = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        exts.decode(&mut frame).unwrap();
+        assert_eq!(frame.body, b"Hello");
+        assert_eq!(frame.rsv, 0);
+
+        let mut frame = Frame::text("hello, world");
+        exts.encode(&mut frame).unwrap();
+        assert_eq!(frame.rsv, RSV1);
+        assert_ne!(frame.body, b"hello, world");
+        exts.decode(&mut frame).unwrap();
+        assert_eq!(frame.body, b"hello, world");
+        assert_eq!(frame.len, 12);
+    }
+
+    #[test]
+    fn continuation_frames_are_not_compressed() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new());
+        exts.negotiate("permessage-deflate");
+        let mut frame = Frame::text("tail");
+        frame.opcode = 0;
+        assert!(exts.encode(&mut frame).is_err());
+        assert_eq!(frame.rsv, 0);
+        assert_eq!(frame.body, b"tail");
+    }
+
+    fn deflate_assembler() -> MessageAssembler {
+        let mut assembler = MessageAssembler::new(Extensions::new().with(PerMessageDeflate::new()));
+        assembler.extensions_mut().negotiate("permessage-deflate");
+        assembler
+    }
+
+    #[test]
+    fn fragmented_messages() {
+        let mut sender = deflate_assembler();
+        let frames = sender.fragment(Frame::text("hello, world"), 5).unwrap();
+        assert!(frames.len() > 1);
+        assert_eq!((frames[0].opcode, frames[0].rsv, frames[0].fin), (1, RSV1, false));
+        for frame in &frames[1..] {
+            assert_eq!((frame.opcode, frame.rsv), (0, 0));
+            assert!(frame.len <= 5);
+        }
+        assert!(frames.last().unwrap().fin);
+
+        let mut receiver = deflate_assembler();
+        let mut messages = vec![];
+        for (idx, frame) in frames.into_iter().enumerate() {
+            if idx == 1 {
+                // control frames may come between the frames of a message
+                let mut ping = Frame::text("");
+                ping.opcode = 9;
+                assert_eq!(receiver.push(ping).unwrap().unwrap().opcode, 9);
+            }
+            messages.extend(receiver.push(frame).unwrap());
+        }
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].opcode, 1);
+        assert_eq!(messages[0].rsv, 0);
+        assert_eq!(messages[0].body, b"hello, world");
+
+        let frames = sender.fragment(Frame::text("hi"), 125).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].rsv, frames[0].fin), (RSV1, true));
+    }
+
+    #[test]
+    fn misordered_frames() {
+        let mut assembler = MessageAssembler::new(Extensions::new());
+        let mut tail = Frame::text("b");
+        tail.opcode = 0;
+        assert!(assembler.push(tail).is_err());
+
+        let mut head = Frame::text("a");
+        head.fin = false;
+        assert!(assembler.push(head).unwrap().is_none());
+        assert!(assembler.push(Frame::text("new")).is_err());
+
+        let mut head = Frame::text("a");
+        head.fin = false;
+        assembler.push(head).unwrap();
+        let mut tail = Frame::text("b");
+        tail.opcode = 0;
+        tail.rsv = RSV1;
+        assert!(assembler.push(tail).is_err());
+
+        let mut assembler = MessageAssembler::new(Extensions::new()).with_max_message(3);
+        let mut head = Frame::text("ab");
+        head.fin = false;
+        assembler.push(head).unwrap();
+        let mut tail = Frame::text("cd");
+        tail.opcode = 0;
+        assert!(assembler.push(tail).is_err());
+    }
+
+    #[test]
+    fn inactive_extensions_are_skipped() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new());
+        let mut frame = Frame::text("plain");
+        exts.encode(&mut frame).unwrap();
+        assert_eq!(frame.body, b"plain");
+        assert_eq!(frame.rsv, 0);
+    }
+
+    #[test]
+    fn inflated_size_limit() {
+        let mut exts = Extensions::new().with(PerMessageDeflate::new().with_max_message(4));
+        exts.negotiate("permessage-deflate");
+        let mut frame = Frame::text("");
+        frame.rsv = RSV1;
+        frame.body = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        assert!(exts.decode(&mut frame).is_err());
+        assert_eq!(frame.rsv, RSV1);
+    }
+}
@@ -0,0 +1,138 @@
+// Byte-level conformance vectors for servers built on this crate, grouped by the outcome
+// a parser is expected to produce. Each group is a `&'static [&'static [u8]]`.
+
+// well-formed requests that parse completely
+pub const VALID_REQUESTS: &[&[u8]] = &[
+    b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    b"GET /docs/index.html?q=1 HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\n\r\n",
+    b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello",
+    b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n",
+    b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+    b"GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    b"GET / HTTP/1.0\r\n\r\n",
+];
+
+// malformed request lines and header sections
+pub const INVALID_REQUESTS: &[&[u8]] = &[
+    b"GET /x\r\n\r\n",
+    b"GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+    b"GET / HTTP/1.1\nHost: a\n\n",
+    b"\r\n\r\n",
+];
+
+// requests a proxy and a backend may frame differently (request smuggling): both
+// Content-Length and Transfer-Encoding, conflicting lengths, chunked not being last
+pub const SMUGGLING_REQUESTS: &[&[u8]] = &[
+    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nContent-Length: 6\r\n\r\nabcdef",
+    b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked, identity\r\n\r\n0\r\n\r\n",
+    b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\ntransfer-encoding: chunked\r\nContent-Length: 3\r\n\r\nabc",
+];
+
+// valid requests cut short, more input would complete them
+pub const TRUNCATED_REQUESTS: &[&[u8]] = &[
+    b"GET / HT",
+    b"GET / HTTP/1.1\r\nHost: a\r\n",
+    b"GET / HTTP/1.1\r\nHost: a",
+    b"GET / HTTP/1.1\r\nHost: a\r\n\r",
+    b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n01234",
+];
+
+// examples from RFC 6455, section 5.7, each a single complete frame
+pub const WS_RFC_FRAMES: &[&[u8]] = &[
+    // unmasked text "Hello"
+    &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f],
+    // masked text "Hello"
+    &[0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58],
+    // fragmented unmasked text "Hel" + "lo"
+    &[0x01, 0x03, 0x48, 0x65, 0x6c],
+    &[0x80, 0x02, 0x6c, 0x6f],
+    // unmasked ping and masked pong, both "Hello"
+    &[0x89, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f],
+    &[0x8a, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58],
+];
+
+// frames that decode but break the protocol rules (see `ws::validate_frame`)
+pub const WS_INVALID_FRAMES: &[&[u8]] = &[
+    // fragmented ping
+    &[0x09, 0x00],
+    // reserved opcodes
+    &[0x83, 0x00],
+    &[0x8b, 0x00],
+];
+
+// frames cut short
+pub const WS_TRUNCATED_FRAMES: &[&[u8]] = &[
+    &[0x81],
+    &[0x81, 0x05, 0x48, 0x65],
+    &[0x81, 0x85, 0x37, 0xfa],
+    &[0x82, 0x7e, 0x01],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{try_parse_http_request, Request};
+    use crate::stream::ByteStream;
+    use crate::ws::{parse_frame, validate_frame};
+
+    fn parse(bytes: &[u8], partial: bool) -> Result<Request, crate::parser::MatchError> {
+        let mut bs = ByteStream::wrap(bytes.to_vec());
+        bs.set_partial(partial);
+        let req = try_parse_http_request(&mut bs)?;
+        assert_eq!(bs.remaining(), 0, "{}", String::from_utf8_lossy(bytes));
+        Ok(req)
+    }
+
+    #[test]
+    fn requests() {
+        for bytes in VALID_REQUESTS {
+            assert!(parse(bytes, false).is_ok(), "{}", String::from_utf8_lossy(bytes));
+        }
+        for bytes in INVALID_REQUESTS {
+            let err = parse(bytes, false).unwrap_err();
+            assert!(!err.is_incomplete(), "{}", String::from_utf8_lossy(bytes));
+        }
+        for bytes in TRUNCATED_REQUESTS {
+            // only a partial stream can tell running out of input from a missing delimiter
+            let err = parse(bytes, true).unwrap_err();
+            assert!(err.is_incomplete(), "{}: {}", String::from_utf8_lossy(bytes), err);
+        }
+    }
+
+    #[test]
+    fn smuggling_requests_are_ambiguous() {
+        for bytes in SMUGGLING_REQUESTS {
+            let mut bs = ByteStream::wrap(bytes.to_vec());
+            let req = try_parse_http_request(&mut bs).unwrap();
+            let lengths = req.headers.iter().filter(|h| h.name.eq_ignore_ascii_case("Content-Length")).count();
+            let chunked_last = match req.transfer_encoding() {
+                None => false,
+                Some(Ok(te)) => te.codings.last() == Some(&crate::http::Coding::Chunked),
+                Some(Err(_)) => true,
+            };
+            assert!(lengths > 1 || (lengths == 1 && req.transfer_encoding().is_some()) || chunked_last,
+                "{}", String::from_utf8_lossy(bytes));
+        }
+    }
+
+    #[test]
+    fn frames() {
+        for bytes in WS_RFC_FRAMES {
+            let mut bs = ByteStream::wrap(bytes.to_vec());
+            let mut frame = parse_frame(&mut bs).unwrap();
+            assert_eq!(bs.remaining(), 0);
+            assert!(validate_frame(&frame, 0).is_ok());
+            frame.unmask_in_place();
+            assert!(b"Hello".starts_with(&frame.body) || frame.body == b"lo");
+        }
+        for bytes in WS_INVALID_FRAMES {
+            let frame = parse_frame(&mut ByteStream::wrap(bytes.to_vec())).unwrap();
+            assert!(validate_frame(&frame, 0).is_err());
+        }
+        for bytes in WS_TRUNCATED_FRAMES {
+            assert!(parse_frame(&mut ByteStream::wrap(bytes.to_vec())).is_none());
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Total message size from a buffered prefix, for pre-allocating and sizing reads.
+// Each matcher reads just enough of the message start to compute its full length
+// (including the part it read) and fails when that is not determinable yet.
+use crate::ascii::eq_ignore_case;
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{before_seq, get_u16, get_u64, get_u8};
+use crate::stream::ByteStream;
+use std::convert::TryFrom;
+
+// runs the matcher without consuming anything, None when the length is not known (yet)
+pub fn message_length_hint<M: Matcher<usize>>(bs: &mut ByteStream, m: M) -> Option<usize> {
+    let mark = bs.mark();
+    let result = m.do_match(bs).ok();
+    bs.reset(mark);
+    result
+}
+
+// HTTP/1.x request or response: header section plus Content-Length (0 when absent),
+// unknown for chunked messages
+pub fn http_length() -> impl Matcher<usize> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        let head = before_seq(b"\r\n\r\n").do_match(bs)?;
+        let head = String::from_utf8_lossy(&head);
+        let mut length = 0;
+        for line in head.split("\r\n").skip(1) {
+            let mut kv = line.splitn(2, ':');
+            let (name, value) = match (kv.next(), kv.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim()),
+                _ => continue,
+            };
+            if eq_ignore_case(name, "Transfer-Encoding") {
+                return Err(MatchError::custom(pos, "length of a chunked message".to_string()));
+            }
+            if eq_ignore_case(name, "Content-Length") {
+                length = value.parse::<usize>()
+                    .map_err(|e| MatchError::custom(pos, format!("Content-Length: {}", e)))?;
+            }
+        }
+        (head.len() + 4).checked_add(length).ok_or_else(|| overflow(pos))
+    }
+}
+
+// WebSocket frame: header (with extended length and masking key) plus payload
+pub fn ws_length() -> impl Matcher<usize> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        get_u8().do_match(bs)?;
+        let b = get_u8().do_match(bs)?;
+        let mask = if b & 0x80 > 0 { 4 } else { 0 };
+        let (header, len) = match b & 0x7F {
+            126 => (4, get_u16().do_match(bs)? as usize),
+            127 => (10, usize::try_from(get_u64().do_match(bs)?).map_err(|_| overflow(pos))?),
+            len => (2, len as usize),
+        };
+        len.checked_add(header + mask).ok_or_else(|| overflow(pos))
+    }
+}
+
+// a length from the input that no buffer could hold
+fn overflow(pos: usize) -> MatchError {
+    MatchError::custom(pos, "message length overflows usize".to_string())
+}
+
+// MQTT control packet: fixed header byte, remaining length varint (1 to 4 bytes), rest
+pub fn mqtt_length() -> impl Matcher<usize> {
+    move |bs: &mut ByteStream| {
+        get_u8().do_match(bs)?;
+        let mut remaining = 0usize;
+        for i in 0..4 {
+            let b = get_u8().do_match(bs)?;
+            remaining |= ((b & 0x7F) as usize) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok(1 + (i + 1) + remaining);
+            }
+        }
+        Err(MatchError::unexpected(
+            bs.pos() - 1,
+            "fifth length byte".to_string(),
+            "remaining length of at most 4 bytes".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn http_hint() {
+        let mut bs = ByteStream::wrap(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\nab".to_vec());
+        assert_eq!(message_length_hint(&mut bs, http_length()), Some(43));
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(b"HTTP/1.1 204 No Content\r\n\r\n".to_vec());
+        assert_eq!(message_length_hint(&mut bs, http_length()), Some(27));
+
+        let mut bs = ByteStream::wrap(b"GET / HTTP/1.1\r\nHost: a\r\n".to_vec());
+        assert_eq!(message_length_hint(&mut bs, http_length()), None);
+        let mut bs = ByteStream::wrap(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec());
+        assert_eq!(message_length_hint(&mut bs, http_length()), None);
+
+        let mut bs = ByteStream::wrap(b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\n".to_vec());
+        assert!(bs.apply(http_length()).unwrap_err().to_string().contains("overflows"));
+    }
+
+    #[test]
+    fn ws_hint() {
+        let mut bs = ByteStream::wrap(vec![129, 134, 87, 35]);
+        assert_eq!(message_length_hint(&mut bs, ws_length()), Some(12));
+        let mut bs = ByteStream::wrap(vec![130, 126, 1, 0]);
+        assert_eq!(message_length_hint(&mut bs, ws_length()), Some(260));
+        let mut bs = ByteStream::wrap(vec![130, 255, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(message_length_hint(&mut bs, ws_length()), None);
+        let mut bs = ByteStream::wrap(vec![130, 255, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(message_length_hint(&mut bs, ws_length()), Some(14 + 65536));
+        let mut bs = ByteStream::wrap(vec![0x82, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(bs.apply(ws_length()).unwrap_err().to_string().contains("overflows"));
+    }
+
+    #[test]
+    fn mqtt_hint() {
+        let mut bs = ByteStream::wrap(vec![0x30, 0x0a]);
+        assert_eq!(message_length_hint(&mut bs, mqtt_length()), Some(12));
+        // 321 = 0xC1 0x02 (spec example)
+        let mut bs = ByteStream::wrap(vec![0x30, 0xc1, 0x02, 0x00]);
+        assert_eq!(message_length_hint(&mut bs, mqtt_length()), Some(324));
+        let mut bs = ByteStream::wrap(vec![0x30, 0xff]);
+        assert_eq!(message_length_hint(&mut bs, mqtt_length()), None);
+        let mut bs = ByteStream::wrap(vec![0x30, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        assert_eq!(message_length_hint(&mut bs, mqtt_length()), None);
+    }
+}
@@ -1,7 +1,20 @@
-use crate::parser::{before, bytes, exact, repeat, single, Applicator, Matcher, unit, ParserExt};
+use crate::ascii::{canonical_header_name, eq_ignore_case, to_lower};
+use crate::parser::{repeat, Applicator, Matcher, MatchError, unit, ParserExt};
 use crate::stream::ByteStream;
+use crate::textmsg::{body, coded, coded_start_line, located_headers, start_line, unlocated, Dialect, StartLine};
 use std::ops::Add;
 
+// stable error codes, see `MatchError::code`
+pub const HTTP_BAD_REQUEST_LINE: &str = "HTTP_BAD_REQUEST_LINE";
+pub const HTTP_BAD_HEADER_NAME: &str = "HTTP_BAD_HEADER_NAME";
+pub const HTTP_BAD_TRANSFER_ENCODING: &str = "HTTP_BAD_TRANSFER_ENCODING";
+
+pub const HTTP: Dialect = Dialect {
+    name: "HTTP",
+    version: "HTTP/",
+    compact: &[],
+};
+
 pub fn as_string(bytes: Vec<u8>) -> String {
     // Consider changing to: std::str::from_utf8(&[u8]) -> Result<&str>
     // Note: from_utf8 can fail for invalid UTF-8 codes
@@ -15,28 +28,13 @@ pub struct Header {
     pub value: String,
 }
 
-fn header_parser() -> impl Matcher<Header> {
-    unit(|| vec![])
-        .then(before(':'))
-        .map(|(mut vec, val)| {
-            vec.push(as_string(val));
-            vec
-        })
-        .then(single(':'))
-        .map(|(vec, _)| vec)
-        .then(single(' '))
-        .map(|(vec, _)| vec)
-        .then(before('\r'))
-        .map(|(mut vec, val)| {
-            vec.push(as_string(val));
-            vec
-        })
-        .then(exact(&[b'\r', b'\n']))
-        .map(|(vec, _)| vec)
-        .map(|vec| Header {
-            name: vec[0].to_owned(),
-            value: vec[1].to_owned(),
-        })
+impl Header {
+    pub fn canonical(self) -> Header {
+        Header {
+            name: canonical_header_name(&self.name),
+            value: self.value,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -48,7 +46,204 @@ pub struct Request {
     pub content: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| eq_ignore_case(&h.name, name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.header("Host").filter(|host| !host.is_empty())
+    }
+
+    pub fn transfer_encoding(&self) -> Option<Result<TransferEncoding, MatchError>> {
+        TransferEncoding::from_headers(&self.headers)
+    }
+
+    // exact number of bytes `encode_into` appends
+    pub fn encoded_len(&self) -> usize {
+        let line = self.method.len() + 1 + self.path.len() + 1 + self.protocol.len() + 2;
+        let headers = self.headers.iter().map(|h| h.name.len() + 2 + h.value.len() + 2).sum::<usize>();
+        line + headers + 2 + self.content.len()
+    }
+
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(format!("{} {} {}\r\n", self.method, self.path, self.protocol).as_bytes());
+        for h in &self.headers {
+            out.extend_from_slice(format!("{}: {}\r\n", h.name, h.value).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out.extend_from_slice(&self.content);
+    }
+
+    // `OPTIONS *`: about the server as a whole rather than any resource
+    pub fn is_asterisk_form(&self) -> bool {
+        self.method == "OPTIONS" && self.path == "*"
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+}
+
+impl Method {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+        }
+    }
+
+    // method names are case-sensitive
+    pub fn parse(name: &str) -> Option<Method> {
+        [
+            Method::Get, Method::Head, Method::Post, Method::Put, Method::Delete,
+            Method::Connect, Method::Options, Method::Trace, Method::Patch,
+        ]
+        .iter()
+        .copied()
+        .find(|m| m.as_str() == name)
+    }
+}
+
+// the methods in the given order, without duplicates
+pub fn allow_header(methods: &[Method]) -> Header {
+    let mut names: Vec<&str> = vec![];
+    for m in methods {
+        if !names.contains(&m.as_str()) {
+            names.push(m.as_str());
+        }
+    }
+    Header {
+        name: "Allow".to_string(),
+        value: names.join(", "),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Coding {
+    Chunked,
+    Compress,
+    Deflate,
+    Gzip,
+    Identity,
+    Other(String),
+}
+
+impl Coding {
+    fn from_name(name: &str) -> Coding {
+        match to_lower(name).as_str() {
+            "chunked" => Coding::Chunked,
+            "compress" | "x-compress" => Coding::Compress,
+            "deflate" => Coding::Deflate,
+            "gzip" | "x-gzip" => Coding::Gzip,
+            "identity" => Coding::Identity,
+            _ => Coding::Other(name.to_string()),
+        }
+    }
+}
+
+// codings in the order they were applied by the sender, so decoding goes in reverse
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TransferEncoding {
+    pub codings: Vec<Coding>,
+}
+
+impl TransferEncoding {
+    // chunked may appear only once and must be the final coding
+    pub fn parse(value: &str) -> Result<TransferEncoding, MatchError> {
+        let mut codings = vec![];
+        let mut offset = 0;
+        for item in value.split(',') {
+            let name = item.split(';').next().unwrap_or("").trim();
+            if name.is_empty() {
+                return Err(MatchError::unexpected(offset, "empty coding".to_string(), "transfer coding".to_string())
+                    .with_code(HTTP_BAD_TRANSFER_ENCODING));
+            }
+            if codings.last() == Some(&Coding::Chunked) {
+                return Err(MatchError::unexpected(
+                    offset,
+                    format!("coding '{}'", name),
+                    "chunked as the final coding".to_string(),
+                ).with_code(HTTP_BAD_TRANSFER_ENCODING));
+            }
+            codings.push(Coding::from_name(name));
+            offset += item.len() + 1;
+        }
+        Ok(TransferEncoding { codings })
+    }
+
+    // multiple Transfer-Encoding headers form a single list
+    pub fn from_headers(headers: &[Header]) -> Option<Result<TransferEncoding, MatchError>> {
+        let values = headers.iter()
+            .filter(|h| eq_ignore_case(&h.name, "Transfer-Encoding"))
+            .map(|h| h.value.as_str())
+            .collect::<Vec<&str>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(TransferEncoding::parse(&values.join(", ")))
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        self.codings.last() == Some(&Coding::Chunked)
+    }
+
+    // codings applied on top of chunked framing, outermost last
+    pub fn content_codings(&self) -> &[Coding] {
+        if self.is_chunked() {
+            &self.codings[..self.codings.len() - 1]
+        } else {
+            &self.codings
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // reject HTTP/1.1 requests without a non-empty Host header
+    pub require_host: bool,
+    // local daemons over unix sockets (Docker-style APIs) get an empty or missing Host,
+    // which is accepted as long as the target is an absolute path
+    pub unix_socket: bool,
+}
+
+impl Config {
+    pub fn unix_socket() -> Config {
+        Config {
+            require_host: true,
+            unix_socket: true,
+        }
+    }
+
+    fn accepts(&self, req: &Request) -> bool {
+        if !self.require_host || req.protocol != "HTTP/1.1" || req.host().is_some() {
+            return true;
+        }
+        self.unix_socket && req.path.starts_with('/')
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Response {
     pub protocol: String,
     pub code: u16,
@@ -57,69 +252,276 @@ pub struct Response {
     pub content: Vec<u8>,
 }
 
-impl Into<String> for Response {
-    fn into(self) -> String {
-        let headers = self
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| eq_ignore_case(&h.name, name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn transfer_encoding(&self) -> Option<Result<TransferEncoding, MatchError>> {
+        TransferEncoding::from_headers(&self.headers)
+    }
+
+    // 1xx responses other than 101 Switching Protocols precede the final response
+    pub fn is_interim(&self) -> bool {
+        self.code >= 100 && self.code < 200 && self.code != 101
+    }
+
+    // responses to HEAD, 1xx, 204 and 304 never carry a body, whatever Content-Length says
+    pub fn has_body(&self, method: &str) -> bool {
+        method != "HEAD"
+            && !self.is_tunnel(method)
+            && !(100..200).contains(&self.code)
+            && self.code != 204
+            && self.code != 304
+    }
+
+    // after a 2xx response to CONNECT the connection carries opaque tunneled bytes
+    pub fn is_tunnel(&self, method: &str) -> bool {
+        method == "CONNECT" && (200..300).contains(&self.code)
+    }
+
+    // 405 with the Allow header listing what the resource does support
+    pub fn method_not_allowed(methods: &[Method]) -> Response {
+        Response {
+            protocol: "HTTP/1.1".to_string(),
+            code: 405,
+            message: "Method Not Allowed".to_string(),
+            headers: vec![
+                allow_header(methods),
+                Header {
+                    name: "Content-Length".to_string(),
+                    value: "0".to_string(),
+                },
+            ],
+            content: vec![],
+        }
+    }
+
+    // exact length of `String::from(response)`, content bytes above 0x7F take two bytes there
+    pub fn encoded_len(&self) -> usize {
+        let line = format!("{} {} {}\r\n", self.protocol, self.code, self.message).len();
+        let headers = self.headers.iter().map(|h| h.name.len() + 2 + h.value.len()).sum::<usize>()
+            + 2 * self.headers.len().saturating_sub(1);
+        let content = self.content.iter().map(|b| (*b as char).len_utf8()).sum::<usize>();
+        line + headers + 4 + content
+    }
+
+    // serialize with canonical header names, regardless of how they were received
+    pub fn canonical(self) -> Response {
+        Response {
+            headers: self.headers.into_iter().map(Header::canonical).collect(),
+            ..self
+        }
+    }
+}
+
+impl From<Response> for String {
+    fn from(res: Response) -> String {
+        let headers = res
             .headers
             .into_iter()
             .map(|h| format!("{}: {}", h.name, h.value))
             .collect::<Vec<String>>()
             .join("\r\n");
-        let content = as_string(self.content);
-        format!("{} {} {}\r\n", self.protocol, self.code, self.message)
+        let content = as_string(res.content);
+        format!("{} {} {}\r\n", res.protocol, res.code, res.message)
             .add(&headers)
             .add("\r\n\r\n")
             .add(&content)
     }
 }
 
-fn request_parser() -> impl Matcher<Request> {
-    unit(|| Request::default())
-        .then(before(' '))
-        .save(|req, bytes| req.method = as_string(bytes))
-        .then(single(' '))
-        .skip()
-        .then(before(' '))
-        .save(|req, bytes| req.path = as_string(bytes))
-        .then(single(' '))
-        .skip()
-        .then(before('\r'))
-        .save(|req, bytes| req.protocol = as_string(bytes))
-        .then(exact(&[b'\r', b'\n']))
-        .skip()
-        .then(repeat(header_parser()))
-        .save(|req, vec| req.headers = vec)
-        .then(exact(&[b'\r', b'\n']))
-        .skip()
-        .then_with(|req| {
-            let n: usize = get_content_length(req).unwrap_or(0);
-            bytes(n)
+fn request_line() -> impl Matcher<Request> {
+    coded_start_line(HTTP, HTTP_BAD_REQUEST_LINE)
+        .try_map(|start| match start {
+            // the asterisk-form target is only defined for OPTIONS
+            StartLine::Request { method, target, .. } if target == "*" && method != "OPTIONS" => {
+                Err(format!("asterisk-form target for {}", method))
+            }
+            StartLine::Request { method, target, protocol } => Ok(Request {
+                method,
+                path: target,
+                protocol,
+                ..Request::default()
+            }),
+            StartLine::Status { .. } => Err("status line".to_string()),
         })
-        .save(|req, content| req.content = content)
+        .context("request line")
+        .map_err(coded(HTTP_BAD_REQUEST_LINE))
 }
 
-fn get_header_value(req: &Request, name: String) -> Option<String> {
-    req.headers
-        .iter()
-        .find(|h| h.name == name)
-        .map(|h| h.value.clone())
+// the headers keep their offsets for `body`
+fn request_head() -> impl Matcher<(Request, Vec<(usize, Header)>)> {
+    request_line().then(located_headers().map_err(coded(HTTP_BAD_HEADER_NAME)))
+}
+
+fn request_head_parser() -> impl Matcher<Request> {
+    request_head().map(|(mut req, headers)| {
+        req.headers = unlocated(headers);
+        req
+    })
+}
+
+fn request_parser() -> impl Matcher<Request> {
+    |bs: &mut ByteStream| {
+        let (mut req, headers) = request_head().do_match(bs)?;
+        req.content = body(bs, &headers)?;
+        req.headers = unlocated(headers);
+        Ok(req)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyState {
+    // the body (possibly empty) was read into the request content
+    Complete,
+    // Content-Length is larger than the buffered bytes
+    Incomplete { length: usize, available: usize },
+    // Content-Length is over the limit, the body was not read
+    TooLarge { length: usize, limit: usize },
+    InvalidLength(String),
 }
 
-fn get_content_length(req: &Request) -> Option<usize> {
-    get_header_value(req, "Content-Length".to_string())
-        .map(|len| len.parse::<usize>().unwrap_or(0))
+// result of a request parse that got past the header section, whatever happened to the body
+#[derive(Debug)]
+pub struct HeadersParsed {
+    pub request: Request,
+    pub body_state: BodyState,
+}
+
+// fails only when the request line or headers are malformed; when the body can not be read
+// the stream is left right after the headers, so a server can still answer e.g. 413 or 400
+pub fn parse_http_request_headers(stream: &mut ByteStream, max_body: usize) -> Result<HeadersParsed, MatchError> {
+    let mark = stream.mark();
+    let mut request = match stream.apply(request_head_parser()) {
+        Ok(req) => req,
+        Err(e) => {
+            stream.reset(mark);
+            return Err(e);
+        }
+    };
+    let length = match request.header("Content-Length") {
+        None => 0,
+        Some(value) => match value.trim().parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                let body_state = BodyState::InvalidLength(value.to_string());
+                return Ok(HeadersParsed { request, body_state });
+            }
+        },
+    };
+    let body_state = if length > max_body {
+        BodyState::TooLarge { length, limit: max_body }
+    } else if length > stream.remaining() {
+        BodyState::Incomplete { length, available: stream.remaining() }
+    } else {
+        request.content = stream.get(length).unwrap_or_default();
+        BodyState::Complete
+    };
+    Ok(HeadersParsed { request, body_state })
+}
+
+fn status_line() -> impl Matcher<Response> {
+    start_line(HTTP).try_map(|start| match start {
+        StartLine::Status { protocol, code, reason } => Ok(Response {
+            protocol,
+            code,
+            message: reason,
+            ..Response::default()
+        }),
+        StartLine::Request { .. } => Err("request line"),
+    })
+}
+
+fn response_parser(method: &str) -> impl Matcher<Response> {
+    let method = method.to_string();
+    move |bs: &mut ByteStream| {
+        let mut res = status_line().do_match(bs)?;
+        let headers = located_headers().do_match(bs)?;
+        if res.has_body(&method) {
+            res.content = body(bs, &headers)?;
+        }
+        res.headers = unlocated(headers);
+        Ok(res)
+    }
+}
+
+fn interim_parser() -> impl Matcher<Response> {
+    response_parser("").verify(Response::is_interim)
+}
+
+pub fn parse_http_response(stream: &mut ByteStream) -> Option<Response> {
+    parse_http_response_for("", stream)
+}
+
+// the request method decides whether the response can have a body at all
+pub fn parse_http_response_for(method: &str, stream: &mut ByteStream) -> Option<Response> {
+    let mark = stream.mark();
+    match stream.apply(response_parser(method)) {
+        Ok(res) => Some(res),
+        Err(_) => {
+            stream.reset(mark);
+            None
+        }
+    }
+}
+
+// on an established tunnel all bytes buffered after the response are handed over (and consumed),
+// otherwise the tunnel part is None and the stream is left positioned right after the response
+pub fn parse_connect_response(stream: &mut ByteStream) -> Option<(Response, Option<Vec<u8>>)> {
+    let res = parse_http_response_for("CONNECT", stream)?;
+    if res.is_tunnel("CONNECT") {
+        let rest = stream.len() - stream.pos();
+        let tunnel = stream.get(rest).unwrap_or_default();
+        Some((res, Some(tunnel)))
+    } else {
+        Some((res, None))
+    }
+}
+
+// consumes interim responses (100 Continue, 103 Early Hints) preceding the final one
+pub fn parse_http_response_with_interim(stream: &mut ByteStream) -> Option<(Vec<Response>, Response)> {
+    let mark = stream.mark();
+    let m = unit(Vec::new)
+        .then(repeat(interim_parser()))
+        .save(|acc, interim| *acc = interim)
+        .then(response_parser(""));
+    match stream.apply(m) {
+        Ok(pair) => Some(pair),
+        Err(_) => {
+            stream.reset(mark);
+            None
+        }
+    }
 }
 
 pub fn parse_http_request(stream: &mut ByteStream) -> Option<Request> {
-    stream
-        .apply(request_parser())
-        .map(|r| Some(r))
-        .unwrap_or_else(|_| None)
+    try_parse_http_request(stream).ok()
+}
+
+// same as `parse_http_request`, but keeps the error (with context labels) on failure
+pub fn try_parse_http_request(stream: &mut ByteStream) -> Result<Request, MatchError> {
+    stream.apply(request_parser())
+}
+
+pub fn parse_http_request_with(stream: &mut ByteStream, config: &Config) -> Option<Request> {
+    let mark = stream.mark();
+    match parse_http_request(stream) {
+        Some(req) if config.accepts(&req) => Some(req),
+        _ => {
+            stream.reset(mark);
+            None
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::MatchErrorKind;
 
     #[test]
     fn curl_request() {
@@ -191,6 +593,175 @@ mod tests {
         assert_eq!(req.headers[4].value, "13");
     }
 
+    #[test]
+    fn unix_socket_request() {
+        let text = "GET /v1.41/containers/json HTTP/1.1\r\nHost:\r\nUser-Agent: docker\r\n\r\n";
+
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_http_request_with(&mut bs, &Config::unix_socket()).unwrap();
+        assert_eq!(req.path, "/v1.41/containers/json");
+        assert_eq!(req.header("host"), Some(""));
+        assert_eq!(req.host(), None);
+        assert_eq!(req.header("User-Agent"), Some("docker"));
+
+        let strict = Config {
+            require_host: true,
+            unix_socket: false,
+        };
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_request_with(&mut bs, &strict).is_none());
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_request_with(&mut bs, &Config::default()).is_some());
+    }
+
+    #[test]
+    fn unix_socket_requires_absolute_path() {
+        let text = "OPTIONS * HTTP/1.1\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_request_with(&mut bs, &Config::unix_socket()).is_none());
+    }
+
+    #[test]
+    fn asterisk_form() {
+        let text = "OPTIONS * HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_http_request(&mut bs).unwrap();
+        assert!(req.is_asterisk_form());
+
+        let text = "GET * HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let err = try_parse_http_request(&mut bs).unwrap_err();
+        assert!(err.to_string().contains("request line"));
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        assert_eq!(Method::parse("PATCH"), Some(Method::Patch));
+        assert_eq!(Method::parse("get"), None);
+
+        let res = Response::method_not_allowed(&[Method::Get, Method::Head, Method::Get, Method::Options]);
+        assert_eq!(
+            String::from(res),
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD, OPTIONS\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn transfer_encoding_chain() {
+        let te = TransferEncoding::parse("gzip, chunked").unwrap();
+        assert_eq!(te.codings, vec![Coding::Gzip, Coding::Chunked]);
+        assert!(te.is_chunked());
+        assert_eq!(te.content_codings(), &[Coding::Gzip]);
+
+        let te = TransferEncoding::parse("Deflate;q=1,x-custom").unwrap();
+        assert_eq!(te.codings, vec![Coding::Deflate, Coding::Other("x-custom".to_string())]);
+        assert!(!te.is_chunked());
+
+        let err = TransferEncoding::parse("chunked, gzip").unwrap_err();
+        assert_eq!(err.offset(), 8);
+        assert!(TransferEncoding::parse("gzip,,chunked").is_err());
+        assert!(TransferEncoding::parse("chunked, chunked").is_err());
+    }
+
+    #[test]
+    fn transfer_encoding_headers() {
+        let text = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\ntransfer-encoding: chunked\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_http_request(&mut bs).unwrap();
+        let te = req.transfer_encoding().unwrap().unwrap();
+        assert_eq!(te.codings, vec![Coding::Gzip, Coding::Chunked]);
+
+        let req = Request::default();
+        assert!(req.transfer_encoding().is_none());
+    }
+
+    #[test]
+    fn http_response_parse() {
+        let text = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nServer: test\r\n\r\nhello";
+        let mut bs: ByteStream = text.to_string().into();
+        let res = parse_http_response(&mut bs).unwrap();
+
+        assert_eq!(res.protocol, "HTTP/1.1");
+        assert_eq!(res.code, 200);
+        assert_eq!(res.message, "OK");
+        assert_eq!(res.header("server"), Some("test"));
+        assert_eq!(res.content, b"hello");
+    }
+
+    #[test]
+    fn http_response_interim() {
+        let text = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\nHTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\nno";
+        let mut bs: ByteStream = text.to_string().into();
+        let (interim, res) = parse_http_response_with_interim(&mut bs).unwrap();
+
+        assert_eq!(interim.len(), 2);
+        assert_eq!(interim[0].code, 100);
+        assert_eq!(interim[1].code, 103);
+        assert_eq!(interim[1].header("Link"), Some("</style.css>; rel=preload"));
+        assert_eq!(res.code, 404);
+        assert_eq!(res.content, b"no");
+        assert_eq!(bs.pos(), text.len());
+
+        let mut bs: ByteStream = text.to_string().into();
+        assert_eq!(parse_http_response(&mut bs).unwrap().code, 100);
+    }
+
+    #[test]
+    fn http_response_bad_status() {
+        let text = "HTTP/1.1 2x0 OK\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_response(&mut bs).is_none());
+    }
+
+    #[test]
+    fn http_response_without_body() {
+        let text = "HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_response(&mut bs).is_none());
+        let res = parse_http_response_for("HEAD", &mut bs).unwrap();
+        assert_eq!(res.header("Content-Length"), Some("1024"));
+        assert!(res.content.is_empty());
+        assert_eq!(bs.pos(), text.len());
+
+        for code in &["204 No Content", "304 Not Modified"] {
+            let text = format!("HTTP/1.1 {}\r\nContent-Length: 10\r\n\r\nHTTP/1.1 200 OK\r\n\r\n", code);
+            let mut bs: ByteStream = text.into();
+            assert!(parse_http_response_for("GET", &mut bs).unwrap().content.is_empty());
+            assert_eq!(parse_http_response(&mut bs).unwrap().code, 200);
+        }
+    }
+
+    #[test]
+    fn http_connect_tunnel() {
+        let mut bytes = b"HTTP/1.1 200 Connection Established\r\nContent-Length: 100\r\n\r\n".to_vec();
+        bytes.extend_from_slice(&[0x16, 0x03, 0x01, 0x02, 0x00]);
+        let mut bs = ByteStream::wrap(bytes);
+
+        let (res, tunnel) = parse_connect_response(&mut bs).unwrap();
+        assert_eq!(res.code, 200);
+        assert!(res.content.is_empty());
+        assert_eq!(tunnel, Some(vec![0x16, 0x03, 0x01, 0x02, 0x00]));
+        assert_eq!(bs.pos(), bs.len());
+
+        let text = "HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 2\r\n\r\nnoHTTP/1.1";
+        let mut bs: ByteStream = text.to_string().into();
+        let (res, tunnel) = parse_connect_response(&mut bs).unwrap();
+        assert_eq!(res.code, 407);
+        assert_eq!(res.content, b"no");
+        assert!(tunnel.is_none());
+        assert_eq!(bs.as_ref(), b"HTTP/1.1");
+    }
+
+    #[test]
+    fn http_response_incomplete() {
+        let text = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        assert!(parse_http_response_with_interim(&mut bs).is_none());
+        assert_eq!(bs.pos(), 0);
+    }
+
     #[test]
     fn http_response() {
         let res = Response {
@@ -210,4 +781,141 @@ mod tests {
             "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".to_string()
         );
     }
+
+    #[test]
+    fn http_response_canonical() {
+        let res = Response {
+            protocol: "HTTP/1.1".to_string(),
+            code: 204,
+            message: "No Content".to_string(),
+            headers: vec![
+                Header {
+                    name: "x-request-ID".to_string(),
+                    value: "abc".to_string(),
+                },
+                Header {
+                    name: "SERVER".to_string(),
+                    value: "parsed".to_string(),
+                },
+            ],
+            content: vec![],
+        };
+
+        let out: String = res.canonical().into();
+        assert_eq!(
+            out,
+            "HTTP/1.1 204 No Content\r\nX-Request-Id: abc\r\nServer: parsed\r\n\r\n".to_string()
+        );
+    }
+
+    #[test]
+    fn content_length_any_case() {
+        let text = "POST / HTTP/1.1\r\ncontent-length: 3\r\n\r\nabc";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_http_request(&mut bs).unwrap();
+        assert_eq!(req.content, b"abc");
+    }
+
+    #[test]
+    fn request_error_context() {
+        let mut bs: ByteStream = "GET /x".to_string().into();
+        let err = try_parse_http_request(&mut bs).unwrap_err();
+        assert_eq!(err.context(), ["request line", "request target"]);
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nab".to_string().into();
+        let err = try_parse_http_request(&mut bs).unwrap_err();
+        assert_eq!(err.context(), ["body"]);
+        assert!(err.to_string().starts_with("in body: "));
+    }
+
+    #[test]
+    fn encoded_len() {
+        let text = "POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = try_parse_http_request(&mut bs).unwrap();
+        let mut out = Vec::with_capacity(req.encoded_len());
+        req.encode_into(&mut out);
+        assert_eq!(out, text.as_bytes());
+        assert_eq!(req.encoded_len(), text.len());
+
+        let res = Response {
+            protocol: "HTTP/1.1".to_string(),
+            code: 200,
+            message: "OK".to_string(),
+            headers: vec![Header { name: "A".to_string(), value: "b".to_string() }],
+            content: vec![b'x', 0xFF],
+        };
+        let len = res.encoded_len();
+        assert_eq!(String::from(res).len(), len);
+    }
+
+    #[test]
+    fn request_error_codes() {
+        let mut bs: ByteStream = "GET /x\r\n\r\n".to_string().into();
+        assert_eq!(try_parse_http_request(&mut bs).unwrap_err().code(), HTTP_BAD_REQUEST_LINE);
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\nHost: a\r\nno colon\r\n\r\n".to_string().into();
+        let err = try_parse_http_request(&mut bs).unwrap_err();
+        assert_eq!(err.code(), HTTP_BAD_HEADER_NAME);
+        assert_eq!(err.kind(), MatchErrorKind::Unexpected);
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\nHost: a\r\n".to_string().into();
+        assert_eq!(try_parse_http_request(&mut bs).unwrap_err().code(), "INCOMPLETE");
+
+        let text = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1x\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let err = try_parse_http_request(&mut bs).unwrap_err();
+        assert_eq!(err.code(), crate::textmsg::TEXTMSG_BAD_CONTENT_LENGTH);
+        assert_eq!(err.offset(), text.find("Content-Length").unwrap());
+
+        assert_eq!(TransferEncoding::parse("chunked, gzip").unwrap_err().code(), HTTP_BAD_TRANSFER_ENCODING);
+    }
+
+    #[test]
+    fn headers_survive_body_errors() {
+        let head = "POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n";
+        let mut bs: ByteStream = format!("{}0123", head).into();
+        let parsed = parse_http_request_headers(&mut bs, 1024).unwrap();
+        assert_eq!(parsed.request.path, "/upload");
+        assert_eq!(parsed.body_state, BodyState::Incomplete { length: 10, available: 4 });
+        assert_eq!(bs.pos(), head.len());
+
+        let mut bs: ByteStream = format!("{}0123456789", head).into();
+        let parsed = parse_http_request_headers(&mut bs, 8).unwrap();
+        assert_eq!(parsed.body_state, BodyState::TooLarge { length: 10, limit: 8 });
+        assert_eq!(parsed.request.host(), Some("a"));
+
+        let mut bs: ByteStream = format!("{}0123456789", head).into();
+        let parsed = parse_http_request_headers(&mut bs, 10).unwrap();
+        assert_eq!(parsed.body_state, BodyState::Complete);
+        assert_eq!(parsed.request.content, b"0123456789");
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n".to_string().into();
+        let parsed = parse_http_request_headers(&mut bs, 10).unwrap();
+        assert_eq!(parsed.body_state, BodyState::InvalidLength("x".to_string()));
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\nHost".to_string().into();
+        assert!(parse_http_request_headers(&mut bs, 10).is_err());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn partial_request() {
+        let text = b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+        let mut bs = ByteStream::with_capacity(text.len());
+        bs.set_partial(true);
+        for (i, b) in text.iter().enumerate() {
+            bs.put(&[*b]);
+            match try_parse_http_request(&mut bs) {
+                Ok(req) => {
+                    assert_eq!(i, text.len() - 1);
+                    assert_eq!(req.content, b"abc");
+                }
+                Err(e) => {
+                    assert!(e.is_incomplete(), "{} at {}", e, i);
+                    assert_eq!(bs.pos(), 0);
+                }
+            }
+        }
+    }
 }
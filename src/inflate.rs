@@ -0,0 +1,324 @@
+// Minimal DEFLATE (RFC 1951) decoder, plus an encoder producing stored blocks only.
+
+const MAX_BITS: usize = 15;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    cnt: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn need(&mut self, n: u32) -> Result<u32, String> {
+        let mut val = self.buf;
+        while self.cnt < n {
+            let b = *self.data.get(self.pos).ok_or("unexpected end of deflate stream")?;
+            self.pos += 1;
+            val |= (b as u32) << self.cnt;
+            self.cnt += 8;
+        }
+        self.buf = val >> n;
+        self.cnt -= n;
+        Ok(val & ((1 << n) - 1))
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.cnt = 0;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cnt == 0 && self.pos >= self.data.len()
+    }
+}
+
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for len in lengths {
+            count[*len as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for len in count.iter().skip(1) {
+            left = (left << 1) - *len as i32;
+            if left < 0 {
+                return Err("over-subscribed huffman code".to_string());
+            }
+        }
+
+        let mut offs = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbol[offs[*len as usize] as usize] = sym as u16;
+                offs[*len as usize] += 1;
+            }
+        }
+        count[0] = 0;
+        Ok(Huffman { count, symbol })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= bits.need(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("invalid huffman code".to_string())
+    }
+}
+
+// fails once the output would grow beyond `max_output` bytes, so a small compressed
+// message cannot expand without bound
+pub fn inflate(data: &[u8], max_output: usize) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { data, pos: 0, buf: 0, cnt: 0 };
+    let mut out = vec![];
+    // raw streams used by permessage-deflate end with a non-final empty stored block
+    while !bits.is_empty() {
+        let last = bits.need(1)? == 1;
+        match bits.need(2)? {
+            0 => stored(&mut bits, &mut out, max_output)?,
+            1 => {
+                let (lit, dist) = fixed()?;
+                codes(&mut bits, &mut out, &lit, &dist, max_output)?
+            }
+            2 => {
+                let (lit, dist) = dynamic(&mut bits)?;
+                codes(&mut bits, &mut out, &lit, &dist, max_output)?
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+fn too_long() -> String {
+    "inflated message too long".to_string()
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, max_output: usize) -> Result<(), String> {
+    bits.align();
+    let len = bits.need(16)? as usize;
+    let nlen = bits.need(16)? as usize;
+    if len != !nlen & 0xFFFF {
+        return Err("stored block length mismatch".to_string());
+    }
+    let end = bits.pos + len;
+    if end > bits.data.len() {
+        return Err("unexpected end of deflate stream".to_string());
+    }
+    if out.len() + len > max_output {
+        return Err(too_long());
+    }
+    out.extend_from_slice(&bits.data[bits.pos..end]);
+    bits.pos = end;
+    Ok(())
+}
+
+fn fixed() -> Result<(Huffman, Huffman), String> {
+    let mut lengths = [0u8; 288];
+    for (sym, len) in lengths.iter_mut().enumerate() {
+        *len = match sym {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5u8; 30])?))
+}
+
+fn dynamic(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let nlen = bits.need(5)? as usize + 257;
+    let ndist = bits.need(5)? as usize + 1;
+    let ncode = bits.need(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err("bad dynamic block counts".to_string());
+    }
+
+    let mut lengths = [0u8; 320];
+    for idx in CODE_LENGTH_ORDER.iter().take(ncode) {
+        lengths[*idx] = bits.need(3)? as u8;
+    }
+    let lencode = Huffman::new(&lengths[..19])?;
+
+    let mut index = 0;
+    while index < nlen + ndist {
+        let sym = lencode.decode(bits)?;
+        if sym < 16 {
+            lengths[index] = sym as u8;
+            index += 1;
+            continue;
+        }
+        let (value, repeat) = match sym {
+            16 => {
+                if index == 0 {
+                    return Err("repeat with no previous length".to_string());
+                }
+                (lengths[index - 1], 3 + bits.need(2)? as usize)
+            }
+            17 => (0, 3 + bits.need(3)? as usize),
+            _ => (0, 11 + bits.need(7)? as usize),
+        };
+        if index + repeat > nlen + ndist {
+            return Err("too many code lengths".to_string());
+        }
+        for len in lengths[index..index + repeat].iter_mut() {
+            *len = value;
+        }
+        index += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err("missing end-of-block code".to_string());
+    }
+
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..nlen + ndist])?))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman, max_output: usize) -> Result<(), String> {
+    loop {
+        let sym = lit.decode(bits)? as usize;
+        if sym < 256 {
+            if out.len() == max_output {
+                return Err(too_long());
+            }
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+        let sym = sym - 257;
+        if sym >= 29 {
+            return Err("invalid length symbol".to_string());
+        }
+        let len = LEN_BASE[sym] as usize + bits.need(LEN_EXTRA[sym] as u32)? as usize;
+        let sym = dist.decode(bits)? as usize;
+        if sym >= 30 {
+            return Err("invalid distance symbol".to_string());
+        }
+        let back = DIST_BASE[sym] as usize + bits.need(DIST_EXTRA[sym] as u32)? as usize;
+        if back > out.len() {
+            return Err("distance too far back".to_string());
+        }
+        if out.len() + len > max_output {
+            return Err(too_long());
+        }
+        let start = out.len() - back;
+        for i in 0..len {
+            let b = out[start + i];
+            out.push(b);
+        }
+    }
+}
+
+// non-final stored blocks followed by an empty one, i.e. what a sync flush produces
+pub fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 5 * (data.len() / 0xFFFF + 2));
+    for chunk in data.chunks(0xFFFF) {
+        stored_block(&mut out, chunk);
+    }
+    stored_block(&mut out, &[]);
+    out
+}
+
+fn stored_block(out: &mut Vec<u8>, chunk: &[u8]) {
+    let len = chunk.len() as u16;
+    out.push(0);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(!len).to_le_bytes());
+    out.extend_from_slice(chunk);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: usize = 1 << 20;
+
+    #[test]
+    fn inflate_fixed() {
+        // "Hello" compressed with permessage-deflate (RFC 7692, section 7.2.3.1), tail restored
+        let data = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00, 0x00, 0xff, 0xff];
+        assert_eq!(inflate(&data, MAX).unwrap(), b"Hello");
+    }
+
+    #[test]
+    fn inflate_back_reference() {
+        // "Hello" twice sharing the window (RFC 7692, section 7.2.3.2)
+        let data = [0xf2, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff];
+        let mut first = inflate(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00, 0x00, 0xff, 0xff], MAX).unwrap();
+        assert!(inflate(&data, MAX).is_err());
+        let mut joined = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00, 0x00, 0xff, 0xff];
+        joined.extend_from_slice(&data);
+        first.extend_from_slice(b"Hello");
+        assert_eq!(inflate(&joined, MAX).unwrap(), first);
+    }
+
+    #[test]
+    fn stored_roundtrip() {
+        let data = (0..200_000u32).map(|x| (x % 251) as u8).collect::<Vec<u8>>();
+        let encoded = deflate_stored(&data);
+        assert!(encoded.ends_with(&[0x00, 0x00, 0x00, 0xff, 0xff]));
+        assert_eq!(inflate(&encoded, MAX).unwrap(), data);
+    }
+
+    #[test]
+    fn inflate_errors() {
+        assert!(inflate(&[0x07], MAX).is_err());
+        assert!(inflate(&[0x00, 0x05, 0x00, 0x00, 0x00], MAX).is_err());
+        assert!(inflate(&[0x00, 0x05, 0x00, 0xfa, 0xff, b'a'], MAX).is_err());
+    }
+
+    #[test]
+    fn inflate_limit() {
+        let data = deflate_stored(&[b'a'; 300]);
+        assert!(inflate(&data, 299).is_err());
+        assert_eq!(inflate(&data, 300).unwrap().len(), 300);
+
+        // literals, then a back reference in the second block
+        let mut data = vec![0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00, 0x00, 0xff, 0xff];
+        assert!(inflate(&data, 4).is_err());
+        data.extend_from_slice(&[0xf2, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff]);
+        assert!(inflate(&data, 9).is_err());
+        assert_eq!(inflate(&data, 10).unwrap(), b"HelloHello");
+    }
+}
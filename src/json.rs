@@ -0,0 +1,301 @@
+// JSON (RFC 8259) values, and newline-delimited JSON records for log pipelines.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{delimited, escaped, exact, get_u8, line, match_value, rest, single, space0, standard_escape};
+use crate::stream::ByteStream;
+
+// nesting limit, so hostile input can not exhaust the stack
+pub const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    // members in document order, duplicates are kept
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+// a single value with optional surrounding whitespace
+pub fn value() -> impl Matcher<Value> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = value_at(bs, 0).and_then(|v| space0().do_match(bs).map(|_| v));
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+fn unexpected(bs: &ByteStream, expected: &str) -> MatchError {
+    match bs.as_ref().first() {
+        Some(b) => MatchError::unexpected(bs.pos(), (*b as char).to_string(), expected.to_string()),
+        None => MatchError::incomplete(bs.pos(), 1),
+    }
+}
+
+fn value_at(bs: &mut ByteStream, depth: usize) -> Result<Value, MatchError> {
+    if depth > MAX_DEPTH {
+        return Err(MatchError::custom(bs.pos(), format!("nesting deeper than {}", MAX_DEPTH)));
+    }
+    space0().do_match(bs)?;
+    match bs.as_ref().first() {
+        Some(b'{') => {
+            bs.advance(1);
+            let mut members = vec![];
+            space0().do_match(bs)?;
+            if bs.as_ref().first() == Some(&b'}') {
+                bs.advance(1);
+                return Ok(Value::Object(members));
+            }
+            loop {
+                space0().do_match(bs)?;
+                if bs.as_ref().first() != Some(&b'"') {
+                    return Err(unexpected(bs, "object key"));
+                }
+                let key = string().do_match(bs)?;
+                space0().do_match(bs)?;
+                single(':').do_match(bs)?;
+                members.push((key, value_at(bs, depth + 1)?));
+                space0().do_match(bs)?;
+                match bs.as_ref().first() {
+                    Some(b',') => {
+                        bs.advance(1);
+                    }
+                    Some(b'}') => {
+                        bs.advance(1);
+                        return Ok(Value::Object(members));
+                    }
+                    _ => return Err(unexpected(bs, "',' or '}'")),
+                }
+            }
+        }
+        Some(b'[') => {
+            bs.advance(1);
+            let mut items = vec![];
+            space0().do_match(bs)?;
+            if bs.as_ref().first() == Some(&b']') {
+                bs.advance(1);
+                return Ok(Value::Array(items));
+            }
+            loop {
+                items.push(value_at(bs, depth + 1)?);
+                space0().do_match(bs)?;
+                match bs.as_ref().first() {
+                    Some(b',') => {
+                        bs.advance(1);
+                    }
+                    Some(b']') => {
+                        bs.advance(1);
+                        return Ok(Value::Array(items));
+                    }
+                    _ => return Err(unexpected(bs, "',' or ']'")),
+                }
+            }
+        }
+        Some(b'"') => string().do_match(bs).map(Value::String),
+        Some(b't') => exact(b"true").do_match(bs).map(|_| Value::Bool(true)),
+        Some(b'f') => exact(b"false").do_match(bs).map(|_| Value::Bool(false)),
+        Some(b'n') => exact(b"null").do_match(bs).map(|_| Value::Null),
+        Some(b'-') | Some(b'0'..=b'9') => number(bs).map(Value::Number),
+        _ => Err(unexpected(bs, "JSON value")),
+    }
+}
+
+// -? (0 | [1-9][0-9]*) (\.[0-9]+)? ([eE][+-]?[0-9]+)?, unlike `float` there is no leading
+// '+', no leading zeros and no bare '.' on either side of the digits
+fn number(bs: &mut ByteStream) -> Result<f64, MatchError> {
+    let pos = bs.pos();
+    let input = bs.as_ref();
+    let digits_at = |i: usize| input[i.min(input.len())..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let fail = |i: usize| match input.get(i) {
+        Some(b) => MatchError::unexpected(pos + i, (*b as char).to_string(), "digit".to_string()),
+        None => MatchError::incomplete(pos + i, 1),
+    };
+    let mut len = (input.first() == Some(&b'-')) as usize;
+    let int = digits_at(len);
+    if int == 0 || (int > 1 && input[len] == b'0') {
+        return Err(fail(len + (int > 1) as usize));
+    }
+    len += int;
+    if input.get(len) == Some(&b'.') {
+        let frac = digits_at(len + 1);
+        if frac == 0 {
+            return Err(fail(len + 1));
+        }
+        len += 1 + frac;
+    }
+    if matches!(input.get(len), Some(b'e') | Some(b'E')) {
+        let sign = matches!(input.get(len + 1), Some(b'+') | Some(b'-')) as usize;
+        let exp = digits_at(len + 1 + sign);
+        if exp == 0 {
+            return Err(fail(len + 1 + sign));
+        }
+        len += 1 + sign + exp;
+    }
+    if bs.is_partial() && len == input.len() {
+        return Err(MatchError::incomplete(pos + len, 1));
+    }
+    let text = String::from_utf8_lossy(&input[..len]).to_string();
+    let n = text.parse::<f64>()
+        .map_err(|_| MatchError::unexpected(pos, text, "JSON number".to_string()))?;
+    bs.advance(len);
+    Ok(n)
+}
+
+// control characters U+0000 to U+001F must be escaped
+fn string() -> impl Matcher<String> {
+    let normal = get_u8().verify(|b| *b >= 0x20 && *b != b'"' && *b != b'\\');
+    delimited(single('"'), escaped(normal, '\\', escape()), single('"'))
+}
+
+// standard escapes plus \uXXXX, with surrogate pairs for characters outside the BMP
+fn escape() -> impl Matcher<char> {
+    move |bs: &mut ByteStream| {
+        if bs.as_ref().first() != Some(&b'u') {
+            return standard_escape().do_match(bs);
+        }
+        let pos = bs.pos();
+        let hi = hex4(bs)?;
+        let code = if (0xD800..0xDC00).contains(&hi) {
+            exact(b"\\").do_match(bs)?;
+            let lo = hex4(bs)?;
+            if !(0xDC00..0xE000).contains(&lo) {
+                return Err(MatchError::unexpected(pos, format!("{:04x}", lo), "low surrogate".to_string()));
+            }
+            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+        } else {
+            hi
+        };
+        std::char::from_u32(code)
+            .ok_or_else(|| MatchError::unexpected(pos, format!("{:04x}", code), "unicode scalar value".to_string()))
+    }
+}
+
+fn hex4(bs: &mut ByteStream) -> Result<u32, MatchError> {
+    single('u').do_match(bs)?;
+    let mut code = 0;
+    for _ in 0..4 {
+        let pos = bs.pos();
+        let b = get_u8().do_match(bs)?;
+        let digit = (b as char).to_digit(16)
+            .ok_or_else(|| MatchError::unexpected(pos, (b as char).to_string(), "hex digit".to_string()))?;
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+// one value per newline-delimited record (NDJSON, JSON Lines), blank lines are skipped;
+// a malformed record yields its error and reading resumes at the next line so one bad
+// record does not stop the pipeline; on a partial stream an unterminated last record is
+// left in place until more bytes arrive
+pub fn lines(stream: &mut ByteStream) -> Lines<'_> {
+    Lines { stream }
+}
+
+pub struct Lines<'a> {
+    stream: &'a mut ByteStream,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Result<Value, MatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.stream.remaining() == 0 {
+                return None;
+            }
+            let start = self.stream.pos();
+            let record = match line().do_match(self.stream) {
+                Ok(record) => record,
+                Err(_) if self.stream.is_partial() => return None,
+                Err(_) => rest().do_match(self.stream).ok()?,
+            };
+            if record.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+            return Some(match_value(&value(), record, start));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn values() {
+        let text = r#" {"a": [1, -2.5e1, true, null], "b": {"c": "x\ty\u00e9\ud83d\ude00"}, "d": []} "#;
+        let mut bs = ByteStream::wrap(text.as_bytes().to_vec());
+        let v = bs.apply(value()).unwrap();
+        assert_eq!(bs.remaining(), 0);
+        assert_eq!(v.get("a"), Some(&Value::Array(vec![
+            Value::Number(1.0),
+            Value::Number(-25.0),
+            Value::Bool(true),
+            Value::Null,
+        ])));
+        assert_eq!(v.get("b").and_then(|b| b.get("c")).and_then(Value::as_str), Some("x\ty\u{e9}\u{1F600}"));
+        assert_eq!(v.get("d"), Some(&Value::Array(vec![])));
+
+        for bad in [&b"{\"a\" 1}"[..], b"[1,]", b"+1", b"\"\\ud83d\"", b"{1: 2}", b"-.5", b"01", b"-01", b"1.", b"1.e5", b"1e", b"-",
+            b"\"a\nb\"", b"\"\x00\"", b"[\"\t\"]"] {
+            let mut bs = ByteStream::wrap(bad.to_vec());
+            assert!(bs.apply(value()).is_err(), "{}", String::from_utf8_lossy(bad));
+            assert_eq!(bs.pos(), 0);
+        }
+
+        let mut bs = ByteStream::wrap(vec![b'['; MAX_DEPTH + 2]);
+        assert!(bs.apply(value()).unwrap_err().to_string().contains("nesting"));
+    }
+
+    #[test]
+    fn ndjson() {
+        let text = b"{\"level\":\"info\"}\n\n{\"level\":}\r\n[1] [2]\n{\"level\":\"warn\"}";
+        let mut bs = ByteStream::wrap(text.to_vec());
+        let records = lines(&mut bs).collect::<Vec<_>>();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].as_ref().unwrap().get("level").and_then(Value::as_str), Some("info"));
+        assert_eq!(records[1].as_ref().unwrap_err().offset(), 27);
+        assert!(records[2].is_err());
+        assert_eq!(records[3].as_ref().unwrap().get("level").and_then(Value::as_str), Some("warn"));
+    }
+
+    #[test]
+    fn ndjson_partial() {
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"{\"n\":1}\n{\"n\":");
+        assert_eq!(lines(&mut bs).count(), 1);
+        bs.put(b"2}\n");
+        let next = lines(&mut bs).next().unwrap().unwrap();
+        assert_eq!(next.get("n").and_then(Value::as_f64), Some(2.0));
+        assert!(lines(&mut bs).next().is_none());
+    }
+}
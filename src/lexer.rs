@@ -0,0 +1,119 @@
+// Token matcher wrapper skipping whitespace and comments around each token.
+use crate::matcher::{MatchError, Matcher};
+use crate::stream::ByteStream;
+
+pub struct Lexer<M> {
+    token: M,
+    line: Vec<&'static str>,
+    block: Vec<(&'static str, &'static str)>,
+}
+
+pub fn lexer<T, M: Matcher<T>>(token: M) -> Lexer<M> {
+    Lexer {
+        token,
+        line: vec![],
+        block: vec![],
+    }
+}
+
+impl<M> Lexer<M> {
+    // comment running until the end of the line, e.g. "#" or "//"
+    pub fn with_line_comments(mut self, prefix: &'static str) -> Lexer<M> {
+        assert!(!prefix.is_empty(), "line comment prefix must not be empty");
+        self.line.push(prefix);
+        self
+    }
+
+    // comment between two delimiters, e.g. "/*" and "*/", not nested
+    pub fn with_block_comments(mut self, open: &'static str, close: &'static str) -> Lexer<M> {
+        assert!(!open.is_empty() && !close.is_empty(), "block comment delimiters must not be empty");
+        self.block.push((open, close));
+        self
+    }
+
+    fn skip(&self, bs: &mut ByteStream) -> Result<(), MatchError> {
+        loop {
+            let ws = bs.as_ref().iter().take_while(|b| b.is_ascii_whitespace()).count();
+            bs.advance(ws);
+            let rest = bs.as_ref();
+            if self.line.iter().any(|p| rest.starts_with(p.as_bytes())) {
+                let len = rest.iter().position(|b| *b == b'\n').map(|idx| idx + 1).unwrap_or(rest.len());
+                bs.advance(len);
+                continue;
+            }
+            match self.block.iter().find(|(open, _)| rest.starts_with(open.as_bytes())) {
+                Some((open, close)) => {
+                    let body = &rest[open.len()..];
+                    match body.windows(close.len()).position(|w| w == close.as_bytes()) {
+                        Some(idx) => {
+                            bs.advance(open.len() + idx + close.len());
+                        }
+                        None => return Err(MatchError::incomplete(bs.len(), close.len())),
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<T, M: Matcher<T>> Matcher<T> for Lexer<M> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let mark = bs.mark();
+        let result = self.skip(bs)
+            .and_then(|_| self.token.do_match(bs))
+            .and_then(|t| self.skip(bs).map(|_| t));
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{alt, repeat, single, until, Applicator};
+
+    fn word() -> impl Matcher<String> {
+        until(|b| b.is_ascii_alphanumeric()).verify(|w| !w.is_empty())
+            .map(|w| String::from_utf8(w).unwrap())
+    }
+
+    #[test]
+    fn skips_comments() {
+        let text = "# header\n key = /* inline */ value // trailing\n/**/=x ";
+        let tok = || {
+            let eq = single('=').map(|c| c.to_string());
+            lexer(alt((word(), eq)))
+                .with_line_comments("#")
+                .with_line_comments("//")
+                .with_block_comments("/*", "*/")
+        };
+        let mut bs = ByteStream::wrap(text.as_bytes().to_vec());
+        let tokens = bs.apply(repeat(tok())).unwrap();
+        assert_eq!(tokens, vec!["key", "=", "value", "=", "x"]);
+        assert_eq!(bs.remaining(), 0);
+    }
+
+    #[test]
+    fn unterminated_block() {
+        let mut bs = ByteStream::wrap(b"a /* b".to_vec());
+        let tok = lexer(word()).with_block_comments("/*", "*/");
+        let err = bs.apply(tok).unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be empty")]
+    fn empty_line_prefix() {
+        lexer(word()).with_line_comments("");
+    }
+
+    #[test]
+    #[should_panic(expected = "must not be empty")]
+    fn empty_block_close() {
+        lexer(word()).with_block_comments("/*", "");
+    }
+}
@@ -1,9 +1,68 @@
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
+#![cfg_attr(not(feature = "forbid-unsafe"), deny(unsafe_code))]
+
 pub mod stream;
 pub mod matcher;
 pub mod parser;
+pub mod debug;
+pub mod chars;
+pub mod ascii;
+pub mod scanner;
+pub mod expr;
+pub mod lexer;
+pub mod framing;
+pub mod metrics;
+pub mod batch;
+pub mod pktline;
+pub mod amqp;
+pub mod tlv;
+pub mod thrift;
+pub mod json;
+pub mod radius;
+pub mod ntp;
+pub mod dhcp;
+pub mod dnssd;
+pub mod modbus;
 
 #[cfg(feature = "http")]
 pub mod http;
 
+#[cfg(feature = "http")]
+pub mod textmsg;
+
+#[cfg(feature = "http")]
+pub mod rtsp;
+
+#[cfg(feature = "http")]
+pub mod sip;
+
 #[cfg(feature = "http")]
 pub mod ws;
+
+#[cfg(feature = "http")]
+pub mod testing;
+
+#[cfg(feature = "http")]
+pub mod middleware;
+
+#[cfg(feature = "http")]
+pub mod multipart;
+
+#[cfg(feature = "http")]
+pub mod extension;
+
+#[cfg(feature = "xml")]
+pub mod xml;
+
+#[cfg(feature = "cbor")]
+pub mod cbor;
+
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+
+#[cfg(feature = "http")]
+mod inflate;
+
+#[cfg(feature = "http")]
+#[cfg_attr(not(feature = "forbid-unsafe"), allow(unsafe_code))]
+mod raw;
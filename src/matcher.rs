@@ -1,11 +1,23 @@
 use crate::stream::ByteStream;
-use std::{error, fmt};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::{error, fmt, io};
 use std::marker::PhantomData;
+use std::ops::Range;
 
-pub trait Matcher<T> {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError>;
+// E can be a domain error type; most combinators in `parser` work with MatchError,
+// see `map_err` for converting in either direction
+pub trait Matcher<T, E = MatchError> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E>;
 
-    fn boxed(self) -> Box<dyn Matcher<T>>
+    // what the matcher accepts at its entry point (literals, byte classes), None when it
+    // can not tell; alternatives use it to report "one of: GET, POST, PUT" on failure
+    fn expected_set(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn boxed(self) -> Box<dyn Matcher<T, E>>
     where
         Self: Sized + 'static,
     {
@@ -15,7 +27,7 @@ pub trait Matcher<T> {
     fn then<U, That>(self, that: That) -> Chain<Self, That>
     where
         Self: Sized,
-        That: Matcher<U>,
+        That: Matcher<U, E>,
     {
         Chain(self, that)
     }
@@ -24,11 +36,40 @@ pub trait Matcher<T> {
     where
         Self: Sized,
         F: Fn(&T) -> N + 'static,
-        N: Matcher<U>,
+        N: Matcher<U, E>,
     {
         Expose { context: self, next: f }
     }
 
+    // like `then_with`, but the continuation owns the context and hands it back together with
+    // the next matcher; the stream is reset when that matcher fails
+    fn then_into<U, F, N>(self, f: F) -> ThenInto<Self, T, F>
+    where
+        Self: Sized,
+        F: Fn(T) -> (T, N) + 'static,
+        N: Matcher<U, E>,
+    {
+        ThenInto {
+            context: self,
+            next: f,
+            phantom: PhantomData::<T>,
+        }
+    }
+
+    // the matcher chosen by the continuation replaces the result, e.g. dispatch on an opcode
+    fn and_then<U, F, N>(self, f: F) -> AndThen<Self, T, F>
+    where
+        Self: Sized,
+        F: Fn(T) -> N + 'static,
+        N: Matcher<U, E>,
+    {
+        AndThen {
+            context: self,
+            next: f,
+            phantom: PhantomData::<T>,
+        }
+    }
+
     fn map<U, F>(self, f: F) -> Map<Self, T, F>
     where
         Self: Sized,
@@ -41,40 +82,168 @@ pub trait Matcher<T> {
         }
     }
 
+    // a clone of `value` in place of whatever was matched, e.g. `string("GET").to(Method::Get)`
+    fn to<U: Clone + 'static>(self, value: U) -> To<Self, T, U>
+    where
+        Self: Sized,
+    {
+        To {
+            prev: self,
+            value,
+            phantom: PhantomData::<T>,
+        }
+    }
+
     fn then_map<U, That, F, V>(self, that: That, f: F) -> Map<Chain<Self, That>, (T, U), F>
     where
         Self: Sized,
-        That: Matcher<U>,
+        That: Matcher<U, E>,
         F: Fn((T, U)) -> V + 'static,
     {
         self.then(that).map(f)
     }
+
+    // the mapper's error becomes the source of the resulting MatchError
+    fn try_map<U, Er, F>(self, f: F) -> TryMap<Self, T, F>
+    where
+        Self: Sized,
+        Er: Into<Box<dyn error::Error + Send + Sync>>,
+        F: Fn(T) -> Result<U, Er> + 'static,
+    {
+        TryMap {
+            prev: self,
+            mapper: f,
+            phantom: PhantomData::<T>,
+        }
+    }
+
+    fn verify<F>(self, f: F) -> Verify<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&T) -> bool + 'static,
+    {
+        Verify { inner: self, check: f }
+    }
+
+    fn map_err<E2, F>(self, f: F) -> MapErr<Self, E, F>
+    where
+        Self: Sized,
+        F: Fn(E) -> E2 + 'static,
+    {
+        MapErr {
+            inner: self,
+            mapper: f,
+            phantom: PhantomData::<E>,
+        }
+    }
+
+    fn spanned(self) -> Spanned<Self>
+    where
+        Self: Sized,
+    {
+        Spanned(self)
+    }
+
+    fn context(self, label: &'static str) -> Context<Self>
+    where
+        Self: Sized,
+    {
+        Context { inner: self, label }
+    }
+
+    fn or<That>(self, that: That) -> Or<Self, That>
+    where
+        Self: Sized,
+        That: Matcher<T>,
+    {
+        Or(self, that)
+    }
 }
 
-impl<T, F> Matcher<T> for F where F: Fn(&mut ByteStream) -> Result<T, MatchError> {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+impl<T, E, F> Matcher<T, E> for F where F: Fn(&mut ByteStream) -> Result<T, E> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E> {
         (self)(bs)
     }
 }
 
-impl<T> Matcher<T> for Box<dyn Matcher<T>> {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+impl<T, E> Matcher<T, E> for Box<dyn Matcher<T, E>> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E> {
         (**self).do_match(bs)
     }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        (**self).expected_set()
+    }
+}
+
+// both sets must be known for the union to be
+pub(crate) fn union(a: Option<Vec<String>>, b: Option<Vec<String>>) -> Option<Vec<String>> {
+    let mut a = a?;
+    for x in b? {
+        if !a.contains(&x) {
+            a.push(x);
+        }
+    }
+    Some(a)
+}
+
+// a failure at the entry point that tells nothing beyond the alternative not applying
+pub(crate) fn fails_at_entry(e: &MatchError, pos: usize) -> bool {
+    e.offset() == pos && !e.is_incomplete() && !e.is_fatal()
+}
+
+// replaces the error of the last failed alternative when all of them failed at the entry
+// point, `at_entry` telling whether the ones before it did
+pub(crate) fn expected_one_of(bs: &ByteStream, pos: usize, e: MatchError, set: Option<Vec<String>>, at_entry: bool) -> MatchError {
+    match set {
+        Some(set) if at_entry && fails_at_entry(&e, pos) => {
+            let got = bs.as_ref().first().map(|b| (*b as char).to_string()).unwrap_or_else(|| "EOF".to_string());
+            MatchError::unexpected(pos, got, format!("one of: {}", set.join(", ")))
+        }
+        _ => e,
+    }
 }
 
 // Chain
 
 pub struct Chain<M, N>(M, N);
 
-impl<M, N, T, U> Matcher<(T, U)> for Chain<M, N> where M: Matcher<T>, N: Matcher<U> {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, U), MatchError> {
+impl<M, N, T, U, E> Matcher<(T, U), E> for Chain<M, N> where M: Matcher<T, E>, N: Matcher<U, E> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, U), E> {
         let t = self.0.do_match(bs)?;
         let u = self.1.do_match(bs)?;
         Ok((t, u))
     }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.0.expected_set()
+    }
+}
+
+// tuples of matchers run in sequence and produce a flat tuple, `(a, b, c)` instead of
+// the `((a, b), c)` of `a.then(b).then(c)`
+macro_rules! tuple_impl {
+    ($($m:ident $t:ident $idx:tt),+) => {
+        impl<E, $($m: Matcher<$t, E>, $t,)+> Matcher<($($t,)+), E> for ($($m,)+) {
+            fn do_match(&self, bs: &mut ByteStream) -> Result<($($t,)+), E> {
+                Ok(($(self.$idx.do_match(bs)?,)+))
+            }
+
+            fn expected_set(&self) -> Option<Vec<String>> {
+                self.0.expected_set()
+            }
+        }
+    };
 }
 
+tuple_impl!(A TA 0, B TB 1);
+tuple_impl!(A TA 0, B TB 1, C TC 2);
+tuple_impl!(A TA 0, B TB 1, C TC 2, D TD 3);
+tuple_impl!(A TA 0, B TB 1, C TC 2, D TD 3, F TF 4);
+tuple_impl!(A TA 0, B TB 1, C TC 2, D TD 3, F TF 4, G TG 5);
+tuple_impl!(A TA 0, B TB 1, C TC 2, D TD 3, F TF 4, G TG 5, H TH 6);
+tuple_impl!(A TA 0, B TB 1, C TC 2, D TD 3, F TF 4, G TG 5, H TH 6, I TI 7);
+
 // Expose
 
 pub struct Expose<M, F>{
@@ -82,18 +251,78 @@ pub struct Expose<M, F>{
     next: F,
 }
 
-impl<M, F, N, T, U> Matcher<(T, U)> for Expose<M, F>
+impl<M, F, N, T, U, E> Matcher<(T, U), E> for Expose<M, F>
 where
-    M: Matcher<T>,
+    M: Matcher<T, E>,
     F: Fn(&T) -> N + 'static,
-    N: Matcher<U>,
+    N: Matcher<U, E>,
 {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, U), MatchError> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, U), E> {
         let t = self.context.do_match(bs)?;
         let g = (self.next)(&t);
         let u = g.do_match(bs)?;
         Ok((t, u))
     }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.context.expected_set()
+    }
+}
+
+// ThenInto
+
+pub struct ThenInto<M, T, F> {
+    context: M,
+    next: F,
+    phantom: PhantomData<T>,
+}
+
+impl<M, T, F, N, U, E> Matcher<(T, U), E> for ThenInto<M, T, F>
+where
+    M: Matcher<T, E>,
+    F: Fn(T) -> (T, N) + 'static,
+    N: Matcher<U, E>,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, U), E> {
+        let mark = bs.mark();
+        let t = self.context.do_match(bs)?;
+        let (t, next) = (self.next)(t);
+        match next.do_match(bs) {
+            Ok(u) => Ok((t, u)),
+            Err(e) => {
+                bs.reset(mark);
+                Err(e)
+            }
+        }
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.context.expected_set()
+    }
+}
+
+// AndThen
+
+pub struct AndThen<M, T, F> {
+    context: M,
+    next: F,
+    phantom: PhantomData<T>,
+}
+
+impl<M, T, F, N, U, E> Matcher<U, E> for AndThen<M, T, F>
+where
+    M: Matcher<T, E>,
+    F: Fn(T) -> N + 'static,
+    N: Matcher<U, E>,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<U, E> {
+        let t = self.context.do_match(bs)?;
+        (self.next)(t).do_match(bs)
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.context.expected_set()
+    }
 }
 
 // Map
@@ -104,16 +333,295 @@ pub struct Map<M, T, F> {
     phantom: PhantomData<T>,
 }
 
-impl<M, T, U, F> Matcher<U> for Map<M, T, F>
+impl<M, T, U, E, F> Matcher<U, E> for Map<M, T, F>
 where
-    M: Matcher<T>,
+    M: Matcher<T, E>,
     F: Fn(T) -> U + 'static,
 {
-    fn do_match(&self, bs: &mut ByteStream) -> Result<U, MatchError> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<U, E> {
         let t = self.prev.do_match(bs)?;
         let u = (self.mapper)(t);
         Ok(u)
     }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.prev.expected_set()
+    }
+}
+
+// To
+
+pub struct To<M, T, U> {
+    prev: M,
+    value: U,
+    phantom: PhantomData<T>,
+}
+
+impl<M, T, U, E> Matcher<U, E> for To<M, T, U>
+where
+    M: Matcher<T, E>,
+    U: Clone + 'static,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<U, E> {
+        self.prev.do_match(bs)?;
+        Ok(self.value.clone())
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.prev.expected_set()
+    }
+}
+
+// TryMap
+
+pub struct TryMap<M, T, F> {
+    prev: M,
+    mapper: F,
+    phantom: PhantomData<T>,
+}
+
+impl<M, T, U, E, Er, F> Matcher<U, E> for TryMap<M, T, F>
+where
+    M: Matcher<T, E>,
+    E: From<MatchError>,
+    Er: Into<Box<dyn error::Error + Send + Sync>>,
+    F: Fn(T) -> Result<U, Er> + 'static,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<U, E> {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let t = self.prev.do_match(bs)?;
+        (self.mapper)(t).map_err(|e| {
+            bs.reset(mark);
+            let e = e.into();
+            MatchError::custom(pos, e.to_string()).with_source(e).into()
+        })
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.prev.expected_set()
+    }
+}
+
+// MapErr
+
+pub struct MapErr<M, E, F> {
+    inner: M,
+    mapper: F,
+    phantom: PhantomData<E>,
+}
+
+impl<M, T, E, E2, F> Matcher<T, E2> for MapErr<M, E, F>
+where
+    M: Matcher<T, E>,
+    F: Fn(E) -> E2 + 'static,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E2> {
+        self.inner.do_match(bs).map_err(&self.mapper)
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.inner.expected_set()
+    }
+}
+
+// Verify
+
+pub struct Verify<M, F> {
+    inner: M,
+    check: F,
+}
+
+impl<M, F, T, E> Matcher<T, E> for Verify<M, F>
+where
+    M: Matcher<T, E>,
+    E: From<MatchError>,
+    F: Fn(&T) -> bool + 'static,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E> {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let t = self.inner.do_match(bs)?;
+        if (self.check)(&t) {
+            Ok(t)
+        } else {
+            bs.reset(mark);
+            Err(MatchError::unexpected(
+                pos,
+                "value rejected by verify".to_string(),
+                "valid value".to_string(),
+            ).into())
+        }
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.inner.expected_set()
+    }
+}
+
+// Spanned
+
+pub struct Spanned<M>(M);
+
+impl<T, E, M: Matcher<T, E>> Matcher<(T, Range<usize>), E> for Spanned<M> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<(T, Range<usize>), E> {
+        let start = bs.pos();
+        let t = self.0.do_match(bs)?;
+        Ok((t, start..bs.pos()))
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.0.expected_set()
+    }
+}
+
+// Context
+
+pub struct Context<M> {
+    inner: M,
+    label: &'static str,
+}
+
+impl<T, M: Matcher<T>> Matcher<T> for Context<M> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        self.inner.do_match(bs).map_err(|e| e.with_context(self.label))
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.inner.expected_set()
+    }
+}
+
+// Or
+
+pub struct Or<M, N>(M, N);
+
+impl<M, N, T> Matcher<T> for Or<M, N> where M: Matcher<T>, N: Matcher<T> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        match self.0.do_match(bs) {
+            Ok(t) => Ok(t),
+            Err(e) if e.is_committed(bs) => Err(e),
+            Err(first) => {
+                bs.reset(mark);
+                let at_entry = fails_at_entry(&first, pos);
+                self.1.do_match(bs).map_err(|e| {
+                    bs.reset(mark);
+                    expected_one_of(bs, pos, e, self.expected_set(), at_entry)
+                })
+            }
+        }
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        union(self.0.expected_set(), self.1.expected_set())
+    }
+}
+
+// Stateful
+
+// FnMut closures (counters, hashers, caches) as matchers; the closure must not
+// re-enter itself, e.g. through `lazy`, as it is borrowed for the duration of a match
+pub struct Stateful<F>(RefCell<F>);
+
+impl<T, E, F> Matcher<T, E> for Stateful<F> where F: FnMut(&mut ByteStream) -> Result<T, E> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, E> {
+        (self.0.borrow_mut())(bs)
+    }
+}
+
+pub fn stateful<T, E, F: FnMut(&mut ByteStream) -> Result<T, E>>(f: F) -> Stateful<F> {
+    Stateful(RefCell::new(f))
+}
+
+// Memoized
+
+// packrat-style cache of outcomes by start position, for grammars that backtrack into the
+// same rule at the same offset: clones share the cache, so use a clone at every place the
+// rule appears. Positions are only meaningful for one input, so the cache is dropped when
+// matching another stream or when the bytes of this one change (a partial stream that
+// grew, `pull` or `clear`), and failures for lack of input are never cached.
+pub struct Memoized<M, T> {
+    inner: Rc<M>,
+    capacity: usize,
+    cache: Rc<RefCell<Memo<T>>>,
+}
+
+struct Memo<T> {
+    epoch: (u64, usize),
+    // start position to the outcome and the position the stream was left at
+    entries: BTreeMap<usize, (Result<T, MatchError>, usize)>,
+}
+
+impl<M, T> Clone for Memoized<M, T> {
+    fn clone(&self) -> Self {
+        Memoized {
+            inner: self.inner.clone(),
+            capacity: self.capacity,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<M, T> Memoized<M, T> {
+    // when full, the lowest positions are evicted first, the parse having moved past them
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn clear(&self) {
+        self.cache.borrow_mut().entries.clear();
+    }
+}
+
+impl<T: Clone, M: Matcher<T>> Matcher<T> for Memoized<M, T> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let pos = bs.pos();
+        {
+            let mut memo = self.cache.borrow_mut();
+            if memo.epoch != bs.epoch() {
+                memo.entries.clear();
+                memo.epoch = bs.epoch();
+            }
+            if let Some((result, end)) = memo.entries.get(&pos) {
+                bs.advance(end - pos);
+                return match result {
+                    Ok(t) => Ok(t.clone()),
+                    Err(e) => Err(e.replay()),
+                };
+            }
+        }
+        // not borrowed while matching, the rule may well be recursive
+        let result = self.inner.do_match(bs);
+        let end = bs.pos();
+        let cached = match &result {
+            Ok(t) => Ok(t.clone()),
+            Err(e) if e.is_incomplete() => return result,
+            Err(e) => Err(e.replay()),
+        };
+        let mut memo = self.cache.borrow_mut();
+        if memo.epoch == bs.epoch() && end >= pos && self.capacity > 0 {
+            while memo.entries.len() >= self.capacity {
+                memo.entries.pop_first();
+            }
+            memo.entries.insert(pos, (cached, end));
+        }
+        result
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.inner.expected_set()
+    }
+}
+
+pub fn memoized<T: Clone, M: Matcher<T>>(inner: M) -> Memoized<M, T> {
+    Memoized {
+        inner: Rc::new(inner),
+        capacity: 4096,
+        cache: Rc::new(RefCell::new(Memo { epoch: (u64::MAX, 0), entries: BTreeMap::new() })),
+    }
 }
 
 pub fn unit<T: 'static, F: Fn() -> T + 'static>(f: F) -> impl Matcher<T> {
@@ -123,37 +631,243 @@ pub fn unit<T: 'static, F: Fn() -> T + 'static>(f: F) -> impl Matcher<T> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MatchErrorKind {
+    Unexpected,
+    // a searched for delimiter or pattern is not in the input
+    NotFound,
+    OverCapacity,
+    // the input ended early, at least `needed` more bytes are required
+    Incomplete { needed: usize },
+    Custom,
+}
+
 #[derive(Debug)]
 pub struct MatchError {
+    kind: MatchErrorKind,
     offset: usize,
     message: String,
+    fatal: bool,
+    context: Vec<String>,
+    position: Option<(usize, usize)>,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+    code: Option<&'static str>,
 }
 
 impl MatchError {
     pub fn unexpected(offset: usize, got: String, expected: String) -> MatchError {
         MatchError {
+            kind: MatchErrorKind::Unexpected,
             offset,
             message: format!(
                 "MatchError at offset {} expected '{}' but got '{}'",
                 offset, expected, got
             ),
+            fatal: false,
+            context: vec![],
+            position: None,
+            source: None,
+            code: None,
         }
     }
 
     pub fn over_capacity(offset: usize, available: usize, requested: usize) -> MatchError {
         MatchError {
+            kind: MatchErrorKind::OverCapacity,
             offset,
             message: format!(
                 "MatchError at offset {}, requested {} bytes, but buffer has only {}",
                 offset, requested, available
             ),
+            fatal: false,
+            context: vec![],
+            position: None,
+            source: None,
+            code: None,
+        }
+    }
+
+    pub fn not_found(offset: usize, expected: String) -> MatchError {
+        MatchError {
+            kind: MatchErrorKind::NotFound,
+            offset,
+            message: format!("MatchError at offset {} expected '{}' but it was not found", offset, expected),
+            fatal: false,
+            context: vec![],
+            position: None,
+            source: None,
+            code: None,
+        }
+    }
+
+    pub fn incomplete(offset: usize, needed: usize) -> MatchError {
+        MatchError {
+            kind: MatchErrorKind::Incomplete { needed },
+            offset,
+            message: format!("MatchError at offset {}, input ended while {} more bytes were needed", offset, needed),
+            fatal: false,
+            context: vec![],
+            position: None,
+            source: None,
+            code: None,
         }
     }
+
+    pub fn custom(offset: usize, message: String) -> MatchError {
+        MatchError {
+            kind: MatchErrorKind::Custom,
+            offset,
+            message: format!("MatchError at offset {}: {}", offset, message),
+            fatal: false,
+            context: vec![],
+            position: None,
+            source: None,
+            code: None,
+        }
+    }
+
+    pub fn kind(&self) -> MatchErrorKind {
+        self.kind
+    }
+
+    // more input might make the match succeed, as opposed to the input being malformed
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, MatchErrorKind::Incomplete { .. })
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // fatal errors are not recovered from by alternatives or repetitions (see `parser::cut`)
+    pub fn is_fatal(&self) -> bool {
+        self.fatal
+    }
+
+    // errors that alternatives and repetitions pass on instead of backtracking: fatal ones,
+    // and running out of input when the stream is partial (more bytes may still arrive)
+    pub fn is_committed(&self, bs: &ByteStream) -> bool {
+        self.fatal || (bs.is_partial() && self.is_incomplete())
+    }
+
+    pub fn into_fatal(mut self) -> MatchError {
+        self.fatal = true;
+        self
+    }
+
+    // labels of the enclosing matchers, outermost first (see `Matcher::context`)
+    pub fn context(&self) -> &[String] {
+        &self.context
+    }
+
+    // 1-based line and column of the offset, set when the stream tracks lines
+    pub fn line(&self) -> Option<usize> {
+        self.position.map(|(line, _)| line)
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.position.map(|(_, col)| col)
+    }
+
+    pub fn with_position(mut self, line: usize, column: usize) -> MatchError {
+        self.position = Some((line, column));
+        self
+    }
+
+    // the underlying error (e.g. from `try_map`), returned by `Error::source`
+    pub fn with_source<E: Into<Box<dyn error::Error + Send + Sync>>>(mut self, source: E) -> MatchError {
+        self.source = Some(source.into());
+        self
+    }
+
+    // a copy for caches, without the source which can not be cloned
+    pub(crate) fn replay(&self) -> MatchError {
+        MatchError {
+            kind: self.kind,
+            offset: self.offset,
+            message: self.message.clone(),
+            fatal: self.fatal,
+            context: self.context.clone(),
+            position: self.position,
+            source: None,
+            code: self.code,
+        }
+    }
+
+    // stable identifier for metrics and programmatic handling, unlike the message wording:
+    // the code given to `with_code`, or one derived from the kind
+    pub fn code(&self) -> &'static str {
+        self.code.unwrap_or(match self.kind {
+            MatchErrorKind::Unexpected => "UNEXPECTED",
+            MatchErrorKind::NotFound => "NOT_FOUND",
+            MatchErrorKind::OverCapacity => "OVER_CAPACITY",
+            MatchErrorKind::Incomplete { .. } => "INCOMPLETE",
+            MatchErrorKind::Custom => "CUSTOM",
+        })
+    }
+
+    // the most specific code wins: a code already set by an inner matcher is kept
+    pub fn with_code(mut self, code: &'static str) -> MatchError {
+        self.code.get_or_insert(code);
+        self
+    }
+
+    // the same error at `offset`, e.g. moved from a nested stream into the outer one
+    pub(crate) fn with_offset(mut self, offset: usize) -> MatchError {
+        let prefix = format!("MatchError at offset {}", self.offset);
+        if let Some(rest) = self.message.strip_prefix(&prefix) {
+            self.message = format!("MatchError at offset {}{}", offset, rest);
+        }
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_context(mut self, label: &str) -> MatchError {
+        self.context.insert(0, label.to_string());
+        self
+    }
 }
 
 impl fmt::Display for MatchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(&self.message)
+        for (i, label) in self.context.iter().enumerate() {
+            let sep = if i + 1 < self.context.len() { " > " } else { ": " };
+            write!(f, "in {}{}", label, sep)?;
+        }
+        f.write_str(&self.message)?;
+        if let Some((line, col)) = self.position {
+            write!(f, " (line {}, column {})", line, col)?;
+        }
+        Ok(())
+    }
+}
+
+// running out of input maps to UnexpectedEof, anything else to InvalidData
+impl From<MatchError> for io::Error {
+    fn from(e: MatchError) -> Self {
+        let kind = if e.is_incomplete() {
+            io::ErrorKind::UnexpectedEof
+        } else {
+            io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl MatchError {
+    // the MatchError wrapped by `From<MatchError> for io::Error`, other errors are given back
+    pub fn try_from_io(e: io::Error) -> Result<MatchError, io::Error> {
+        if e.get_ref().map(|inner| inner.is::<MatchError>()).unwrap_or(false) {
+            let inner = e.into_inner().unwrap();
+            Ok(*inner.downcast::<MatchError>().unwrap())
+        } else {
+            Err(e)
+        }
+    }
+
+    pub fn from_io_ref(e: &io::Error) -> Option<&MatchError> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<MatchError>())
     }
 }
 
@@ -161,7 +875,7 @@ impl error::Error for MatchError {
     fn description(&self) -> &str {
         "MatchError"
     }
-    fn cause(&self) -> Option<&dyn error::Error> {
-        None
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_ref().map(|e| &**e as &(dyn error::Error + 'static))
     }
 }
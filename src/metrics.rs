@@ -0,0 +1,120 @@
+// Hooks for counting parse outcomes (e.g. into Prometheus counters) without wrapping
+// every call site: wrap the matcher once with `metered` and share the sink.
+use crate::matcher::{MatchError, Matcher};
+use crate::stream::ByteStream;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// methods take &self as matchers do, sinks are expected to use atomics or cells
+pub trait Metrics {
+    // `bytes` consumed by the successful match of a `kind` of message
+    fn on_parse_ok(&self, _kind: &'static str, _bytes: usize, _duration: Duration) {}
+
+    // `MatchError::code` of the failure
+    fn on_parse_err(&self, _code: &'static str) {}
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+impl<R: Metrics + ?Sized> Metrics for &R {
+    fn on_parse_ok(&self, kind: &'static str, bytes: usize, duration: Duration) {
+        (**self).on_parse_ok(kind, bytes, duration)
+    }
+
+    fn on_parse_err(&self, code: &'static str) {
+        (**self).on_parse_err(code)
+    }
+}
+
+impl<R: Metrics + ?Sized> Metrics for Rc<R> {
+    fn on_parse_ok(&self, kind: &'static str, bytes: usize, duration: Duration) {
+        (**self).on_parse_ok(kind, bytes, duration)
+    }
+
+    fn on_parse_err(&self, code: &'static str) {
+        (**self).on_parse_err(code)
+    }
+}
+
+impl<R: Metrics + ?Sized> Metrics for Arc<R> {
+    fn on_parse_ok(&self, kind: &'static str, bytes: usize, duration: Duration) {
+        (**self).on_parse_ok(kind, bytes, duration)
+    }
+
+    fn on_parse_err(&self, code: &'static str) {
+        (**self).on_parse_err(code)
+    }
+}
+
+pub struct Metered<M, R> {
+    inner: M,
+    kind: &'static str,
+    metrics: R,
+}
+
+impl<T, M: Matcher<T>, R: Metrics> Matcher<T> for Metered<M, R> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let pos = bs.pos();
+        let start = Instant::now();
+        match self.inner.do_match(bs) {
+            Ok(t) => {
+                self.metrics.on_parse_ok(self.kind, bs.pos() - pos, start.elapsed());
+                Ok(t)
+            }
+            Err(e) => {
+                self.metrics.on_parse_err(e.code());
+                Err(e)
+            }
+        }
+    }
+}
+
+pub fn metered<T, M: Matcher<T>, R: Metrics>(kind: &'static str, metrics: R, inner: M) -> Metered<M, R> {
+    Metered { inner, kind, metrics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{bytes, get_u8, Applicator};
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Counters {
+        ok: RefCell<Vec<(&'static str, usize)>>,
+        err: RefCell<Vec<&'static str>>,
+    }
+
+    impl Metrics for Counters {
+        fn on_parse_ok(&self, kind: &'static str, bytes: usize, _duration: Duration) {
+            self.ok.borrow_mut().push((kind, bytes));
+        }
+
+        fn on_parse_err(&self, code: &'static str) {
+            self.err.borrow_mut().push(code);
+        }
+    }
+
+    #[test]
+    fn counts_outcomes() {
+        let counters = Rc::new(Counters::default());
+        let m = metered("frame", counters.clone(), get_u8().then_with(|n| bytes(*n as usize)));
+
+        let mut bs = ByteStream::wrap(vec![2, 10, 20, 3, 30]);
+        assert_eq!(m.do_match(&mut bs).unwrap(), (2, vec![10, 20]));
+        assert!(m.do_match(&mut bs).is_err());
+
+        assert_eq!(*counters.ok.borrow(), vec![("frame", 3)]);
+        assert_eq!(*counters.err.borrow(), vec!["INCOMPLETE"]);
+    }
+
+    #[test]
+    fn no_metrics() {
+        let mut bs = ByteStream::wrap(vec![1]);
+        assert_eq!(bs.apply(metered("byte", NoMetrics, get_u8())).unwrap(), 1);
+    }
+}
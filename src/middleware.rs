@@ -0,0 +1,141 @@
+use crate::http::{Request, Response};
+
+pub trait Middleware {
+    // returning a response short-circuits the chain, the handler is not invoked
+    fn on_request(&mut self, _req: &mut Request) -> Option<Response> {
+        None
+    }
+
+    fn on_response(&mut self, _res: &mut Response) {}
+}
+
+// requests pass through layers in insertion order, responses in reverse order
+#[derive(Default)]
+pub struct MiddlewareChain {
+    layers: Vec<Box<dyn Middleware>>,
+}
+
+impl MiddlewareChain {
+    pub fn new() -> MiddlewareChain {
+        MiddlewareChain { layers: vec![] }
+    }
+
+    pub fn with<M: Middleware + 'static>(mut self, m: M) -> MiddlewareChain {
+        self.layers.push(Box::new(m));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn handle<F: FnOnce(&Request) -> Response>(&mut self, mut req: Request, handler: F) -> Response {
+        let mut entered = 0;
+        let mut early = None;
+        for layer in self.layers.iter_mut() {
+            entered += 1;
+            if let Some(res) = layer.on_request(&mut req) {
+                early = Some(res);
+                break;
+            }
+        }
+
+        let mut res = match early {
+            Some(res) => res,
+            None => handler(&req),
+        };
+        for layer in self.layers[..entered].iter_mut().rev() {
+            layer.on_response(&mut res);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Header;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn response(code: u16, message: &str) -> Response {
+        Response {
+            protocol: "HTTP/1.1".to_string(),
+            code,
+            message: message.to_string(),
+            headers: vec![],
+            content: vec![],
+        }
+    }
+
+    struct Log(Rc<RefCell<Vec<String>>>, &'static str);
+
+    impl Middleware for Log {
+        fn on_request(&mut self, req: &mut Request) -> Option<Response> {
+            self.0.borrow_mut().push(format!("{} > {}", self.1, req.path));
+            None
+        }
+
+        fn on_response(&mut self, res: &mut Response) {
+            self.0.borrow_mut().push(format!("{} < {}", self.1, res.code));
+        }
+    }
+
+    struct Auth;
+
+    impl Middleware for Auth {
+        fn on_request(&mut self, req: &mut Request) -> Option<Response> {
+            if req.headers.iter().any(|h| h.name == "Authorization") {
+                None
+            } else {
+                Some(response(401, "Unauthorized"))
+            }
+        }
+    }
+
+    #[test]
+    fn chain_order() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut chain = MiddlewareChain::new()
+            .with(Log(log.clone(), "a"))
+            .with(Log(log.clone(), "b"));
+
+        let req = Request {
+            path: "/x".to_string(),
+            ..Default::default()
+        };
+        let res = chain.handle(req, |_| response(200, "OK"));
+
+        assert_eq!(res.code, 200);
+        assert_eq!(*log.borrow(), vec!["a > /x", "b > /x", "b < 200", "a < 200"]);
+    }
+
+    #[test]
+    fn chain_short_circuit() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let mut chain = MiddlewareChain::new()
+            .with(Log(log.clone(), "a"))
+            .with(Auth)
+            .with(Log(log.clone(), "b"));
+
+        let res = chain.handle(Request::default(), |_| response(200, "OK"));
+        assert_eq!(res.code, 401);
+        assert_eq!(*log.borrow(), vec!["a > ", "a < 401"]);
+
+        log.borrow_mut().clear();
+        let req = Request {
+            headers: vec![Header {
+                name: "Authorization".to_string(),
+                value: "token".to_string(),
+            }],
+            ..Default::default()
+        };
+        let res = chain.handle(req, |_| response(204, "No Content"));
+        assert_eq!(res.code, 204);
+        assert_eq!(log.borrow().len(), 4);
+    }
+}
@@ -0,0 +1,375 @@
+// Modbus TCP: the MBAP header and the request PDUs of the common function codes, with
+// encoders for the matching responses (Modbus Application Protocol V1.1b3).
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{bytes, get_u16, get_u8, match_value, rest};
+use crate::stream::ByteStream;
+use std::convert::TryFrom;
+
+pub const HEADER_LEN: usize = 7;
+// unit id and at most 253 bytes of PDU
+pub const MAX_LENGTH: u16 = 254;
+// per read request and response, so that the data fits the PDU
+pub const MAX_BITS: usize = 2000;
+pub const MAX_REGISTERS: usize = 125;
+// per write request, where the address, quantity and byte count take part of the PDU
+pub const MAX_WRITE_BITS: usize = 0x07B0;
+pub const MAX_WRITE_REGISTERS: usize = 0x007B;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0F;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+pub const ILLEGAL_FUNCTION: u8 = 0x01;
+pub const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+pub const ILLEGAL_DATA_VALUE: u8 = 0x03;
+pub const SERVER_DEVICE_FAILURE: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub transaction: u16,
+    // always 0 for Modbus
+    pub protocol: u16,
+    // bytes following the length field: the unit id and the PDU
+    pub length: u16,
+    pub unit: u8,
+}
+
+impl Header {
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.transaction.to_be_bytes());
+        out.extend_from_slice(&self.protocol.to_be_bytes());
+        out.extend_from_slice(&self.length.to_be_bytes());
+        out.push(self.unit);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    // READ_COILS, READ_DISCRETE_INPUTS, READ_HOLDING_REGISTERS or READ_INPUT_REGISTERS
+    Read { function: u8, address: u16, quantity: u16 },
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultipleCoils { address: u16, values: Vec<bool> },
+    WriteMultipleRegisters { address: u16, values: Vec<u16> },
+    // left for the caller, usually answered with ILLEGAL_FUNCTION
+    Other { function: u8, data: Vec<u8> },
+}
+
+impl Request {
+    pub fn function(&self) -> u8 {
+        match self {
+            Request::Read { function, .. } | Request::Other { function, .. } => *function,
+            Request::WriteSingleCoil { .. } => WRITE_SINGLE_COIL,
+            Request::WriteSingleRegister { .. } => WRITE_SINGLE_REGISTER,
+            Request::WriteMultipleCoils { .. } => WRITE_MULTIPLE_COILS,
+            Request::WriteMultipleRegisters { .. } => WRITE_MULTIPLE_REGISTERS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    // READ_COILS or READ_DISCRETE_INPUTS, packed LSB first into bytes
+    Bits { function: u8, values: Vec<bool> },
+    // READ_HOLDING_REGISTERS or READ_INPUT_REGISTERS
+    Registers { function: u8, values: Vec<u16> },
+    // echoes of the write requests
+    WriteSingleCoil { address: u16, value: bool },
+    WriteSingleRegister { address: u16, value: u16 },
+    WriteMultiple { function: u8, address: u16, quantity: u16 },
+    Exception { function: u8, code: u8 },
+}
+
+impl Response {
+    pub fn exception(request: &Request, code: u8) -> Response {
+        Response::Exception { function: request.function(), code }
+    }
+
+    // the echo of a successful write, None for reads and other functions (and for writes
+    // of more values than a quantity field holds)
+    pub fn write_echo(request: &Request) -> Option<Response> {
+        match request {
+            Request::WriteSingleCoil { address, value } => Some(Response::WriteSingleCoil { address: *address, value: *value }),
+            Request::WriteSingleRegister { address, value } => Some(Response::WriteSingleRegister { address: *address, value: *value }),
+            Request::WriteMultipleCoils { address, values } => Some(Response::WriteMultiple {
+                function: WRITE_MULTIPLE_COILS,
+                address: *address,
+                quantity: u16::try_from(values.len()).ok()?,
+            }),
+            Request::WriteMultipleRegisters { address, values } => Some(Response::WriteMultiple {
+                function: WRITE_MULTIPLE_REGISTERS,
+                address: *address,
+                quantity: u16::try_from(values.len()).ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    // fails (writing nothing) for more than MAX_BITS or MAX_REGISTERS values, reported at the function code
+    pub fn encode_pdu(&self, out: &mut Vec<u8>) -> Result<(), MatchError> {
+        match self {
+            Response::Bits { values, .. } if values.len() > MAX_BITS => return Err(too_many(values.len(), MAX_BITS)),
+            Response::Registers { values, .. } if values.len() > MAX_REGISTERS => {
+                return Err(too_many(values.len(), MAX_REGISTERS))
+            }
+            _ => (),
+        }
+        match self {
+            Response::Bits { function, values } => {
+                out.push(*function);
+                out.push(values.len().div_ceil(8) as u8);
+                out.extend(values.chunks(8).map(|chunk| {
+                    chunk.iter().enumerate().fold(0u8, |acc, (i, bit)| acc | (*bit as u8) << i)
+                }));
+            }
+            Response::Registers { function, values } => {
+                out.push(*function);
+                out.push((values.len() * 2) as u8);
+                for value in values {
+                    out.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Response::WriteSingleCoil { address, value } => {
+                out.push(WRITE_SINGLE_COIL);
+                out.extend_from_slice(&address.to_be_bytes());
+                out.extend_from_slice(&coil(*value).to_be_bytes());
+            }
+            Response::WriteSingleRegister { address, value } => {
+                out.push(WRITE_SINGLE_REGISTER);
+                out.extend_from_slice(&address.to_be_bytes());
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Response::WriteMultiple { function, address, quantity } => {
+                out.push(*function);
+                out.extend_from_slice(&address.to_be_bytes());
+                out.extend_from_slice(&quantity.to_be_bytes());
+            }
+            Response::Exception { function, code } => {
+                out.push(function | 0x80);
+                out.push(*code);
+            }
+        }
+        Ok(())
+    }
+
+    // the whole ADU, answering the request that came with `request`
+    pub fn encode_into(&self, request: &Header, out: &mut Vec<u8>) -> Result<(), MatchError> {
+        let start = out.len();
+        Header { length: 0, ..*request }.encode_into(out);
+        if let Err(e) = self.encode_pdu(out) {
+            out.truncate(start);
+            let offset = HEADER_LEN + e.offset();
+            return Err(e.with_offset(offset));
+        }
+        // at most MAX_LENGTH given the value limits
+        let length = (out.len() - start - HEADER_LEN + 1) as u16;
+        out[start + 4..start + 6].copy_from_slice(&length.to_be_bytes());
+        Ok(())
+    }
+}
+
+fn too_many(len: usize, max: usize) -> MatchError {
+    MatchError::custom(0, format!("{} values, at most {} fit a response", len, max))
+}
+
+fn coil(value: bool) -> u16 {
+    if value {
+        0xFF00
+    } else {
+        0x0000
+    }
+}
+
+// nothing is consumed on failure
+pub fn header() -> impl Matcher<Header> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = (get_u16(), get_u16(), get_u16(), get_u8())
+            .do_match(bs)
+            .and_then(|(transaction, protocol, length, unit)| {
+                if protocol != 0 {
+                    return Err(MatchError::unexpected(pos + 2, format!("protocol {}", protocol), "protocol 0".to_string()));
+                }
+                if !(2..=MAX_LENGTH).contains(&length) {
+                    return Err(MatchError::unexpected(
+                        pos + 4,
+                        format!("length {}", length),
+                        format!("length 2 to {}", MAX_LENGTH),
+                    ));
+                }
+                Ok(Header { transaction, protocol, length, unit })
+            });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// a request PDU spanning the whole stream; quantities and byte counts are checked against
+// the limits of the spec, so a failure here is worth an ILLEGAL_DATA_VALUE exception
+pub fn request_pdu() -> impl Matcher<Request> {
+    move |bs: &mut ByteStream| {
+        let function = get_u8().do_match(bs)?;
+        let request = match function {
+            READ_COILS | READ_DISCRETE_INPUTS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                let pos = bs.pos();
+                let (address, quantity) = (get_u16(), get_u16()).do_match(bs)?;
+                let max = if function <= READ_DISCRETE_INPUTS { MAX_BITS as u16 } else { MAX_REGISTERS as u16 };
+                check_quantity(pos + 2, quantity, max)?;
+                Request::Read { function, address, quantity }
+            }
+            WRITE_SINGLE_COIL => {
+                let pos = bs.pos();
+                let (address, value) = (get_u16(), get_u16()).do_match(bs)?;
+                let value = match value {
+                    0xFF00 => true,
+                    0x0000 => false,
+                    _ => return Err(MatchError::unexpected(pos + 2, format!("{:#06x}", value), "0xff00 or 0x0000".to_string())),
+                };
+                Request::WriteSingleCoil { address, value }
+            }
+            WRITE_SINGLE_REGISTER => {
+                let (address, value) = (get_u16(), get_u16()).do_match(bs)?;
+                Request::WriteSingleRegister { address, value }
+            }
+            WRITE_MULTIPLE_COILS => {
+                let pos = bs.pos();
+                let (address, quantity, count) = (get_u16(), get_u16(), get_u8()).do_match(bs)?;
+                check_quantity(pos + 2, quantity, MAX_WRITE_BITS as u16)?;
+                check_count(pos + 4, count, (quantity as usize).div_ceil(8))?;
+                let data = bytes(count as usize).do_match(bs)?;
+                let values = (0..quantity as usize).map(|i| data[i / 8] & (1 << (i % 8)) != 0).collect();
+                Request::WriteMultipleCoils { address, values }
+            }
+            WRITE_MULTIPLE_REGISTERS => {
+                let pos = bs.pos();
+                let (address, quantity, count) = (get_u16(), get_u16(), get_u8()).do_match(bs)?;
+                check_quantity(pos + 2, quantity, MAX_WRITE_REGISTERS as u16)?;
+                check_count(pos + 4, count, quantity as usize * 2)?;
+                let mut values = Vec::with_capacity(quantity as usize);
+                for _ in 0..quantity {
+                    values.push(get_u16().do_match(bs)?);
+                }
+                Request::WriteMultipleRegisters { address, values }
+            }
+            _ => Request::Other { function, data: rest().do_match(bs)? },
+        };
+        Ok(request)
+    }
+}
+
+fn check_quantity(pos: usize, quantity: u16, max: u16) -> Result<(), MatchError> {
+    if (1..=max).contains(&quantity) {
+        Ok(())
+    } else {
+        Err(MatchError::unexpected(pos, format!("quantity {}", quantity), format!("quantity 1 to {}", max)))
+    }
+}
+
+fn check_count(pos: usize, count: u8, expected: usize) -> Result<(), MatchError> {
+    if count as usize == expected {
+        Ok(())
+    } else {
+        Err(MatchError::unexpected(pos, format!("byte count {}", count), format!("byte count {}", expected)))
+    }
+}
+
+// a whole request ADU
+pub fn request() -> impl Matcher<(Header, Request)> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = header().do_match(bs).and_then(|header| {
+            let start = bs.pos();
+            let pdu = bytes(header.length as usize - 1).do_match(bs)?;
+            let request = match_value(&request_pdu(), pdu, start)?;
+            Ok((header, request))
+        });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn read_registers() {
+        let mut bs = ByteStream::wrap(vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x11, 0x03, 0x00, 0x6B, 0x00, 0x03]);
+        let (header, req) = bs.apply(request()).unwrap();
+        assert_eq!(header, Header { transaction: 1, protocol: 0, length: 6, unit: 0x11 });
+        assert_eq!(req, Request::Read { function: READ_HOLDING_REGISTERS, address: 0x6B, quantity: 3 });
+
+        let mut out = vec![];
+        Response::Registers { function: READ_HOLDING_REGISTERS, values: vec![0x022B, 0x0000, 0x0064] }.encode_into(&header, &mut out).unwrap();
+        assert_eq!(out, vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x09, 0x11, 0x03, 0x06, 0x02, 0x2B, 0x00, 0x00, 0x00, 0x64]);
+    }
+
+    #[test]
+    fn write_coils() {
+        // 10 coils starting at 19: 1,0,1,1,0,0,1,1 / 1,0
+        let mut bs = ByteStream::wrap(vec![0x00, 0x02, 0x00, 0x00, 0x00, 0x09, 0x01, 0x0F, 0x00, 0x13, 0x00, 0x0A, 0x02, 0xCD, 0x01]);
+        let (header, req) = bs.apply(request()).unwrap();
+        let values = vec![true, false, true, true, false, false, true, true, true, false];
+        assert_eq!(req, Request::WriteMultipleCoils { address: 0x13, values: values.clone() });
+
+        let mut out = vec![];
+        Response::write_echo(&req).unwrap().encode_into(&header, &mut out).unwrap();
+        assert_eq!(&out[4..], &[0x00, 0x06, 0x01, 0x0F, 0x00, 0x13, 0x00, 0x0A]);
+
+        let mut pdu = vec![];
+        Response::Bits { function: READ_COILS, values }.encode_pdu(&mut pdu).unwrap();
+        assert_eq!(pdu, vec![0x01, 0x02, 0xCD, 0x01]);
+    }
+
+    #[test]
+    fn exceptions_and_errors() {
+        let mut bs = ByteStream::wrap(vec![0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x01, 0x2B, 0x0E]);
+        let (header, req) = bs.apply(request()).unwrap();
+        assert_eq!(req, Request::Other { function: 0x2B, data: vec![0x0E] });
+        let mut out = vec![];
+        Response::exception(&req, ILLEGAL_FUNCTION).encode_into(&header, &mut out).unwrap();
+        assert_eq!(&out[4..], &[0x00, 0x03, 0x01, 0xAB, 0x01]);
+
+        // quantity over 125 registers
+        let mut bs = ByteStream::wrap(vec![0x00, 0x04, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x7E]);
+        assert_eq!(bs.apply(request()).unwrap_err().offset(), 10);
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(vec![0x00, 0x04, 0x00, 0x01, 0x00, 0x06, 0x01]);
+        assert_eq!(bs.apply(super::header()).unwrap_err().offset(), 2);
+        let mut bs = ByteStream::wrap(vec![0x00, 0x04, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03]);
+        assert!(bs.apply(request()).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn response_limits() {
+        let header = Header { transaction: 5, protocol: 0, length: 6, unit: 1 };
+        let mut out = vec![];
+        let bits = Response::Bits { function: READ_COILS, values: vec![true; MAX_BITS] };
+        bits.encode_into(&header, &mut out).unwrap();
+        assert_eq!(out.len(), HEADER_LEN + 2 + MAX_BITS / 8);
+        assert!(out.len() - 6 <= MAX_LENGTH as usize);
+
+        let mut out = vec![];
+        let bits = Response::Bits { function: READ_COILS, values: vec![true; MAX_BITS + 1] };
+        assert_eq!(bits.encode_into(&header, &mut out).unwrap_err().offset(), HEADER_LEN);
+        let registers = Response::Registers { function: READ_HOLDING_REGISTERS, values: vec![0; MAX_REGISTERS + 1] };
+        assert!(registers.encode_into(&header, &mut out).is_err());
+        assert_eq!(registers.encode_pdu(&mut out).unwrap_err().offset(), 0);
+        assert!(out.is_empty());
+
+        let req = Request::WriteMultipleRegisters { address: 0, values: vec![0; 70_000] };
+        assert_eq!(Response::write_echo(&req), None);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::http::Header;
+use crate::textmsg::header_parser;
+use crate::parser::{repeat, Applicator, MatchError};
+use crate::scanner::DelimiterScanner;
+use crate::stream::ByteStream;
+
+const MAX_HEADERS_LEN: usize = 8 * 1024;
+
+pub trait PartHandler {
+    fn on_part_headers(&mut self, headers: &[Header]);
+
+    fn on_part_chunk(&mut self, chunk: &[u8]);
+
+    fn on_part_end(&mut self);
+}
+
+// boundary parameter of a multipart Content-Type header value
+pub fn boundary(content_type: &str) -> Option<String> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("boundary") => {
+                    Some(v.trim().trim_matches('"').to_string())
+                }
+                _ => None,
+            }
+        })
+        .find(|b| !b.is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Body,
+    Done,
+}
+
+// incremental multipart body reader: feed it bytes as they arrive, part data is
+// passed to the handler as soon as it can no longer be the start of a delimiter
+pub struct MultipartReader {
+    delimiter: DelimiterScanner,
+    headers_end: DelimiterScanner,
+    buf: Vec<u8>,
+    state: State,
+    consumed: usize,
+}
+
+impl MultipartReader {
+    pub fn new(boundary: &str) -> MultipartReader {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        MultipartReader {
+            delimiter: DelimiterScanner::new(&delimiter),
+            headers_end: DelimiterScanner::new(b"\r\n\r\n"),
+            // the first delimiter is not preceded by CRLF
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+            consumed: 0,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn feed<H: PartHandler>(&mut self, data: &[u8], handler: &mut H) -> Result<(), MatchError> {
+        if self.state == State::Done {
+            return Ok(());
+        }
+        self.buf.extend_from_slice(data);
+        loop {
+            match self.state {
+                State::Preamble => match self.delimiter.find(&self.buf) {
+                    Some(idx) => {
+                        self.consume(idx + self.delimiter.pattern().len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        self.consume(self.safe_len());
+                        return Ok(());
+                    }
+                },
+                State::Delimiter => {
+                    if self.buf.len() < 2 {
+                        return Ok(());
+                    }
+                    if self.buf.starts_with(b"--") {
+                        let len = self.buf.len();
+                        self.consume(len);
+                        self.state = State::Done;
+                        return Ok(());
+                    }
+                    if !self.buf.starts_with(b"\r\n") {
+                        return Err(self.unexpected("CRLF or '--' after boundary"));
+                    }
+                    self.consume(2);
+                    self.state = State::Headers;
+                }
+                State::Headers => {
+                    if self.buf.starts_with(b"\r\n") {
+                        self.consume(2);
+                        handler.on_part_headers(&[]);
+                        self.state = State::Body;
+                        continue;
+                    }
+                    match self.headers_end.find(&self.buf) {
+                        Some(idx) => {
+                            let mut bs = ByteStream::wrap(self.buf[..idx + 2].to_vec());
+                            let headers = bs.apply(repeat(header_parser()))?;
+                            if bs.pos() != idx + 2 {
+                                return Err(self.unexpected("part header"));
+                            }
+                            self.consume(idx + 4);
+                            handler.on_part_headers(&headers);
+                            self.state = State::Body;
+                        }
+                        None if self.buf.len() > MAX_HEADERS_LEN => {
+                            return Err(MatchError::over_capacity(self.offset(), MAX_HEADERS_LEN, self.buf.len()));
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                State::Body => match self.delimiter.find(&self.buf) {
+                    Some(idx) => {
+                        if idx > 0 {
+                            handler.on_part_chunk(&self.buf[..idx]);
+                        }
+                        handler.on_part_end();
+                        self.consume(idx + self.delimiter.pattern().len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        let len = self.safe_len();
+                        if len > 0 {
+                            handler.on_part_chunk(&self.buf[..len]);
+                            self.consume(len);
+                        }
+                        return Ok(());
+                    }
+                },
+                State::Done => return Ok(()),
+            }
+        }
+    }
+
+    // bytes that can not be a prefix of a delimiter split across feeds
+    fn safe_len(&self) -> usize {
+        self.delimiter.safe_len(&self.buf)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.buf.drain(..n);
+        self.delimiter.consumed(n);
+        self.headers_end.consumed(n);
+        self.consumed += n;
+    }
+
+    // position in the fed input, not counting the leading CRLF added for the first delimiter
+    fn offset(&self) -> usize {
+        self.consumed.saturating_sub(2)
+    }
+
+    fn unexpected(&self, expected: &str) -> MatchError {
+        let got = self.buf.iter().take(8).map(|b| *b as char).collect::<String>();
+        MatchError::unexpected(self.offset(), got, expected.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Part = (Vec<(String, String)>, Vec<u8>);
+
+    #[derive(Default)]
+    struct Parts {
+        parts: Vec<Part>,
+        ended: usize,
+    }
+
+    impl PartHandler for Parts {
+        fn on_part_headers(&mut self, headers: &[Header]) {
+            let headers = headers.iter().map(|h| (h.name.clone(), h.value.clone())).collect();
+            self.parts.push((headers, vec![]));
+        }
+
+        fn on_part_chunk(&mut self, chunk: &[u8]) {
+            self.parts.last_mut().unwrap().1.extend_from_slice(chunk);
+        }
+
+        fn on_part_end(&mut self) {
+            self.ended += 1;
+        }
+    }
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue a\r\n--XyZ\r\nContent-Type: text/plain\r\n\r\nline 1\r\nline 2 --XyZ\r\n\r\n--XyZ\r\n\r\n\r\n--XyZ--\r\nepilogue";
+
+    fn check(parts: &Parts) {
+        assert_eq!(parts.ended, 3);
+        assert_eq!(parts.parts.len(), 3);
+        assert_eq!(parts.parts[0].0, vec![("Content-Disposition".to_string(), "form-data; name=\"a\"".to_string())]);
+        assert_eq!(parts.parts[0].1, b"value a");
+        assert_eq!(parts.parts[1].0, vec![("Content-Type".to_string(), "text/plain".to_string())]);
+        assert_eq!(parts.parts[1].1, b"line 1\r\nline 2 --XyZ\r\n");
+        assert!(parts.parts[2].0.is_empty());
+        assert!(parts.parts[2].1.is_empty());
+    }
+
+    #[test]
+    fn whole_body() {
+        let mut reader = MultipartReader::new("XyZ");
+        let mut parts = Parts::default();
+        reader.feed(BODY, &mut parts).unwrap();
+        assert!(reader.is_done());
+        check(&parts);
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let mut reader = MultipartReader::new("XyZ");
+        let mut parts = Parts::default();
+        for b in BODY {
+            reader.feed(&[*b], &mut parts).unwrap();
+        }
+        assert!(reader.is_done());
+        check(&parts);
+    }
+
+    #[test]
+    fn malformed_delimiter() {
+        let mut reader = MultipartReader::new("XyZ");
+        let mut parts = Parts::default();
+        let err = reader.feed(b"--XyZ!!\r\n", &mut parts).unwrap_err();
+        assert_eq!(err.offset(), 5);
+    }
+
+    #[test]
+    fn split_across_feeds() {
+        let mut reader = MultipartReader::new("XyZ");
+        let mut parts = Parts::default();
+        for chunk in BODY.chunks(7) {
+            reader.feed(chunk, &mut parts).unwrap();
+        }
+        assert!(reader.is_done());
+        check(&parts);
+    }
+
+    #[test]
+    fn content_type_boundary() {
+        assert_eq!(boundary("multipart/form-data; boundary=----abc"), Some("----abc".to_string()));
+        assert_eq!(boundary("multipart/mixed; charset=utf-8; Boundary=\"a b\""), Some("a b".to_string()));
+        assert_eq!(boundary("multipart/mixed"), None);
+    }
+}
@@ -0,0 +1,192 @@
+// NTP (RFC 5905) and SNTP (RFC 4330) packets: the 48-byte header, extension fields and
+// MAC that may follow it are left unread.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{bit_fields, get_array, get_i8, get_u32, get_u64, get_u8};
+use crate::stream::ByteStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const PACKET_LEN: usize = 48;
+
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+pub const MODE_BROADCAST: u8 = 5;
+
+// seconds from 1900-01-01 (era 0) to the Unix epoch
+const UNIX_OFFSET: u64 = 2_208_988_800;
+
+// 32.32 fixed point seconds since 1900, zero means "not set"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    pub fn seconds(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    pub fn fraction(self) -> u32 {
+        self.0 as u32
+    }
+
+    // times before the Unix epoch are not supported, after 2036 the seconds wrap into era 1
+    pub fn from_system_time(time: SystemTime) -> Option<Timestamp> {
+        let since = time.duration_since(UNIX_EPOCH).ok()?;
+        let seconds = (since.as_secs() + UNIX_OFFSET) as u32;
+        let fraction = ((since.subsec_nanos() as u64) << 32) / 1_000_000_000;
+        Some(Timestamp(((seconds as u64) << 32) | fraction))
+    }
+
+    // RFC 4330, section 3: with the top bit clear the seconds are counted in era 1,
+    // which starts in 2036, so this keeps working across the rollover
+    pub fn to_system_time(self) -> Option<SystemTime> {
+        if self.0 == 0 {
+            return None;
+        }
+        let seconds = self.seconds() as u64;
+        let since_1900 = if seconds & 0x8000_0000 == 0 { seconds + (1 << 32) } else { seconds };
+        let nanos = ((self.fraction() as u64 * 1_000_000_000) >> 32) as u32;
+        UNIX_EPOCH.checked_add(Duration::new(since_1900.checked_sub(UNIX_OFFSET)?, nanos))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub leap: u8,
+    pub version: u8,
+    pub mode: u8,
+    pub stratum: u8,
+    // log2 seconds
+    pub poll: i8,
+    pub precision: i8,
+    // 16.16 fixed point seconds
+    pub root_delay: u32,
+    pub root_dispersion: u32,
+    // an ASCII code for stratum 0 and 1 (e.g. "GPS\0"), an address hash above that
+    pub reference_id: [u8; 4],
+    pub reference: Timestamp,
+    pub origin: Timestamp,
+    pub receive: Timestamp,
+    pub transmit: Timestamp,
+}
+
+impl Packet {
+    // client request in SNTP style: everything zero but the version, mode and transmit time
+    pub fn request(transmit: Timestamp) -> Packet {
+        Packet {
+            leap: 0,
+            version: 4,
+            mode: MODE_CLIENT,
+            stratum: 0,
+            poll: 0,
+            precision: 0,
+            root_delay: 0,
+            root_dispersion: 0,
+            reference_id: [0; 4],
+            reference: Timestamp(0),
+            origin: Timestamp(0),
+            receive: Timestamp(0),
+            transmit,
+        }
+    }
+
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push((self.leap & 0b11) << 6 | (self.version & 0b111) << 3 | (self.mode & 0b111));
+        out.push(self.stratum);
+        out.push(self.poll as u8);
+        out.push(self.precision as u8);
+        out.extend_from_slice(&self.root_delay.to_be_bytes());
+        out.extend_from_slice(&self.root_dispersion.to_be_bytes());
+        out.extend_from_slice(&self.reference_id);
+        for ts in [self.reference, self.origin, self.receive, self.transmit] {
+            out.extend_from_slice(&ts.0.to_be_bytes());
+        }
+    }
+}
+
+// nothing is consumed on failure
+pub fn packet() -> impl Matcher<Packet> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = (bit_fields([2, 3, 3]), get_u8(), get_i8(), get_i8(), get_u32(), get_u32(), get_array::<4>())
+            .do_match(bs)
+            .and_then(|(flags, stratum, poll, precision, root_delay, root_dispersion, reference_id)| {
+                let [leap, version, mode] = flags;
+                if version == 0 {
+                    return Err(MatchError::unexpected(pos, "version 0".to_string(), "NTP version 1 to 7".to_string()));
+                }
+                let ts = || get_u64().map(Timestamp);
+                let (reference, origin, receive, transmit) = (ts(), ts(), ts(), ts()).do_match(bs)?;
+                Ok(Packet {
+                    leap: leap as u8,
+                    version: version as u8,
+                    mode: mode as u8,
+                    stratum,
+                    poll,
+                    precision,
+                    root_delay,
+                    root_dispersion,
+                    reference_id,
+                    reference,
+                    origin,
+                    receive,
+                    transmit,
+                })
+            });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn roundtrip() {
+        let now = UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000);
+        let ts = Timestamp::from_system_time(now).unwrap();
+        assert_eq!(ts.seconds() as u64, 1_700_000_000 + UNIX_OFFSET);
+        assert_eq!(ts.fraction(), 1 << 30);
+        assert_eq!(ts.to_system_time(), Some(now));
+
+        let mut res = Packet::request(ts);
+        res.mode = MODE_SERVER;
+        res.stratum = 1;
+        res.poll = 6;
+        res.precision = -20;
+        res.root_dispersion = 0x0000_8000;
+        res.reference_id = *b"GPS\0";
+        res.origin = ts;
+        let mut out = vec![];
+        res.encode_into(&mut out);
+        assert_eq!(out.len(), PACKET_LEN);
+        assert_eq!(out[0], 0x24);
+
+        out.extend_from_slice(&[0; 20]);
+        let mut bs = ByteStream::wrap(out);
+        assert_eq!(bs.apply(packet()).unwrap(), res);
+        assert_eq!(bs.remaining(), 20);
+    }
+
+    #[test]
+    fn eras() {
+        assert_eq!(Timestamp(0).to_system_time(), None);
+        // 2036-02-07T06:28:16Z is second 0 of era 1
+        let rollover = UNIX_EPOCH + Duration::from_secs((1 << 32) - UNIX_OFFSET);
+        assert_eq!(Timestamp(1 << 32).to_system_time().map(|t| t > rollover), Some(true));
+        assert_eq!(Timestamp::from_system_time(rollover).map(|ts| ts.seconds()), Some(0));
+        assert!(Timestamp::from_system_time(UNIX_EPOCH - Duration::from_secs(1)).is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        let mut bs = ByteStream::wrap(vec![0x23; 47]);
+        assert!(bs.apply(packet()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+        let mut bs = ByteStream::wrap(vec![0x03; 48]);
+        assert_eq!(bs.apply(packet()).unwrap_err().offset(), 0);
+    }
+}
@@ -1,6 +1,9 @@
-pub use crate::matcher::{Matcher, MatchError, unit};
+pub use crate::matcher::{MatchErrorKind, Matcher, MatchError, memoized, stateful, unit};
+use crate::matcher::{expected_one_of, fails_at_entry, union};
 use crate::stream::ByteStream;
 use std::marker::PhantomData;
+use std::convert::TryInto;
+use std::str::FromStr;
 
 pub struct Save<M, T, U, F> {
     matcher: M,
@@ -20,6 +23,30 @@ where
     }
 }
 
+pub struct TrySave<M, T, U, F> {
+    matcher: M,
+    func: F,
+    phantom: PhantomData<(T, U)>,
+}
+
+impl<M, T, U, F> Matcher<T> for TrySave<M, T, U, F>
+where
+    M: Matcher<(T, U)>,
+    F: Fn(&mut T, U) -> Result<(), MatchError>,
+{
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let mark = bs.mark();
+        let (mut t, u) = self.matcher.do_match(bs)?;
+        match (self.func)(&mut t, u) {
+            Ok(()) => Ok(t),
+            Err(e) => {
+                bs.reset(mark);
+                Err(e)
+            }
+        }
+    }
+}
+
 pub struct Skip<M, T, U> {
     matcher: M,
     phantom: PhantomData<(T, U)>,
@@ -38,6 +65,9 @@ where
 pub trait ParserExt<T, U>: Sized {
     fn save<F: Fn(&mut T, U) + 'static>(self, f: F) -> Save<Self, T, U, F>;
 
+    // validates while storing: an error from `f` fails the match and resets the stream
+    fn try_save<F: Fn(&mut T, U) -> Result<(), MatchError> + 'static>(self, f: F) -> TrySave<Self, T, U, F>;
+
     fn skip(self) -> Skip<Self, T, U>;
 }
 
@@ -50,6 +80,14 @@ impl<M, T, U> ParserExt<T, U> for M where M: Matcher<(T, U)> + Sized {
         }
     }
 
+    fn try_save<F: Fn(&mut T, U) -> Result<(), MatchError> + 'static>(self, f: F) -> TrySave<Self, T, U, F> {
+        TrySave {
+            matcher: self,
+            func: f,
+            phantom: PhantomData::<(T, U)>,
+        }
+    }
+
     fn skip(self) -> Skip<Self, T, U> {
         Skip {
             matcher: self,
@@ -61,25 +99,59 @@ impl<M, T, U> ParserExt<T, U> for M where M: Matcher<(T, U)> + Sized {
 pub fn one(b: u8) -> impl Matcher<u8> {
     move |bs: &mut ByteStream| {
         let pos = bs.pos();
-        bs.next().filter(|x| *x == b).ok_or(MatchError::unexpected(
-            pos,
-            "EOF".to_string(),
-            format!("byte {}", b),
-        ))
+        match bs.next() {
+            Some(x) if x == b => Ok(x),
+            Some(x) => Err(MatchError::unexpected(pos, format!("byte {}", x), format!("byte {}", b))),
+            None => Err(MatchError::incomplete(pos, 1)),
+        }
     }
 }
 
 pub fn single(chr: char) -> impl Matcher<char> {
     move |bs: &mut ByteStream| {
         let pos = bs.pos();
-        bs.next()
-            .map(|b| b as char)
-            .filter(|c| *c == chr)
-            .ok_or(MatchError::unexpected(
-                pos,
-                "EOF".to_string(),
-                format!("char '{}'", chr),
-            ))
+        match bs.next().map(|b| b as char) {
+            Some(c) if c == chr => Ok(c),
+            Some(c) => Err(MatchError::unexpected(pos, c.to_string(), format!("char '{}'", chr))),
+            None => Err(MatchError::incomplete(pos, 1)),
+        }
+    }
+}
+
+// any byte of the set: `one_of(b"+-*/")`
+pub fn one_of<P: AsRef<[u8]> + 'static>(set: P) -> impl Matcher<u8> {
+    OneOf(set)
+}
+
+struct OneOf<P>(P);
+
+impl<P: AsRef<[u8]>> Matcher<u8> for OneOf<P> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<u8, MatchError> {
+        byte_where(bs, |b| self.0.as_ref().contains(&b), || format!("one of '{}'", String::from_utf8_lossy(self.0.as_ref())))
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        Some(self.0.as_ref().iter().map(|b| (*b as char).to_string()).collect())
+    }
+}
+
+// any byte not in the set: `none_of(b";,")`
+pub fn none_of<P: AsRef<[u8]> + 'static>(set: P) -> impl Matcher<u8> {
+    move |bs: &mut ByteStream| {
+        byte_where(bs, |b| !set.as_ref().contains(&b), || format!("none of '{}'", String::from_utf8_lossy(set.as_ref())))
+    }
+}
+
+// the stream is not advanced when the byte is rejected
+fn byte_where<F: Fn(u8) -> bool, X: Fn() -> String>(bs: &mut ByteStream, f: F, expected: X) -> Result<u8, MatchError> {
+    let pos = bs.pos();
+    match bs.as_ref().first() {
+        Some(&b) if f(b) => {
+            bs.advance(1);
+            Ok(b)
+        }
+        Some(&b) => Err(MatchError::unexpected(pos, (b as char).to_string(), expected())),
+        None => Err(MatchError::incomplete(pos, 1)),
     }
 }
 
@@ -89,6 +161,52 @@ pub fn repeat<T: 'static>(this: impl Matcher<T>) -> impl Matcher<Vec<T>> {
         loop {
             let mark = bs.mark();
             match this.do_match(bs) {
+                Err(e) if e.is_committed(bs) => return Err(e),
+                Err(_) => {
+                    bs.reset(mark);
+                    return Ok(acc);
+                }
+                Ok(t) => acc.push(t),
+            }
+        }
+    }
+}
+
+pub fn fold<T, A, I, F>(init: I, this: impl Matcher<T>, f: F) -> impl Matcher<A>
+where
+    I: Fn() -> A + 'static,
+    F: Fn(A, T) -> A + 'static,
+{
+    move |bs: &mut ByteStream| {
+        let mut acc = init();
+        loop {
+            let mark = bs.mark();
+            match this.do_match(bs) {
+                Err(e) if e.is_committed(bs) => return Err(e),
+                Err(_) => {
+                    bs.reset(mark);
+                    return Ok(acc);
+                }
+                Ok(t) => acc = f(acc, t),
+            }
+        }
+    }
+}
+
+pub fn many1<T: 'static>(this: impl Matcher<T>) -> impl Matcher<Vec<T>> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let mut acc: Vec<T> = match this.do_match(bs) {
+            Ok(t) => vec![t],
+            Err(e) => {
+                bs.reset(mark);
+                return Err(e);
+            }
+        };
+        loop {
+            let mark = bs.mark();
+            match this.do_match(bs) {
+                Err(e) if e.is_committed(bs) => return Err(e),
                 Err(_) => {
                     bs.reset(mark);
                     return Ok(acc);
@@ -99,6 +217,138 @@ pub fn repeat<T: 'static>(this: impl Matcher<T>) -> impl Matcher<Vec<T>> {
     }
 }
 
+pub fn repeat_bounded<T: 'static>(this: impl Matcher<T>, min: usize, max: usize) -> impl Matcher<Vec<T>> {
+    assert!(min <= max, "repeat_bounded: min {} is over max {}", min, max);
+    move |bs: &mut ByteStream| {
+        let start = bs.mark();
+        let mut acc: Vec<T> = vec![];
+        while acc.len() < max {
+            let mark = bs.mark();
+            match this.do_match(bs) {
+                Ok(t) => acc.push(t),
+                Err(e) if e.is_committed(bs) => return Err(e),
+                Err(_) => {
+                    bs.reset(mark);
+                    break;
+                }
+            }
+        }
+        if acc.len() < min {
+            let pos = bs.pos();
+            bs.reset(start);
+            return Err(MatchError::unexpected(
+                pos,
+                format!("{} matches", acc.len()),
+                format!("at least {} matches", min),
+            ));
+        }
+        Ok(acc)
+    }
+}
+
+pub fn repeat_n<T: 'static>(this: impl Matcher<T>, n: usize) -> impl Matcher<Vec<T>> {
+    repeat_bounded(this, n, n)
+}
+
+// items up to the first place `terminator` matches, which is tried first at every step
+// and consumed; fails (consuming nothing) with the item's error if it never matches
+pub fn repeat_until<T: 'static, U: 'static>(item: impl Matcher<T>, terminator: impl Matcher<U>) -> impl Matcher<(Vec<T>, U)> {
+    move |bs: &mut ByteStream| {
+        let start = bs.mark();
+        let mut acc: Vec<T> = vec![];
+        loop {
+            let mark = bs.mark();
+            match terminator.do_match(bs) {
+                Ok(u) => return Ok((acc, u)),
+                Err(e) if e.is_committed(bs) => {
+                    bs.reset(start);
+                    return Err(e);
+                }
+                Err(_) => bs.reset(mark),
+            }
+            match item.do_match(bs) {
+                Ok(t) => acc.push(t),
+                Err(e) => {
+                    bs.reset(start);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+fn separated<T, S>(
+    item: &impl Matcher<T>,
+    sep: &impl Matcher<S>,
+    bs: &mut ByteStream,
+) -> Result<Vec<T>, MatchError> {
+    let start = bs.mark();
+    let mut acc = match item.do_match(bs) {
+        Ok(t) => vec![t],
+        Err(e) => {
+            bs.reset(start);
+            return Err(e);
+        }
+    };
+    loop {
+        // trailing separator is left unconsumed
+        let mark = bs.mark();
+        match sep.do_match(bs).and_then(|_| item.do_match(bs)) {
+            Ok(t) => acc.push(t),
+            Err(e) if e.is_committed(bs) => return Err(e),
+            Err(_) => {
+                bs.reset(mark);
+                return Ok(acc);
+            }
+        }
+    }
+}
+
+pub fn separated_list<T: 'static, S>(item: impl Matcher<T>, sep: impl Matcher<S>) -> impl Matcher<Vec<T>> {
+    move |bs: &mut ByteStream| match separated(&item, &sep, bs) {
+        Err(e) if e.is_committed(bs) => Err(e),
+        r => Ok(r.unwrap_or_default()),
+    }
+}
+
+pub fn separated_list1<T: 'static, S>(item: impl Matcher<T>, sep: impl Matcher<S>) -> impl Matcher<Vec<T>> {
+    move |bs: &mut ByteStream| separated(&item, &sep, bs)
+}
+
+pub fn preceded<P, T: 'static>(prefix: impl Matcher<P>, inner: impl Matcher<T>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        match prefix.do_match(bs).and_then(|_| inner.do_match(bs)) {
+            Ok(t) => Ok(t),
+            Err(e) => {
+                bs.reset(mark);
+                Err(e)
+            }
+        }
+    }
+}
+
+pub fn terminated<T: 'static, S>(inner: impl Matcher<T>, suffix: impl Matcher<S>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        match inner.do_match(bs).and_then(|t| suffix.do_match(bs).map(|_| t)) {
+            Ok(t) => Ok(t),
+            Err(e) => {
+                bs.reset(mark);
+                Err(e)
+            }
+        }
+    }
+}
+
+pub fn delimited<O, T: 'static, C>(
+    open: impl Matcher<O>,
+    inner: impl Matcher<T>,
+    close: impl Matcher<C>,
+) -> impl Matcher<T> {
+    preceded(open, terminated(inner, close))
+}
+
 pub fn times<T: 'static>(count: usize, this: impl Matcher<T>) -> impl Matcher<Vec<T>> {
     move |bs: &mut ByteStream| {
         let mut acc: Vec<T> = vec![];
@@ -120,11 +370,17 @@ pub fn times<T: 'static>(count: usize, this: impl Matcher<T>) -> impl Matcher<Ve
     }
 }
 
+// same as `matcher.to(v)`, reads better in front of a long matcher
+pub fn value<T: 'static, U: Clone + 'static>(v: U, matcher: impl Matcher<T>) -> impl Matcher<U> {
+    matcher.to(v)
+}
+
 pub fn maybe<T: 'static>(this: impl  Matcher<T>) -> impl Matcher<Option<T>> {
     move |bs: &mut ByteStream| {
         let mark = bs.mark();
         match this.do_match(bs) {
             Ok(m) => Ok(Some(m)),
+            Err(e) if e.is_committed(bs) => Err(e),
             Err(_) => {
                 bs.reset(mark);
                 Ok(None)
@@ -133,6 +389,50 @@ pub fn maybe<T: 'static>(this: impl  Matcher<T>) -> impl Matcher<Option<T>> {
     }
 }
 
+// `maybe` with a fallback, so optional fields fold straight into a struct builder
+pub fn optional_or<T: Clone + 'static>(this: impl Matcher<T>, default: T) -> impl Matcher<T> {
+    maybe(this).map(move |t| t.unwrap_or_else(|| default.clone()))
+}
+
+pub fn maybe_default<T: Default + 'static>(this: impl Matcher<T>) -> impl Matcher<T> {
+    maybe(this).map(Option::unwrap_or_default)
+}
+
+// runs the matcher without consuming input, on success as well as on failure
+pub fn peek<T: 'static>(this: impl Matcher<T>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = this.do_match(bs);
+        bs.reset(mark);
+        result
+    }
+}
+
+// succeeds without consuming input only when the matcher fails at the current position
+pub fn not<T: 'static>(this: impl Matcher<T>) -> impl Matcher<()> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = this.do_match(bs);
+        bs.reset(mark);
+        match result {
+            Ok(_) => Err(MatchError::unexpected(
+                pos,
+                "match".to_string(),
+                "no match".to_string(),
+            )),
+            Err(e) if e.is_committed(bs) => Err(e),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+// commits to the current branch: a failure of the matcher becomes fatal, so enclosing
+// alternatives and repetitions report it instead of backtracking
+pub fn cut<T: 'static>(this: impl Matcher<T>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| this.do_match(bs).map_err(MatchError::into_fatal)
+}
+
 pub fn until<F: Fn(u8) -> bool + 'static>(f: F) -> impl Matcher<Vec<u8>> {
     move |bs: &mut ByteStream| {
         let mut acc = vec![];
@@ -144,20 +444,84 @@ pub fn until<F: Fn(u8) -> bool + 'static>(f: F) -> impl Matcher<Vec<u8>> {
                     bs.reset(mark);
                     return Ok(acc);
                 },
-                _ => return Err(MatchError::over_capacity(
-                        bs.pos(), bs.len(), 1))
+                _ => return Err(MatchError::incomplete(bs.pos(), 1)),
+            }
+        }
+    }
+}
+
+// bytes while `f` holds, possibly none; unlike `until` the end of input just ends the run
+pub fn take_while<F: Fn(u8) -> bool + 'static>(f: F) -> impl Matcher<Vec<u8>> {
+    move |bs: &mut ByteStream| scan(bs, &f)
+}
+
+// as `take_while`, but at least one byte must match, nothing is consumed otherwise
+pub fn take_while1<F: Fn(u8) -> bool + 'static>(f: F) -> impl Matcher<Vec<u8>> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        let acc = scan(bs, &f)?;
+        if acc.is_empty() {
+            let got = bs.as_ref().first()
+                .map(|b| (*b as char).to_string())
+                .unwrap_or_else(|| "end of input".to_string());
+            return Err(MatchError::unexpected(pos, got, "at least one matching byte".to_string()));
+        }
+        Ok(acc)
+    }
+}
+
+// bytes up to the first one for which `f` holds, possibly none
+pub fn take_till<F: Fn(u8) -> bool + 'static>(f: F) -> impl Matcher<Vec<u8>> {
+    take_while(move |b| !f(b))
+}
+
+// in a partial stream more matching bytes may still arrive
+fn scan<F: Fn(u8) -> bool>(bs: &mut ByteStream, f: &F) -> Result<Vec<u8>, MatchError> {
+    let mut acc = vec![];
+    loop {
+        let mark = bs.mark();
+        match bs.next() {
+            Some(b) if f(b) => acc.push(b),
+            Some(_) => {
+                bs.reset(mark);
+                return Ok(acc);
             }
+            None if bs.is_partial() => return Err(MatchError::incomplete(bs.pos(), 1)),
+            None => return Ok(acc),
         }
     }
 }
 
+// in a partial stream the delimiter may still arrive
+fn not_found(bs: &ByteStream, expected: String) -> MatchError {
+    if bs.is_partial() {
+        MatchError::incomplete(bs.len(), 1)
+    } else {
+        MatchError::not_found(bs.pos(), expected)
+    }
+}
+
 pub fn before(chr: char) -> impl Matcher<Vec<u8>> {
     move |bs: &mut ByteStream| {
         let pos = bs.pos();
         bs.find_single(|c| *c == chr as u8)
             .map(|idx| idx - pos)
             .and_then(|len| bs.get(len))
-            .ok_or(MatchError::over_capacity(pos, bs.len(), 1))
+            .ok_or_else(|| not_found(bs, format!("char '{}'", chr)))
+    }
+}
+
+// an empty pattern is found right away, like `str::find("")`
+pub fn before_seq(pattern: &'static [u8]) -> impl Matcher<Vec<u8>> {
+    move |bs: &mut ByteStream| {
+        if pattern.is_empty() {
+            return Ok(vec![]);
+        }
+        let pos = bs.pos();
+        bs.find_window(pattern.len(), |w| w == pattern)
+            .map(|idx| idx - pos)
+            .and_then(|len| bs.get(len))
+            .ok_or_else(|| not_found(bs, String::from_utf8_lossy(pattern).to_string()))
     }
 }
 
@@ -165,38 +529,54 @@ pub fn token() -> impl Matcher<String> {
     before(' ').map(|vec| vec.into_iter().map(|b| b as char).collect::<String>())
 }
 
-pub fn exact(slice: &'static [u8]) -> impl Matcher<Vec<u8>> {
-    move |bs: &mut ByteStream| {
-        let mark = bs.mark();
-        let mut acc = vec![];
-        for i in 0..slice.len() {
-            let b = slice[i];
-            let s = single(b as char);
-            match s.do_match(bs) {
-                Ok(b) => acc.push(b),
-                Err(e) => {
-                    bs.reset(mark);
-                    return Err(e);
-                }
-            }
-        }
+// the pattern may be computed at runtime, e.g. a multipart boundary: `exact(delimiter.into_bytes())`
+pub fn exact<P: AsRef<[u8]> + 'static>(pattern: P) -> impl Matcher<Vec<u8>> {
+    Exact(pattern)
+}
+
+struct Exact<P>(P);
 
-        Ok(acc.into_iter().map(|c| c as u8).collect())
+impl<P: AsRef<[u8]>> Matcher<Vec<u8>> for Exact<P> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<Vec<u8>, MatchError> {
+        match_exact(bs, self.0.as_ref())
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        Some(vec![String::from_utf8_lossy(self.0.as_ref()).to_string()])
     }
 }
 
-pub fn string(s: &'static str) -> impl Matcher<String> {
-    move |bs: &mut ByteStream| {
-        let m = exact(s.as_bytes());
-        let mark = bs.mark();
-        match m.do_match(bs) {
-            Ok(vec) => Ok(String::from_utf8(vec).unwrap()),
+fn match_exact(bs: &mut ByteStream, pattern: &[u8]) -> Result<Vec<u8>, MatchError> {
+    let mark = bs.mark();
+    let mut acc = vec![];
+    for &b in pattern {
+        let s = single(b as char);
+        match s.do_match(bs) {
+            Ok(b) => acc.push(b),
             Err(e) => {
                 bs.reset(mark);
                 return Err(e);
             }
         }
     }
+
+    Ok(acc.into_iter().map(|c| c as u8).collect())
+}
+
+pub fn string<S: AsRef<str> + 'static>(s: S) -> impl Matcher<String> {
+    Literal(s)
+}
+
+struct Literal<S>(S);
+
+impl<S: AsRef<str>> Matcher<String> for Literal<S> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<String, MatchError> {
+        match_exact(bs, self.0.as_ref().as_bytes()).map(|_| self.0.as_ref().to_string())
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        Some(vec![self.0.as_ref().to_string()])
+    }
 }
 
 pub fn space() -> impl Matcher<Vec<char>> {
@@ -209,213 +589,2123 @@ pub fn space() -> impl Matcher<Vec<char>> {
     }
 }
 
-pub fn bytes(len: usize) -> impl Matcher<Vec<u8>> {
+// unlike `space`, these succeed at the end of input
+pub fn space0() -> impl Matcher<()> {
     move |bs: &mut ByteStream| {
-        bs.get(len)
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), len))
+        skip_while(bs, |b| b.is_ascii_whitespace());
+        Ok(())
     }
 }
 
-pub fn get_u8() -> impl Matcher<u8> {
+pub fn space1() -> impl Matcher<()> {
     move |bs: &mut ByteStream| {
-        bs.get_u8()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 1))
+        let pos = bs.pos();
+        if skip_while(bs, |b| b.is_ascii_whitespace()) > 0 {
+            return Ok(());
+        }
+        match bs.as_ref().first() {
+            Some(b) => Err(MatchError::unexpected(pos, (*b as char).to_string(), "whitespace".to_string())),
+            None => Err(MatchError::incomplete(pos, 1)),
+        }
     }
 }
 
-pub fn get_u16() -> impl Matcher<u16> {
+// spaces and tabs only, line breaks are left in place
+pub fn hspace() -> impl Matcher<()> {
     move |bs: &mut ByteStream| {
-        bs.get_u16()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 2))
+        skip_while(bs, |b| b == b' ' || b == b'\t');
+        Ok(())
     }
 }
 
-pub fn get_u32() -> impl Matcher<u32> {
-    move |bs: &mut ByteStream| {
-        bs.get_u32()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 4))
-    }
+fn skip_while<F: Fn(u8) -> bool>(bs: &mut ByteStream, f: F) -> usize {
+    let n = bs.as_ref().iter().take_while(|b| f(**b)).count();
+    bs.advance(n);
+    n
 }
 
-pub fn get_u64() -> impl Matcher<u64> {
-    move |bs: &mut ByteStream| {
-        bs.get_u64()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 8))
-    }
+// skips whitespace around the matcher, for token-style grammars
+pub fn lexeme<T: 'static>(inner: impl Matcher<T>) -> impl Matcher<T> {
+    lexeme_with(space0(), inner)
 }
 
-pub fn get_16() -> impl Matcher<[u8; 16]> {
+// same as `lexeme` with a custom definition of whitespace, e.g. `hspace()`
+pub fn lexeme_with<T: 'static>(ws: impl Matcher<()>, inner: impl Matcher<T>) -> impl Matcher<T> {
     move |bs: &mut ByteStream| {
-        bs.get_16()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 16))
+        let mark = bs.mark();
+        let result = ws.do_match(bs)
+            .and_then(|_| inner.do_match(bs))
+            .and_then(|t| ws.do_match(bs).map(|_| t));
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
     }
 }
 
-pub fn get_32() -> impl Matcher<[u8; 32]> {
+// ASCII digits with an optional leading sign, out of range values fail without consuming
+pub fn decimal<T: FromStr>() -> impl Matcher<T> {
     move |bs: &mut ByteStream| {
-        bs.get_32()
-            .ok_or(MatchError::over_capacity(bs.pos(), bs.len(), 32))
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let mut text = String::new();
+        if let Some(&b) = bs.as_ref().first().filter(|b| **b == b'+' || **b == b'-') {
+            bs.advance(1);
+            text.push(b as char);
+        }
+        let digits = text.len();
+        while let Some(&b) = bs.as_ref().first().filter(|b| b.is_ascii_digit()) {
+            bs.advance(1);
+            text.push(b as char);
+        }
+        if bs.is_partial() && bs.remaining() == 0 {
+            bs.reset(mark);
+            return Err(MatchError::incomplete(bs.len(), 1));
+        }
+        if text.len() == digits {
+            let got = bs.as_ref().first().map(|b| (*b as char).to_string()).unwrap_or_else(|| "EOF".to_string());
+            bs.reset(mark);
+            return Err(MatchError::unexpected(pos, got, "decimal digit".to_string()));
+        }
+        match text.parse::<T>() {
+            Ok(n) => Ok(n),
+            Err(_) => {
+                bs.reset(mark);
+                Err(MatchError::unexpected(pos, text, format!("decimal {}", std::any::type_name::<T>())))
+            }
+        }
     }
 }
 
-pub trait Applicator {
-    fn apply<T>(&mut self, parser: impl Matcher<T>) -> Result<T, MatchError>;
+// [+-]digits[.digits][(e|E)[+-]digits], digits on at least one side of the point;
+// an exponent marker not followed by digits is left unconsumed
+pub fn float<T: FromStr>() -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        let input = bs.as_ref();
+        let digits_at = |i: usize| input[i.min(input.len())..].iter().take_while(|b| b.is_ascii_digit()).count();
+        let mut len = 0;
+        if matches!(input.first(), Some(b'+') | Some(b'-')) {
+            len += 1;
+        }
+        let int = digits_at(len);
+        len += int;
+        let mut frac = 0;
+        if input.get(len) == Some(&b'.') {
+            frac = digits_at(len + 1);
+            len += 1 + frac;
+        }
+        if int + frac == 0 {
+            let got = input.first().map(|b| (*b as char).to_string()).unwrap_or_else(|| "EOF".to_string());
+            return if bs.is_partial() && len == input.len() {
+                Err(MatchError::incomplete(bs.len(), 1))
+            } else {
+                Err(MatchError::unexpected(pos, got, "decimal float".to_string()))
+            };
+        }
+        if matches!(input.get(len), Some(b'e') | Some(b'E')) {
+            let sign = matches!(input.get(len + 1), Some(b'+') | Some(b'-')) as usize;
+            let exp = digits_at(len + 1 + sign);
+            if exp > 0 {
+                len += 1 + sign + exp;
+            } else if bs.is_partial() && len + 1 + sign == input.len() {
+                return Err(MatchError::incomplete(bs.len(), 1));
+            }
+        }
+        if bs.is_partial() && len == input.len() {
+            return Err(MatchError::incomplete(bs.len(), 1));
+        }
+        let text = String::from_utf8_lossy(&input[..len]).to_string();
+        match text.parse::<T>() {
+            Ok(x) => {
+                bs.advance(len);
+                Ok(x)
+            }
+            Err(_) => Err(MatchError::unexpected(pos, text, format!("decimal {}", std::any::type_name::<T>()))),
+        }
+    }
+}
+
+const BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+
+// UTF-8 byte order mark, as written at the start of text files by Windows tooling
+pub fn bom() -> impl Matcher<()> {
+    exact(BOM).map(|_| ())
+}
+
+// skips a byte order mark if there is one, true if it was there
+pub fn maybe_bom() -> impl Matcher<bool> {
+    maybe(bom()).map(|bom| bom.is_some())
+}
+
+// "\r\n", "\n" or a lone "\r", so files with mixed line endings still split into lines
+pub fn newline() -> impl Matcher<()> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        match bs.as_ref() {
+            [b'\r', b'\n', ..] => bs.advance(2),
+            [b'\r'] if bs.is_partial() => return Err(MatchError::incomplete(bs.len(), 1)),
+            [b'\r', ..] | [b'\n', ..] => bs.advance(1),
+            [] => return Err(MatchError::incomplete(pos, 1)),
+            [b, ..] => {
+                return Err(MatchError::unexpected(pos, (*b as char).to_string(), "newline".to_string()));
+            }
+        };
+        Ok(())
+    }
+}
+
+// contents of a line ended by a `newline` ("\r\n", "\n" or a lone "\r"), without the
+// terminator, skipping a byte order mark at the start of the stream; the last line of a
+// final stream needs no terminator, while on a partial one it is Incomplete (and nothing
+// is consumed) until the line end has arrived
+pub fn line() -> impl Matcher<Vec<u8>> {
+    let bom = maybe_bom();
+    let end = newline();
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        if bs.at_start() {
+            bom.do_match(bs)?;
+        }
+        let idx = match bs.as_ref().iter().position(|b| *b == b'\n' || *b == b'\r') {
+            Some(idx) => idx,
+            None if bs.is_partial() || bs.remaining() == 0 => {
+                let len = bs.len();
+                bs.reset(mark);
+                return Err(MatchError::incomplete(len, 1));
+            }
+            None => bs.remaining(),
+        };
+        let line = bs.get(idx).unwrap_or_default();
+        if bs.remaining() > 0 {
+            if let Err(e) = end.do_match(bs) {
+                bs.reset(mark);
+                return Err(e);
+            }
+        }
+        Ok(line)
+    }
+}
+
+// everything not read yet, e.g. a body delimited by the connection closing; like `eof`,
+// that is only known once the stream is no longer partial, until then it is Incomplete
+pub fn rest() -> impl Matcher<Vec<u8>> {
+    move |bs: &mut ByteStream| {
+        if bs.is_partial() {
+            return Err(MatchError::incomplete(bs.len(), 1));
+        }
+        Ok(bs.get(bs.remaining()).unwrap_or_default())
+    }
+}
+
+// a partial stream may still get more bytes, so its end is only known once it is no
+// longer partial: until then running out of the buffer is Incomplete
+pub fn eof() -> impl Matcher<()> {
+    move |bs: &mut ByteStream| match bs.remaining() {
+        0 if bs.is_partial() => Err(MatchError::incomplete(bs.pos(), 1)),
+        0 => Ok(()),
+        n => Err(MatchError::unexpected(bs.pos(), format!("{} more bytes", n), "end of input".to_string())),
+    }
+}
+
+// error code of `limited`, see `MatchError::code`
+pub const LIMIT_EXCEEDED: &str = "LIMIT_EXCEEDED";
+
+// caps the bytes `inner` may consume, against hostile input: going over the budget fails
+// (consuming nothing), and so does running out of a partial stream past it, so a matcher
+// that keeps waiting for a delimiter gives up instead of buffering without bound; `inner`
+// sees one byte past the budget and no further, so the work it does is bounded as well
+pub fn limited<T: 'static>(max_bytes: usize, inner: impl Matcher<T>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let start = bs.pos();
+        let end = start.saturating_add(max_bytes).saturating_add(1);
+        let capped = bs.len() >= end;
+        let over = |bs: &mut ByteStream| {
+            bs.reset(mark);
+            MatchError::custom(start + max_bytes, format!("more than {} bytes", max_bytes)).with_code(LIMIT_EXCEEDED)
+        };
+        match bs.within(end, |bs| inner.do_match(bs)) {
+            Ok(_) if bs.pos() - start > max_bytes => Err(over(bs)),
+            // ran into the hidden bytes, or needs more than the budget leaves
+            Err(e) if capped && matches!(e.kind(), MatchErrorKind::Incomplete { .. } | MatchErrorKind::NotFound) => Err(over(bs)),
+            Err(e) if matches!(e.kind(), MatchErrorKind::Incomplete { needed } if e.offset().saturating_add(needed) - start > max_bytes) => {
+                Err(over(bs))
+            }
+            result => result,
+        }
+    }
+}
+
+// keeps going past a failure of `inner`: skips to just after the next place `sync` matches
+// (or to the end of input) and yields `fallback` of the error, e.g. an error node; running
+// out of a partial stream, or having nothing to skip, still fails (consuming nothing)
+pub fn recover<T: 'static, S: 'static, F>(inner: impl Matcher<T>, sync: impl Matcher<S>, fallback: F) -> impl Matcher<T>
+where
+    F: Fn(MatchError) -> T + 'static,
+{
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let start = bs.pos();
+        let e = match inner.do_match(bs) {
+            Ok(t) => return Ok(t),
+            Err(e) if bs.is_partial() && e.is_incomplete() => {
+                bs.reset(mark);
+                return Err(e);
+            }
+            Err(e) => e,
+        };
+        bs.reset(mark);
+        loop {
+            let at = bs.mark();
+            match sync.do_match(bs) {
+                Ok(_) => break,
+                Err(e) if bs.is_partial() && e.is_incomplete() => {
+                    bs.reset(mark);
+                    return Err(e);
+                }
+                Err(_) => bs.reset(at),
+            }
+            if !bs.advance(1) {
+                if bs.is_partial() {
+                    bs.reset(mark);
+                    return Err(MatchError::incomplete(bs.len(), 1));
+                }
+                break;
+            }
+        }
+        if bs.pos() == start {
+            return Err(e);
+        }
+        Ok(fallback(e))
+    }
+}
+
+// a length, then exactly that many bytes holding one `inner` value (TLV, protobuf fields,
+// TLS records); `inner` sees only those bytes and must consume all of them
+pub fn length_value<N: TryInto<usize> + 'static, T: 'static>(
+    length: impl Matcher<N>,
+    inner: impl Matcher<T>,
+) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let n = match length.do_match(bs) {
+            Ok(n) => n,
+            Err(e) => {
+                bs.reset(mark);
+                return Err(e);
+            }
+        };
+        let n = match n.try_into() {
+            Ok(n) => n,
+            Err(_) => {
+                bs.reset(mark);
+                return Err(MatchError::custom(pos, "length does not fit into usize".to_string()));
+            }
+        };
+        let start = bs.pos();
+        let value = match bs.get(n) {
+            Some(vec) => vec,
+            None => {
+                let e = MatchError::incomplete(start, n - bs.remaining());
+                bs.reset(mark);
+                return Err(e);
+            }
+        };
+        let result = match_value(&inner, value, start);
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// runs `inner` over the bytes of a value that started at `start` in the outer stream,
+// all of them must be consumed; errors keep their kind and code, at the outer offset
+pub(crate) fn match_value<T, M: Matcher<T>>(inner: &M, value: Vec<u8>, start: usize) -> Result<T, MatchError> {
+    let n = value.len();
+    let mut value = ByteStream::wrap(value);
+    match inner.do_match(&mut value) {
+        Ok(_) if value.remaining() > 0 => Err(MatchError::unexpected(
+            start + value.pos(),
+            format!("{} unread bytes", value.remaining()),
+            format!("end of {} byte value", n),
+        )),
+        Ok(t) => Ok(t),
+        Err(e) => {
+            let offset = start + e.offset();
+            Err(e.with_offset(offset).with_context(&format!("{} byte value", n)))
+        }
+    }
+}
+
+// `inner` followed by a checksum over the bytes `inner` consumed, e.g. a trailing CRC:
+// `checksummed(frame(), get_u32(), |bytes, crc| crc32(bytes) == *crc)`
+pub fn checksummed<T: 'static, C: 'static, F>(
+    inner: impl Matcher<T>,
+    checksum: impl Matcher<C>,
+    verify: F,
+) -> impl Matcher<T>
+where
+    F: Fn(&[u8], &C) -> bool + 'static,
+{
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let start = bs.pos();
+        let result = inner.do_match(bs).and_then(|t| {
+            let end = bs.pos();
+            let c = checksum.do_match(bs)?;
+            if verify(bs.slice(start..end).unwrap_or_default(), &c) {
+                Ok(t)
+            } else {
+                Err(MatchError::unexpected(end, "checksum mismatch".to_string(), "valid checksum".to_string()))
+            }
+        });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+pub fn bytes(len: usize) -> impl Matcher<Vec<u8>> {
+    move |bs: &mut ByteStream| {
+        bs.get(len)
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), len - bs.remaining()))
+    }
+}
+
+pub fn get_u8() -> impl Matcher<u8> {
+    move |bs: &mut ByteStream| {
+        bs.get_u8()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 1 - bs.remaining()))
+    }
+}
+
+pub fn get_u16() -> impl Matcher<u16> {
+    move |bs: &mut ByteStream| {
+        bs.get_u16()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 2 - bs.remaining()))
+    }
+}
+
+pub fn get_u32() -> impl Matcher<u32> {
+    move |bs: &mut ByteStream| {
+        bs.get_u32()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 4 - bs.remaining()))
+    }
+}
+
+pub fn get_u64() -> impl Matcher<u64> {
+    move |bs: &mut ByteStream| {
+        bs.get_u64()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 8 - bs.remaining()))
+    }
+}
+
+pub fn get_u16_le() -> impl Matcher<u16> {
+    move |bs: &mut ByteStream| {
+        bs.get_u16_le()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 2 - bs.remaining()))
+    }
+}
+
+pub fn get_u32_le() -> impl Matcher<u32> {
+    move |bs: &mut ByteStream| {
+        bs.get_u32_le()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 4 - bs.remaining()))
+    }
+}
+
+pub fn get_u64_le() -> impl Matcher<u64> {
+    move |bs: &mut ByteStream| {
+        bs.get_u64_le()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 8 - bs.remaining()))
+    }
+}
+
+pub fn get_i8() -> impl Matcher<i8> {
+    get_u8().map(|x| x as i8)
+}
+
+pub fn get_i16() -> impl Matcher<i16> {
+    get_u16().map(|x| x as i16)
+}
+
+pub fn get_i32() -> impl Matcher<i32> {
+    get_u32().map(|x| x as i32)
+}
+
+pub fn get_i64() -> impl Matcher<i64> {
+    get_u64().map(|x| x as i64)
+}
+
+pub fn get_i16_le() -> impl Matcher<i16> {
+    get_u16_le().map(|x| x as i16)
+}
+
+pub fn get_i32_le() -> impl Matcher<i32> {
+    get_u32_le().map(|x| x as i32)
+}
+
+pub fn get_i64_le() -> impl Matcher<i64> {
+    get_u64_le().map(|x| x as i64)
+}
+
+pub fn get_f32() -> impl Matcher<f32> {
+    get_u32().map(f32::from_bits)
+}
+
+pub fn get_f64() -> impl Matcher<f64> {
+    get_u64().map(f64::from_bits)
+}
+
+pub fn get_f32_le() -> impl Matcher<f32> {
+    get_u32_le().map(f32::from_bits)
+}
+
+pub fn get_f64_le() -> impl Matcher<f64> {
+    get_u64_le().map(f64::from_bits)
+}
+
+// unsigned LEB128 (protobuf, wasm): 7 bits per byte, least significant group first
+pub fn varint_u32() -> impl Matcher<u32> {
+    move |bs: &mut ByteStream| varint(bs, 32).map(|x| x as u32)
+}
+
+pub fn varint_u64() -> impl Matcher<u64> {
+    move |bs: &mut ByteStream| varint(bs, 64)
+}
+
+fn varint(bs: &mut ByteStream, bits: u32) -> Result<u64, MatchError> {
+    let mark = bs.mark();
+    let pos = bs.pos();
+    let max_len = bits.div_ceil(7);
+    let mut value = 0u64;
+    for i in 0..max_len {
+        let b = match get_u8().do_match(bs) {
+            Ok(b) => b,
+            Err(e) => {
+                bs.reset(mark);
+                return Err(e);
+            }
+        };
+        let shift = 7 * i;
+        let group = (b & 0x7F) as u64;
+        if shift + 7 > bits && group >> (bits - shift) != 0 {
+            bs.reset(mark);
+            return Err(MatchError::unexpected(
+                pos,
+                "overflowing varint".to_string(),
+                format!("varint of at most {} bits", bits),
+            ));
+        }
+        value |= group << shift;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bs.reset(mark);
+    Err(MatchError::unexpected(
+        pos,
+        format!("more than {} bytes", max_len),
+        format!("varint of at most {} bits", bits),
+    ))
+}
+
+pub fn get_16() -> impl Matcher<[u8; 16]> {
+    move |bs: &mut ByteStream| {
+        bs.get_16()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 16 - bs.remaining()))
+    }
+}
+
+pub fn get_32() -> impl Matcher<[u8; 32]> {
+    move |bs: &mut ByteStream| {
+        bs.get_32()
+            .ok_or_else(|| MatchError::incomplete(bs.pos(), 32 - bs.remaining()))
+    }
+}
+
+pub fn get_array<const N: usize>() -> impl Matcher<[u8; N]> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        bs.get(N)
+            .and_then(|v| v.try_into().ok())
+            .ok_or_else(|| MatchError::incomplete(pos, N - bs.remaining()))
+    }
+}
+
+// packed bit fields, most significant first, e.g. `bit_fields([2, 3, 3])` for the
+// LI/VN/Mode byte of NTP; the widths must add up to whole bytes, at most 64 bits
+pub fn bit_fields<const K: usize>(widths: [u32; K]) -> impl Matcher<[u64; K]> {
+    let total = widths.iter().sum::<u32>();
+    assert!(0 < total && total <= 64 && total % 8 == 0, "bit fields must fill 1 to 8 whole bytes");
+    move |bs: &mut ByteStream| {
+        let n = (total / 8) as usize;
+        let pos = bs.pos();
+        let bytes = bs.get(n).ok_or_else(|| MatchError::incomplete(pos, n - bs.remaining()))?;
+        let mut word = bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        let mut fields = [0u64; K];
+        for (field, width) in fields.iter_mut().zip(widths.iter()).rev() {
+            let mask = if *width == 64 { u64::MAX } else { (1u64 << *width) - 1 };
+            *field = word & mask;
+            word = word.checked_shr(*width).unwrap_or(0);
+        }
+        Ok(fields)
+    }
+}
+
+pub trait Alt<T> {
+    fn choose(&self, bs: &mut ByteStream) -> Result<T, MatchError>;
+
+    fn expected_set(&self) -> Option<Vec<String>>;
+}
+
+macro_rules! alt_impl {
+    ($($m:ident $idx:tt),+; $last:ident $lidx:tt) => {
+        impl<T, $($m: Matcher<T>,)+ $last: Matcher<T>> Alt<T> for ($($m,)+ $last) {
+            fn choose(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+                let mark = bs.mark();
+                let pos = bs.pos();
+                let mut at_entry = true;
+                $(
+                    match self.$idx.do_match(bs) {
+                        Ok(t) => return Ok(t),
+                        Err(e) if e.is_committed(bs) => return Err(e),
+                        Err(e) => {
+                            at_entry &= fails_at_entry(&e, pos);
+                            bs.reset(mark);
+                        }
+                    }
+                )+
+                self.$lidx.do_match(bs).map_err(|e| {
+                    bs.reset(mark);
+                    expected_one_of(bs, pos, e, Alt::expected_set(self), at_entry)
+                })
+            }
+
+            fn expected_set(&self) -> Option<Vec<String>> {
+                let set = self.0.expected_set();
+                $(let set = union(set, self.$idx.expected_set());)+
+                union(set, self.$lidx.expected_set())
+            }
+        }
+    };
+}
+
+alt_impl!(A 0; B 1);
+alt_impl!(A 0, B 1; C 2);
+alt_impl!(A 0, B 1, C 2; D 3);
+alt_impl!(A 0, B 1, C 2, D 3; E 4);
+alt_impl!(A 0, B 1, C 2, D 3, E 4; F 5);
+alt_impl!(A 0, B 1, C 2, D 3, E 4, F 5; G 6);
+alt_impl!(A 0, B 1, C 2, D 3, E 4, F 5, G 6; H 7);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+// `a`, or else `b`, keeping apart which one matched (request vs response, text vs binary)
+pub fn either<L: 'static, R: 'static>(a: impl Matcher<L>, b: impl Matcher<R>) -> impl Matcher<Either<L, R>> {
+    a.map(Either::Left).or(b.map(Either::Right))
+}
+
+// `either` with each branch mapped to a common type
+pub fn alt_map<L: 'static, R: 'static, V, F, G>(a: impl Matcher<L>, f: F, b: impl Matcher<R>, g: G) -> impl Matcher<V>
+where
+    F: Fn(L) -> V + 'static,
+    G: Fn(R) -> V + 'static,
+{
+    either(a, b).map(move |e| match e {
+        Either::Left(l) => f(l),
+        Either::Right(r) => g(r),
+    })
+}
+
+// first successful alternative, stream is reset before each attempt
+pub fn alt<T, A: Alt<T>>(alts: A) -> impl Matcher<T> {
+    Choose(alts, PhantomData)
+}
+
+struct Choose<A, T>(A, PhantomData<T>);
+
+impl<T, A: Alt<T>> Matcher<T> for Choose<A, T> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        self.0.choose(bs)
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        self.0.expected_set()
+    }
+}
+
+// `alt` over a list built at runtime: the first success in order, or the error of the
+// alternative that got furthest
+pub fn choice<T: 'static>(alts: Vec<Box<dyn Matcher<T>>>) -> impl Matcher<T> {
+    Choice(alts)
+}
+
+struct Choice<T>(Vec<Box<dyn Matcher<T>>>);
+
+impl<T> Matcher<T> for Choice<T> {
+    fn do_match(&self, bs: &mut ByteStream) -> Result<T, MatchError> {
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let mut error: Option<MatchError> = None;
+        let mut at_entry = true;
+        for m in self.0.iter() {
+            match m.do_match(bs) {
+                Ok(t) => return Ok(t),
+                Err(e) if e.is_committed(bs) => return Err(e),
+                Err(e) => {
+                    at_entry &= fails_at_entry(&e, pos);
+                    bs.reset(mark);
+                    if error.as_ref().map(|x| e.offset() > x.offset()).unwrap_or(true) {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+        let e = error.unwrap_or_else(|| MatchError::custom(pos, "no alternatives".to_string()));
+        Err(expected_one_of(bs, pos, e, self.expected_set(), at_entry))
+    }
+
+    fn expected_set(&self) -> Option<Vec<String>> {
+        let mut alts = self.0.iter();
+        let first = alts.next()?.expected_set();
+        alts.fold(first, |set, m| union(set, m.expected_set()))
+    }
 }
 
-impl Applicator for ByteStream {
-    fn apply<T>(&mut self, parser: impl Matcher<T>) -> Result<T, MatchError> {
-        parser.do_match(self)
+// tries every alternative from the same position and keeps the one that consumed the most
+// input (the first one on ties); if all fail, the error that got furthest is returned
+pub fn longest_of<T: 'static>(alts: Vec<Box<dyn Matcher<T>>>) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| {
+        let start = bs.pos();
+        let mark = bs.mark();
+        let mut best: Option<(usize, T)> = None;
+        let mut error: Option<MatchError> = None;
+        for m in alts.iter() {
+            bs.reset(mark);
+            match m.do_match(bs) {
+                Ok(t) => {
+                    if best.as_ref().map(|(end, _)| bs.pos() > *end).unwrap_or(true) {
+                        best = Some((bs.pos(), t));
+                    }
+                }
+                Err(e) if e.is_committed(bs) => return Err(e),
+                Err(e) => {
+                    if error.as_ref().map(|x| e.offset() > x.offset()).unwrap_or(true) {
+                        error = Some(e);
+                    }
+                }
+            }
+        }
+        bs.reset(mark);
+        match best {
+            Some((end, t)) => {
+                bs.advance(end - start);
+                Ok(t)
+            }
+            None => Err(error.unwrap_or_else(|| MatchError::custom(start, "no alternatives".to_string()))),
+        }
+    }
+}
+
+// quoted string with escape sequences, `escape` is followed by whatever `map_escape` matches,
+// e.g. `standard_escape()`; when `escape` equals `quote` (as in CSV) a quote that is not
+// a valid escape ends the string
+pub fn escaped_string(quote: char, escape: char, map_escape: impl Matcher<char>) -> impl Matcher<String> {
+    let open = quote.to_string().into_bytes();
+    let close = open.clone();
+    let special = [open.clone(), escape.to_string().into_bytes()];
+    let normal = move |bs: &mut ByteStream| {
+        if special.iter().any(|p| bs.as_ref().starts_with(p)) {
+            return Err(MatchError::unexpected(bs.pos(), "quote or escape".to_string(), "unescaped char".to_string()));
+        }
+        get_u8().do_match(bs)
+    };
+    delimited(exact(open), unescape(normal, escape, map_escape, quote == escape), exact(close))
+}
+
+// unescaped content up to the first byte that is neither `normal` nor an escape sequence,
+// e.g. the inside of a quoted string: `escaped(none_of(b"\"\\"), '\\', standard_escape())`
+pub fn escaped(normal: impl Matcher<u8>, escape: char, escaped_items: impl Matcher<char>) -> impl Matcher<String> {
+    unescape(normal, escape, escaped_items, false)
+}
+
+// `escaped`, where with `or_end` an escape not followed by a valid escape sequence ends the
+// content instead of failing the match
+fn unescape(normal: impl Matcher<u8>, escape: char, escaped_items: impl Matcher<char>, or_end: bool) -> impl Matcher<String> {
+    let escape = escape.to_string().into_bytes();
+    move |bs: &mut ByteStream| {
+        let start = bs.mark();
+        let pos = bs.pos();
+        let mut acc = vec![];
+        loop {
+            if bs.as_ref().starts_with(&escape) {
+                let mark = bs.mark();
+                bs.advance(escape.len());
+                match escaped_items.do_match(bs) {
+                    Ok(c) => acc.extend_from_slice(c.encode_utf8(&mut [0u8; 4]).as_bytes()),
+                    Err(e) if or_end && !e.is_committed(bs) => {
+                        bs.reset(mark);
+                        break;
+                    }
+                    Err(e) => {
+                        bs.reset(start);
+                        return Err(e);
+                    }
+                }
+                continue;
+            }
+            let mark = bs.mark();
+            match normal.do_match(bs) {
+                Ok(b) => acc.push(b),
+                Err(e) if e.is_committed(bs) => {
+                    bs.reset(start);
+                    return Err(e);
+                }
+                Err(_) => {
+                    bs.reset(mark);
+                    break;
+                }
+            }
+        }
+        String::from_utf8(acc).map_err(|e| {
+            bs.reset(start);
+            MatchError::unexpected(pos, "invalid UTF-8".to_string(), "string".to_string()).with_source(e.utf8_error())
+        })
+    }
+}
+
+// double-quoted string with backslash escapes (HTTP quoted-string, JSON strings), unescaped
+pub fn quoted_string() -> impl Matcher<String> {
+    escaped_string('"', '\\', standard_escape())
+}
+
+// the usual single-character escapes: \n \r \t \0 \b \f \\ \" \' \/
+pub fn standard_escape() -> impl Matcher<char> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        let c = get_u8().do_match(bs)? as char;
+        match c {
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            '0' => Ok('\0'),
+            'b' => Ok('\u{8}'),
+            'f' => Ok('\u{c}'),
+            '\\' | '"' | '\'' | '/' => Ok(c),
+            _ => Err(MatchError::unexpected(pos, c.to_string(), "escape sequence".to_string())),
+        }
+    }
+}
+
+// builds the matcher on every use, which lets a grammar refer to itself:
+// `fn value() -> impl Matcher<V> { ... lazy(|| value().boxed()) ... }`
+pub fn lazy<T, F: Fn() -> Box<dyn Matcher<T>> + 'static>(f: F) -> impl Matcher<T> {
+    move |bs: &mut ByteStream| f().do_match(bs)
+}
+
+pub trait Applicator {
+    fn apply<T>(&mut self, parser: impl Matcher<T>) -> Result<T, MatchError>;
+}
+
+// on a partial stream an Incomplete failure always leaves the stream where it was,
+// so the same parser can be retried once more bytes are put into it
+impl Applicator for ByteStream {
+    fn apply<T>(&mut self, parser: impl Matcher<T>) -> Result<T, MatchError> {
+        let mark = self.mark();
+        match parser.do_match(self) {
+            Err(e) => {
+                if self.is_partial() && e.is_incomplete() {
+                    self.reset(mark);
+                }
+                if self.tracks_lines() {
+                    let (line, col) = self.line_col(e.offset());
+                    return Err(e.with_position(line, col));
+                }
+                Err(e)
+            }
+            ok => ok,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::redundant_closure)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple() {
+        #[derive(Debug, Eq, PartialEq)]
+        enum Token {
+            KV { k: char, v: char },
+        }
+
+        struct TokenBuilder {
+            k: Option<char>,
+            v: Option<char>,
+        }
+
+        impl TokenBuilder {
+            fn zero() -> TokenBuilder {
+                TokenBuilder { k: None, v: None }
+            }
+        }
+
+        let mut bs: ByteStream = "abc".to_string().into();
+
+        let m = unit(|| TokenBuilder::zero())
+            .then_map(single('a'), |(tb, a)| TokenBuilder { k: Some(a), ..tb })
+            .then_map(single('b'), |(tb, b)| TokenBuilder { v: Some(b), ..tb })
+            .map(|tb| Token::KV {
+                k: tb.k.unwrap(),
+                v: tb.v.unwrap(),
+            });
+
+        assert_eq!(bs.apply(m).unwrap(), Token::KV { k: 'a', v: 'b' });
+        assert_eq!(bs.pos(), 2);
+    }
+
+    #[test]
+    fn list() {
+        let mut bs: ByteStream = "abccc".to_string().into();
+
+        let c = single('c');
+
+        let abccc = unit(|| vec![])
+            .then_map(single('a'), |(acc, a)| {
+                let mut copy = acc.clone();
+                copy.push(a);
+                copy
+            })
+            .then_map(single('b'), |(acc, b)| {
+                let mut copy = acc.clone();
+                copy.push(b);
+                copy
+            })
+            .then_map(repeat(c), |(acc, vec)| {
+                let mut copy = acc;
+                for item in vec {
+                    copy.push(item);
+                }
+                copy
+            });
+
+        assert_eq!(bs.apply(abccc).unwrap(), vec!['a', 'b', 'c', 'c', 'c']);
+        assert_eq!(bs.pos(), 5);
+    }
+
+    #[test]
+    fn until() {
+        let mut bs: ByteStream = "asdasdasdasd1".to_string().into();
+
+        let until1 = unit(|| ())
+            .then_map(before('1'), |(_, vec)| {
+                vec.into_iter().map(|b| b as char).collect::<String>()
+            })
+            .then_map(single('1'), |(vec, one)| (vec, one));
+
+        assert_eq!(bs.apply(until1).unwrap(), ("asdasdasdasd".to_string(), '1'));
+    }
+
+    #[test]
+    fn take_while_till() {
+        let mut bs: ByteStream = "abc123".to_string().into();
+        let digit: fn(u8) -> bool = |b| b.is_ascii_digit();
+        assert!(bs.apply(take_while(digit)).unwrap().is_empty());
+        assert!(bs.apply(take_while1(digit)).is_err());
+        assert_eq!(bs.pos(), 0);
+        assert_eq!(bs.apply(take_till(digit)).unwrap(), b"abc");
+        assert_eq!(bs.apply(take_while1(digit)).unwrap(), b"123");
+        assert!(bs.apply(take_while(digit)).unwrap().is_empty());
+        assert_eq!(bs.apply(take_while1(digit)).unwrap_err().offset(), 6);
+    }
+
+    #[test]
+    fn chunks() {
+        let mut bs: ByteStream = "asdasdqqq123123token1 token2\n".to_string().into();
+
+        let m = unit(|| vec![])
+            .then(exact("asd".as_bytes()))
+            .map(|(mut vec, bs)| {
+                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
+                vec
+            })
+            .then(exact("asdqqq".as_bytes()))
+            .map(|(mut vec, bs)| {
+                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
+                vec
+            })
+            .then(exact("123123".as_bytes()))
+            .map(|(mut vec, bs)| {
+                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
+                vec
+            })
+            .then(token())
+            .map(|(mut vec, bs)| {
+                vec.push(bs);
+                vec
+            })
+            .then(before('\n'))
+            .map(|(mut vec, bs)| {
+                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
+                vec
+            });
+
+        assert_eq!(
+            bs.apply(m).unwrap(),
+            vec![
+                "asd".to_string(),
+                "asdqqq".to_string(),
+                "123123".to_string(),
+                "token1".to_string(),
+                " token2".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_times_ok() {
+        let mut bs = ByteStream::wrap(b"012301230123".to_vec());
+
+        let m = unit(|| ())
+            .then(times(3, exact(b"0123")))
+            .map(|(_, times)| times);
+
+        assert_eq!(
+            bs.apply(m).unwrap(),
+            vec![
+                b"0123",
+                b"0123",
+                b"0123",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_times_mismatch() {
+        let mut bs = ByteStream::wrap(b"0123012301AA".to_vec());
+
+        let m = unit(|| vec![])
+            .then(times(3, exact(b"0123")))
+            .save(|vec, times| *vec = times);
+
+        assert!(bs.apply(m).is_err());
+    }
+
+    #[test]
+    fn test_many1() {
+        let mut bs = ByteStream::wrap(b"aaab".to_vec());
+
+        assert_eq!(bs.apply(many1(single('a'))).unwrap(), vec!['a', 'a', 'a']);
+        assert!(bs.apply(many1(single('a'))).is_err());
+        assert_eq!(bs.pos(), 3);
+        assert_eq!(bs.apply(many1(single('b'))).unwrap(), vec!['b']);
+    }
+
+    #[test]
+    fn test_repeat_bounded() {
+        let mut bs = ByteStream::wrap(b"aaaaab".to_vec());
+
+        assert_eq!(bs.apply(repeat_bounded(single('a'), 2, 3)).unwrap().len(), 3);
+        assert_eq!(bs.pos(), 3);
+        let err = bs.apply(repeat_bounded(single('a'), 3, 4)).unwrap_err();
+        assert_eq!(err.offset(), 5);
+        assert!(err.to_string().contains("at least 3 matches"));
+        assert_eq!(bs.pos(), 3);
+        assert_eq!(bs.apply(repeat_bounded(single('a'), 0, 16)).unwrap().len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "min 3 is over max 2")]
+    fn test_repeat_bounded_min_over_max() {
+        repeat_bounded(single('a'), 3, 2);
+    }
+
+    #[test]
+    fn test_repeat_n() {
+        let mut bs = ByteStream::wrap(b"aaab".to_vec());
+
+        assert_eq!(bs.apply(repeat_n(single('a'), 0)).unwrap().len(), 0);
+        assert_eq!(bs.apply(repeat_n(single('a'), 2)).unwrap(), vec!['a', 'a']);
+        assert!(bs.apply(repeat_n(single('a'), 2)).is_err());
+        assert_eq!(bs.pos(), 2);
+    }
+
+    #[test]
+    fn test_separated_list() {
+        let mut bs = ByteStream::wrap(b"gzip, br, deflate,;".to_vec());
+
+        let coding = alt((string("gzip"), string("br"), string("deflate")));
+        let list = separated_list1(coding, string(", ").or(string(",")));
+        assert_eq!(bs.apply(list).unwrap(), vec!["gzip", "br", "deflate"]);
+        assert_eq!(bs.pos(), 17);
+
+        let list = separated_list(single('x'), single(','));
+        assert_eq!(bs.apply(list).unwrap(), vec![]);
+        assert_eq!(bs.pos(), 17);
+
+        let list = separated_list1(single('x'), single(','));
+        assert!(bs.apply(list).is_err());
+        assert_eq!(bs.pos(), 17);
+    }
+
+    #[test]
+    fn test_delimited() {
+        let mut bs = ByteStream::wrap(b"\"abc\" key: value\r\n".to_vec());
+
+        assert_eq!(bs.apply(delimited(single('"'), before('"'), single('"'))).unwrap(), b"abc");
+        assert_eq!(bs.apply(preceded(single(' '), token())).unwrap(), "key:");
+        assert!(bs.apply(terminated(preceded(single(' '), before('\r')), string("\n"))).is_err());
+        assert_eq!(bs.pos(), 10);
+        assert_eq!(bs.apply(preceded(single(' '), terminated(before('\r'), string("\r\n")))).unwrap(), b"value");
+        assert_eq!(bs.pos(), 18);
+    }
+
+    #[test]
+    fn test_peek() {
+        let mut bs = ByteStream::wrap(b"GET / HTTP/1.1\r\n".to_vec());
+
+        assert_eq!(bs.apply(peek(string("GET"))).unwrap(), "GET");
+        assert_eq!(bs.pos(), 0);
+        assert!(bs.apply(peek(string("GET /x"))).is_err());
+        assert_eq!(bs.pos(), 0);
+        assert_eq!(bs.apply(token()).unwrap(), "GET");
+    }
+
+    #[test]
+    fn test_not() {
+        let mut bs = ByteStream::wrap(b"ab*/".to_vec());
+
+        let comment_char = preceded(not(string("*/")), get_u8());
+        assert_eq!(bs.apply(repeat(comment_char)).unwrap(), b"ab");
+        assert_eq!(bs.pos(), 2);
+        assert!(bs.apply(not(string("*/"))).is_err());
+        assert_eq!(bs.pos(), 2);
+    }
+
+    #[test]
+    fn test_before_seq() {
+        let mut bs = ByteStream::wrap(b"Host: a\r\nAccept: */*\r\n\r\nbody\r\n".to_vec());
+
+        let head = terminated(before_seq(b"\r\n\r\n"), exact(b"\r\n\r\n"));
+        assert_eq!(bs.apply(head).unwrap(), b"Host: a\r\nAccept: */*");
+        assert!(bs.apply(before_seq(b"\r\n\r\n")).is_err());
+        assert_eq!(bs.pos(), 24);
+        assert_eq!(bs.apply(before_seq(b"\r\n")).unwrap(), b"body");
+        assert_eq!(bs.apply(before_seq(b"")).unwrap(), b"");
+        assert_eq!(bs.pos(), 28);
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut bs = ByteStream::wrap(vec![0x81, 0x0F]);
+
+        let opcode = || get_u8().map(|b| b & 0x0F).verify(|op| *op < 8);
+        assert_eq!(bs.apply(opcode()).unwrap(), 1);
+        let err = bs.apply(opcode()).unwrap_err();
+        assert_eq!(err.offset(), 1);
+        assert_eq!(bs.pos(), 1);
+    }
+
+    #[test]
+    fn test_try_map() {
+        let mut bs = ByteStream::wrap(b"8 x1 ".to_vec());
+
+        let len = || token().try_map(|s| s.parse::<usize>());
+        assert_eq!(bs.apply(len()).unwrap(), 8);
+        assert_eq!(bs.apply(single(' ')).unwrap(), ' ');
+        let err = bs.apply(len()).unwrap_err();
+        assert_eq!(err.offset(), 2);
+        assert!(err.to_string().contains("invalid digit"));
+        assert_eq!(bs.pos(), 2);
+    }
+
+    #[test]
+    fn test_fold() {
+        let mut bs = ByteStream::wrap(b"1234x".to_vec());
+
+        let digit = get_u8().verify(|b| b.is_ascii_digit()).map(|b| (b - b'0') as u64);
+        let number = fold(|| 0u64, digit, |acc, d| acc * 10 + d);
+        assert_eq!(bs.apply(number).unwrap(), 1234);
+        assert_eq!(bs.pos(), 4);
+
+        let count = fold(|| 0usize, single('y'), |n, _| n + 1);
+        assert_eq!(bs.apply(count).unwrap(), 0);
+        assert_eq!(bs.pos(), 4);
+    }
+
+    #[test]
+    fn test_cut() {
+        let get = string("GET ").then(cut(string("/path")));
+        let post = string("GET /x").or(string("POST"));
+        let mut bs = ByteStream::wrap(b"GET /x".to_vec());
+        let err = bs.apply(alt((get.map(|_| ()), post.map(|_| ())))).unwrap_err();
+        assert!(err.is_fatal());
+        assert_eq!(err.offset(), 5);
+
+        let mut bs = ByteStream::wrap(b"GET /x".to_vec());
+        let m = string("GET ").then(string("/path")).map(|_| ()).or(string("GET /x").map(|_| ()));
+        assert!(bs.apply(m).is_ok());
+
+        let mut bs = ByteStream::wrap(b"a1a1a!".to_vec());
+        let item = single('a').then(cut(get_u8().verify(|b| b.is_ascii_digit())));
+        let err = bs.apply(repeat(item)).unwrap_err();
+        assert!(err.is_fatal());
+        assert_eq!(err.offset(), 5);
+
+        let mut bs = ByteStream::wrap(b"x".to_vec());
+        assert!(bs.apply(maybe(cut(single('y')))).unwrap_err().is_fatal());
+    }
+
+    #[test]
+    fn test_context() {
+        let method = string("GET").context("method token");
+        let line = method.then(single(' ')).context("request line");
+        let mut bs = ByteStream::wrap(b"PUT /".to_vec());
+        let err = bs.apply(line).unwrap_err();
+        assert_eq!(err.context(), ["request line", "method token"]);
+        assert_eq!(
+            err.to_string(),
+            "in request line > in method token: MatchError at offset 0 expected 'char 'G'' but got 'P'"
+        );
+
+        let err = MatchError::custom(3, "oops".to_string());
+        assert!(err.context().is_empty());
+        assert_eq!(err.to_string(), "MatchError at offset 3: oops");
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let mut bs = ByteStream::wrap(b"GE".to_vec());
+        let err = bs.apply(string("GET")).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Incomplete { needed: 1 });
+        assert!(err.is_incomplete());
+        assert_eq!(err.offset(), 2);
+
+        let err = bs.apply(string("PUT")).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Unexpected);
+        assert!(!err.is_incomplete());
+
+        let err = bs.apply(bytes(5)).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Incomplete { needed: 3 });
+        let err = bs.apply(get_u32()).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Incomplete { needed: 2 });
+
+        let err = bs.apply(before(' ')).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::NotFound);
+        assert_eq!(MatchError::custom(0, "x".to_string()).kind(), MatchErrorKind::Custom);
+    }
+
+    #[test]
+    fn test_partial() {
+        let mut bs = ByteStream::with_capacity(16);
+        bs.set_partial(true);
+        bs.put(b"abab a");
+        let m = || repeat(string("ab")).then(before(';'));
+        let err = bs.apply(m()).unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(bs.pos(), 0);
+
+        bs.put(b"b;");
+        let (items, rest) = bs.apply(m()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(rest, b" ab");
+
+        // a repetition cut short by the end of the buffer could still continue
+        let mut bs = ByteStream::wrap(b"aba".to_vec());
+        bs.set_partial(true);
+        assert!(bs.apply(m()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(b"aba".to_vec());
+        let err = bs.apply(repeat(string("ab")).then(before(';'))).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::NotFound);
+
+        let mut bs = ByteStream::wrap(b"GE".to_vec());
+        bs.set_partial(true);
+        let err = bs.apply(string("GET").or(string("PUT"))).unwrap_err();
+        assert!(err.is_incomplete());
+        assert!(bs.apply(maybe(string("GET"))).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_line_tracking() {
+        let text = b"key = 1\nname = x\nbroken\n".to_vec();
+        let entry = || terminated(before(' '), string(" = ")).then(before('\n')).then(single('\n'));
+        let mut bs = ByteStream::wrap(text.clone());
+        bs.set_track_lines(true);
+        let err = bs.apply(repeat_n(entry(), 3)).unwrap_err();
+        assert_eq!(err.offset(), 17);
+        assert_eq!((err.line(), err.column()), (Some(3), Some(1)));
+        assert!(err.to_string().ends_with(" (line 3, column 1)"));
+
+        let mut bs = ByteStream::wrap(text);
+        let err = bs.apply(repeat_n(entry(), 3)).unwrap_err();
+        assert_eq!(err.line(), None);
+    }
+
+    #[test]
+    fn test_get_array() {
+        let mut bs = ByteStream::wrap(vec![1, 2, 3, 4, 5]);
+        assert_eq!(bs.apply(get_array::<3>()).unwrap(), [1, 2, 3]);
+        assert_eq!(bs.apply(get_array::<0>()).unwrap(), []);
+        assert!(bs.apply(get_array::<3>()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 3);
+    }
+
+    #[test]
+    fn test_bit_fields() {
+        let mut bs = ByteStream::wrap(vec![0b1110_0011, 0xAB, 0xCD, 0xEF]);
+        assert_eq!(bs.apply(bit_fields([2, 3, 3])).unwrap(), [3, 4, 3]);
+        assert_eq!(bs.apply(bit_fields([4, 12, 8])).unwrap(), [0xA, 0xBCD, 0xEF]);
+        let mut bs = ByteStream::wrap(vec![0xFF; 8]);
+        assert_eq!(bs.apply(bit_fields([64])).unwrap(), [u64::MAX]);
+    }
+
+    #[test]
+    #[should_panic(expected = "bit fields must fill 1 to 8 whole bytes")]
+    fn test_bit_fields_empty() {
+        bit_fields([0, 0]);
+    }
+
+    #[test]
+    fn test_limited() {
+        let mut bs = ByteStream::wrap(b"GET /index.html HTTP/1.1".to_vec());
+        assert_eq!(bs.apply(limited(3, token())).unwrap(), "GET");
+        bs.apply(single(' ')).unwrap();
+        let err = bs.apply(limited(8, token())).unwrap_err();
+        assert_eq!(err.code(), LIMIT_EXCEEDED);
+        assert_eq!(err.offset(), 12);
+        assert_eq!(bs.pos(), 4);
+
+        // the delimiter has not arrived within the budget, waiting for it is pointless
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"aaaa");
+        assert!(bs.apply(limited(8, line())).unwrap_err().is_incomplete());
+        bs.put(b"aaaa");
+        assert_eq!(bs.apply(limited(8, line())).unwrap_err().code(), LIMIT_EXCEEDED);
+        assert_eq!(bs.pos(), 0);
+
+        // a length known to be over the budget fails before the bytes arrive
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"ab");
+        assert!(bs.apply(limited(8, bytes(4))).unwrap_err().is_incomplete());
+        assert_eq!(bs.apply(limited(8, bytes(1000))).unwrap_err().code(), LIMIT_EXCEEDED);
+
+        // bytes past the budget are not visible to the inner matcher
+        let mut bs = ByteStream::wrap([vec![b'a'; 100], b"\n".to_vec()].concat());
+        let inner = || repeat(single('a')).map(|v| v.len());
+        assert_eq!(bs.apply(limited(8, inner())).unwrap_err().code(), LIMIT_EXCEEDED);
+        assert_eq!(bs.apply(limited(100, inner())).unwrap(), 100);
+        assert_eq!(bs.remaining(), 1);
+        assert!(bs.apply(limited(8, line())).is_ok());
+    }
+
+    #[test]
+    fn test_recover() {
+        #[derive(Debug, PartialEq)]
+        enum Stmt {
+            Num(u32),
+            Error(usize),
+        }
+        let stmt = || recover(terminated(decimal::<u32>().map(Stmt::Num), single('\n')), single('\n'), |e| Stmt::Error(e.offset()));
+
+        let mut bs = ByteStream::wrap(b"12\nx9\n7 ?\n34\n".to_vec());
+        assert_eq!(bs.apply(repeat(stmt())).unwrap(), vec![Stmt::Num(12), Stmt::Error(3), Stmt::Error(7), Stmt::Num(34)]);
+        assert_eq!(bs.remaining(), 0);
+
+        // without a sync point the rest is skipped, with nothing to skip the error stands
+        let mut bs = ByteStream::wrap(b"oops".to_vec());
+        assert_eq!(bs.apply(stmt()).unwrap(), Stmt::Error(0));
+        assert_eq!(bs.apply(stmt()).unwrap_err().offset(), 4);
+
+        let mut bs = ByteStream::with_capacity(16);
+        bs.put(b"oo");
+        bs.set_partial(true);
+        assert!(bs.apply(stmt()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+        bs.put(b"ps\n1\n");
+        assert_eq!(bs.apply(stmt()).unwrap(), Stmt::Error(0));
+        assert_eq!(bs.apply(stmt()).unwrap(), Stmt::Num(1));
+    }
+
+    #[test]
+    fn test_memoized() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let number = memoized(move |bs: &mut ByteStream| {
+            counter.set(counter.get() + 1);
+            decimal::<u32>().do_match(bs)
+        });
+        let sum = number.clone().then(single('+')).map(|(n, _)| n);
+        let diff = number.clone().then(single('-')).map(|(n, _)| n);
+        let mut bs = ByteStream::wrap(b"12-".to_vec());
+        assert_eq!(bs.apply(sum.or(diff)).unwrap(), 12);
+        assert_eq!(calls.get(), 1);
+
+        // another input drops the cache, failures are cached too
+        let mut bs = ByteStream::wrap(b"x".to_vec());
+        assert!(bs.apply(number.clone()).is_err());
+        assert!(bs.apply(number.clone()).is_err());
+        assert_eq!(calls.get(), 2);
+
+        // a growing partial stream is re-matched
+        let mut bs = ByteStream::with_capacity(8);
+        bs.set_partial(true);
+        bs.put(b"4");
+        assert!(bs.apply(number.clone()).unwrap_err().is_incomplete());
+        bs.put(b"2 ");
+        assert_eq!(bs.apply(number.clone()).unwrap(), 42);
+        assert_eq!(calls.get(), 4);
+
+        let number = number.capacity(0);
+        let mut bs = ByteStream::wrap(b"7".to_vec());
+        let start = bs.mark();
+        bs.apply(number.clone()).unwrap();
+        bs.reset(start);
+        bs.apply(number).unwrap();
+        assert_eq!(calls.get(), 6);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_choice() {
+        let plugins = || {
+            let mut alts: Vec<Box<dyn Matcher<String>>> = ["PING", "PONG"].iter().map(|k| string(*k).boxed()).collect();
+            alts.push(preceded(string("ECHO "), before('\n')).map(|v| String::from_utf8(v).unwrap()).boxed());
+            choice(alts)
+        };
+        assert_eq!(plugins().expected_set(), None);
+
+        let mut bs = ByteStream::wrap(b"PINGECHO hi\nECHO x".to_vec());
+        assert_eq!(bs.apply(plugins()).unwrap(), "PING");
+        assert_eq!(bs.apply(plugins()).unwrap(), "hi");
+        bs.advance(1);
+        // the echo branch got further than the ping one
+        let err = bs.apply(plugins()).unwrap_err();
+        assert_eq!(err.offset(), 17);
+        assert_eq!(bs.pos(), 12);
+
+        let m = choice(vec![string("a").boxed(), string("b").boxed()]);
+        let mut bs = ByteStream::wrap(b"c".to_vec());
+        assert!(bs.apply(m).unwrap_err().to_string().contains("one of: a, b"));
+        assert!(choice::<u8>(vec![]).do_match(&mut ByteStream::wrap(vec![])).is_err());
+    }
 
     #[test]
-    fn simple() {
-        #[derive(Debug, Eq, PartialEq)]
-        enum Token {
-            KV { k: char, v: char },
-        }
+    fn test_longest_of() {
+        let op = || longest_of(vec![
+            string("<").boxed(),
+            string("<=").boxed(),
+            string("<<").boxed(),
+            string("<<=").boxed(),
+        ]);
+        let mut bs = ByteStream::wrap(b"<= <<= < <<x".to_vec());
+        assert_eq!(bs.apply(op()).unwrap(), "<=");
+        assert_eq!(bs.pos(), 2);
+        bs.apply(single(' ')).unwrap();
+        assert_eq!(bs.apply(op()).unwrap(), "<<=");
+        bs.apply(single(' ')).unwrap();
+        assert_eq!(bs.apply(op()).unwrap(), "<");
+        bs.apply(single(' ')).unwrap();
+        assert_eq!(bs.apply(op()).unwrap(), "<<");
+        assert_eq!(bs.pos(), 11);
 
-        struct TokenBuilder {
-            k: Option<char>,
-            v: Option<char>,
+        let err = bs.apply(op()).unwrap_err();
+        assert_eq!(err.offset(), 11);
+        assert_eq!(bs.pos(), 11);
+    }
+
+    #[test]
+    fn test_spanned() {
+        let mut bs = ByteStream::wrap(b"GET /index.html".to_vec());
+        let m = token().spanned().then(single(' ')).then(maybe(string("/")).spanned());
+        let (((method, m_span), _), (slash, s_span)) = bs.apply(m).unwrap();
+        assert_eq!((method.as_str(), m_span), ("GET", 0..3));
+        assert_eq!((slash, s_span), (Some("/".to_string()), 4..5));
+
+        let (nothing, span) = bs.apply(maybe(single('#')).spanned()).unwrap();
+        assert_eq!((nothing, span), (None, 5..5));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Tree {
+        Leaf(u8),
+        Node(Vec<Tree>),
+    }
+
+    fn tree() -> impl Matcher<Tree> {
+        let leaf = get_u8().verify(|b| b.is_ascii_digit()).map(|b| Tree::Leaf(b - b'0'));
+        let node = delimited(single('['), separated_list(lazy(|| tree().boxed()), single(',')), single(']'))
+            .map(Tree::Node);
+        leaf.or(node)
+    }
+
+    #[test]
+    fn test_lazy() {
+        let mut bs = ByteStream::wrap(b"[1,[2,[]],3]".to_vec());
+        let expected = Tree::Node(vec![
+            Tree::Leaf(1),
+            Tree::Node(vec![Tree::Leaf(2), Tree::Node(vec![])]),
+            Tree::Leaf(3),
+        ]);
+        assert_eq!(bs.apply(tree()).unwrap(), expected);
+
+        let mut bs = ByteStream::wrap(b"[1,[2]".to_vec());
+        assert!(bs.apply(tree()).is_err());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn test_stateful() {
+        let mut seen = 0;
+        let numbered = stateful(move |bs: &mut ByteStream| {
+            let b = get_u8().do_match(bs)?;
+            seen += 1;
+            Ok((seen, b as char))
+        });
+        let mut bs = ByteStream::wrap(b"abc".to_vec());
+        assert_eq!(bs.apply(repeat(numbered)).unwrap(), vec![(1, 'a'), (2, 'b'), (3, 'c')]);
+
+        let mut total = 0u32;
+        let mut bs = ByteStream::wrap(b"\x01\x02\x03".to_vec());
+        let sum = stateful(|bs: &mut ByteStream| {
+            total += get_u8().do_match(bs)? as u32;
+            Ok(())
+        });
+        bs.apply(repeat(sum)).unwrap();
+        assert_eq!(total, 6);
+    }
+
+    #[test]
+    fn test_escaped_string() {
+        let mut bs = ByteStream::wrap(b"\"a\\tb\\\"c\\\\\" rest".to_vec());
+        assert_eq!(bs.apply(escaped_string('"', '\\', standard_escape())).unwrap(), "a\tb\"c\\");
+        assert_eq!(bs.as_ref(), b" rest");
+
+        // JSON adds \uXXXX on top of the standard escapes
+        let unicode = preceded(single('u'), bytes(4))
+            .try_map(|hex| u32::from_str_radix(&String::from_utf8_lossy(&hex), 16))
+            .try_map(|code| std::char::from_u32(code).ok_or("invalid code point"));
+        let json = escaped_string('"', '\\', standard_escape().or(unicode));
+        let mut bs = ByteStream::wrap("\"caf\\u00e9 \u{1F600}\"".as_bytes().to_vec());
+        assert_eq!(bs.apply(json).unwrap(), "caf\u{e9} \u{1F600}");
+
+        // CSV doubles the quote
+        let csv = || escaped_string('"', '"', single('"'));
+        let mut bs = ByteStream::wrap(b"\"say \"\"hi\"\"\",next".to_vec());
+        assert_eq!(bs.apply(csv()).unwrap(), "say \"hi\"");
+        assert_eq!(bs.as_ref(), b",next");
+
+        let mut bs = ByteStream::wrap(b"\"bad \\q\"".to_vec());
+        assert!(bs.apply(escaped_string('"', '\\', standard_escape())).is_err());
+        assert_eq!(bs.pos(), 0);
+        let mut bs = ByteStream::wrap(b"\"open".to_vec());
+        assert!(bs.apply(csv()).unwrap_err().is_incomplete());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum PortError {
+        Syntax(usize),
+        Privileged(u16),
+    }
+
+    impl From<MatchError> for PortError {
+        fn from(e: MatchError) -> Self {
+            PortError::Syntax(e.offset())
         }
+    }
 
-        impl TokenBuilder {
-            fn zero() -> TokenBuilder {
-                TokenBuilder { k: None, v: None }
+    #[test]
+    fn test_custom_error() {
+        let digits = many1(get_u8().verify(|b| b.is_ascii_digit()))
+            .try_map(|ds| String::from_utf8(ds).unwrap().parse::<u16>())
+            .map_err(PortError::from);
+        let unprivileged = |bs: &mut ByteStream| -> Result<u16, PortError> {
+            match digits.do_match(bs)? {
+                port if port < 1024 => Err(PortError::Privileged(port)),
+                port => Ok(port),
             }
+        };
+        let port = single(':').map_err(PortError::from).then(unprivileged).map(|(_, port)| port);
+
+        let mut bs = ByteStream::wrap(b":8080".to_vec());
+        assert_eq!(port.do_match(&mut bs), Ok(8080));
+        let mut bs = ByteStream::wrap(b":80".to_vec());
+        assert_eq!(port.do_match(&mut bs), Err(PortError::Privileged(80)));
+        let mut bs = ByteStream::wrap(b"80".to_vec());
+        assert_eq!(port.do_match(&mut bs), Err(PortError::Syntax(0)));
+
+        // and back, to use it with the MatchError based combinators
+        let port = port.map_err(|e| MatchError::custom(0, format!("{:?}", e)));
+        let mut bs = ByteStream::wrap(b":80:9000".to_vec());
+        assert_eq!(bs.apply(repeat(port)).unwrap(), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_tuple_sequence() {
+        let mut bs = ByteStream::wrap(b"GET /x HTTP/1.1\r\n".to_vec());
+        let line = (token(), single(' '), token(), single(' '), before('\r'), string("\r\n"));
+        let (method, _, path, _, protocol, _) = bs.apply(line).unwrap();
+        assert_eq!((method.as_str(), path.as_str()), ("GET", "/x"));
+        assert_eq!(protocol, b"HTTP/1.1");
+
+        let mut bs = ByteStream::wrap(vec![1, 0, 2, 3]);
+        assert_eq!(bs.apply((get_u8(), get_u16())).unwrap(), (1, 2));
+        assert!(bs.apply((get_u8(), get_u8())).is_err());
+    }
+
+    #[test]
+    fn test_little_endian() {
+        let mut bs = ByteStream::wrap(vec![1, 0, 2, 0, 0, 0, 0xfe, 0xff, 3, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bs.apply((get_u16_le(), get_u32_le(), get_i16_le())).unwrap(), (1, 2, -2));
+        assert!(bs.apply(get_u64_le()).unwrap_err().is_incomplete());
+        assert_eq!(bs.apply(get_i32_le()).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_floats() {
+        let mut bytes = 2.5f32.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&(-2.5f64).to_le_bytes());
+        bytes.extend_from_slice(&[0x7f, 0xc0, 0, 0]);
+        let mut bs = ByteStream::wrap(bytes);
+        assert_eq!(bs.apply((get_f32(), get_f64_le())).unwrap(), (2.5, -2.5));
+        assert!(bs.apply(get_f32()).unwrap().is_nan());
+        assert!(bs.apply(get_f64()).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_signed() {
+        let mut bs = ByteStream::wrap(vec![0x80, 0xff, 0x85, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(bs.apply((get_i8(), get_i16(), get_i32())).unwrap(), (-128, -123, -1));
+        assert!(bs.apply(get_i64()).unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn test_error_source() {
+        use std::error::Error;
+        let mut bs = ByteStream::wrap(b"99999 x".to_vec());
+        let err = bs.apply(token().try_map(|s| s.parse::<u8>())).unwrap_err();
+        let source = err.source().unwrap();
+        assert!(source.downcast_ref::<std::num::ParseIntError>().is_some());
+        assert_eq!(source.to_string(), "number too large to fit in target type");
+
+        let err = bs.apply(token().try_map(|_| Err::<u8, _>("nope"))).unwrap_err();
+        assert_eq!(err.source().unwrap().to_string(), "nope");
+        assert!(MatchError::custom(0, "x".to_string()).source().is_none());
+
+        let mut bs = ByteStream::wrap(b"\"\xff\"".to_vec());
+        let err = bs.apply(escaped_string('"', '\\', standard_escape())).unwrap_err();
+        assert!(err.source().unwrap().downcast_ref::<std::str::Utf8Error>().is_some());
+    }
+
+    #[test]
+    fn test_io_error() {
+        use std::io;
+        let mut bs = ByteStream::wrap(b"GE".to_vec());
+        let err: io::Error = bs.apply(string("GET")).unwrap_err().into();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(MatchError::from_io_ref(&err).unwrap().offset(), 2);
+        let back = MatchError::try_from_io(err).unwrap();
+        assert!(back.is_incomplete());
+
+        let err: io::Error = bs.apply(string("PUT")).unwrap_err().into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let other = io::Error::new(io::ErrorKind::NotFound, "boom");
+        assert!(MatchError::from_io_ref(&other).is_none());
+        assert_eq!(MatchError::try_from_io(other).unwrap_err().to_string(), "boom");
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut bs = ByteStream::with_capacity(64);
+        for n in [0u64, 1, 127, 128, 300, u32::MAX as u64, u64::MAX].iter() {
+            assert!(bs.put_varint(*n) > 0);
         }
+        assert_eq!(&bs.as_ref()[..6], &[0x00, 0x01, 0x7f, 0x80, 0x01, 0xac]);
+        let m = (varint_u32(), varint_u32(), varint_u32(), varint_u32(), varint_u32(), varint_u32(), varint_u64());
+        assert_eq!(bs.apply(m).unwrap(), (0, 1, 127, 128, 300, u32::MAX, u64::MAX));
 
-        let mut bs: ByteStream = "abc".to_string().into();
+        // u32::MAX + 1 does not fit
+        let mut bs = ByteStream::wrap(vec![0x80, 0x80, 0x80, 0x80, 0x10]);
+        assert!(bs.apply(varint_u32()).is_err());
+        assert_eq!(bs.pos(), 0);
+        assert_eq!(bs.apply(varint_u64()).unwrap(), 1 << 32);
 
-        let m = unit(|| TokenBuilder::zero())
-            .then_map(single('a'), |(tb, a)| TokenBuilder { k: Some(a), ..tb })
-            .then_map(single('b'), |(tb, b)| TokenBuilder { v: Some(b), ..tb })
-            .map(|tb| Token::KV {
-                k: tb.k.unwrap(),
-                v: tb.v.unwrap(),
-            });
+        let mut bs = ByteStream::wrap(vec![0xff; 11]);
+        assert!(bs.apply(varint_u64()).is_err());
+        let mut bs = ByteStream::wrap(vec![0x80, 0x80]);
+        assert!(bs.apply(varint_u64()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+    }
 
-        assert_eq!(bs.apply(m).unwrap(), Token::KV { k: 'a', v: 'b' });
+    #[test]
+    fn test_decimal() {
+        let mut bs = ByteStream::wrap(b"8\r\n-42,+7,".to_vec());
+        assert_eq!(bs.apply(decimal::<usize>()).unwrap(), 8);
+        bs.apply(string("\r\n")).unwrap();
+        assert_eq!(bs.apply(decimal::<i32>()).unwrap(), -42);
+        bs.apply(single(',')).unwrap();
+        assert_eq!(bs.apply(decimal::<u8>()).unwrap(), 7);
+        assert!(bs.apply(decimal::<u8>()).is_err());
+        assert_eq!(bs.pos(), 9);
+
+        let mut bs = ByteStream::wrap(b"256 -1 - ".to_vec());
+        let err = bs.apply(decimal::<u8>()).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Unexpected);
+        assert_eq!(bs.pos(), 0);
+        assert_eq!(bs.apply(decimal::<u16>()).unwrap(), 256);
+        bs.apply(single(' ')).unwrap();
+        assert!(bs.apply(decimal::<u32>()).is_err());
+        assert_eq!(bs.apply(decimal::<i64>()).unwrap(), -1);
+        bs.apply(single(' ')).unwrap();
+        assert!(bs.apply(decimal::<i64>()).is_err());
+        assert_eq!(bs.pos(), 7);
+
+        let mut bs = ByteStream::wrap(b"12".to_vec());
+        bs.set_partial(true);
+        assert!(bs.apply(decimal::<u64>()).unwrap_err().is_incomplete());
+        bs.set_partial(false);
+        assert_eq!(bs.apply(decimal::<u64>()).unwrap(), 12);
+    }
+
+    #[test]
+    fn test_error_code() {
+        let mut bs = ByteStream::wrap(b"ab".to_vec());
+        assert_eq!(bs.apply(single('x')).unwrap_err().code(), "UNEXPECTED");
+        assert_eq!(bs.apply(bytes(3)).unwrap_err().code(), "INCOMPLETE");
+        assert_eq!(bs.apply(before('x')).unwrap_err().code(), "NOT_FOUND");
+
+        let err = MatchError::custom(0, "bad".to_string()).with_code("INNER").with_code("OUTER");
+        assert_eq!(err.code(), "INNER");
+        assert_eq!(err.kind(), MatchErrorKind::Custom);
+    }
+
+    #[test]
+    fn test_float() {
+        let mut bs = ByteStream::wrap(b"2.75 -0.5e3 +2. .25E-2 1e x".to_vec());
+        let space = || single(' ');
+        assert_eq!(bs.apply(float::<f64>()).unwrap(), 2.75);
+        bs.apply(space()).unwrap();
+        assert_eq!(bs.apply(float::<f64>()).unwrap(), -500.0);
+        bs.apply(space()).unwrap();
+        assert_eq!(bs.apply(float::<f32>()).unwrap(), 2.0);
+        bs.apply(space()).unwrap();
+        assert_eq!(bs.apply(float::<f64>()).unwrap(), 0.0025);
+        bs.apply(space()).unwrap();
+        assert_eq!(bs.apply(float::<f64>()).unwrap(), 1.0);
+        assert_eq!(bs.apply(single('e')).unwrap(), 'e');
+        bs.apply(space()).unwrap();
+        assert!(bs.apply(float::<f64>()).is_err());
+        assert_eq!(bs.pos(), 26);
+
+        let mut bs = ByteStream::wrap(b"-.".to_vec());
+        assert!(bs.apply(float::<f64>()).is_err());
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(b"1.5e".to_vec());
+        bs.set_partial(true);
+        assert!(bs.apply(float::<f64>()).unwrap_err().is_incomplete());
+        bs.set_partial(false);
+        assert_eq!(bs.apply(float::<f64>()).unwrap(), 1.5);
+        assert_eq!(bs.pos(), 3);
+    }
+
+    #[test]
+    fn test_bom_and_newlines() {
+        let mut bs = ByteStream::wrap(b"\xEF\xBB\xBFa\r\nb\nc\rd".to_vec());
+        assert!(bs.apply(maybe_bom()).unwrap());
+        assert!(!bs.apply(maybe_bom()).unwrap());
+        let line = || terminated(get_u8(), newline());
+        assert_eq!(bs.apply(repeat(line())).unwrap(), vec![b'a', b'b', b'c']);
+        assert_eq!(bs.apply(get_u8()).unwrap(), b'd');
+        assert!(bs.apply(newline()).unwrap_err().is_incomplete());
+
+        let mut bs = ByteStream::wrap(b"\xEF\xBBx".to_vec());
+        assert!(bs.apply(bom()).is_err());
+        assert!(!bs.apply(maybe_bom()).unwrap());
+        assert_eq!(bs.pos(), 0);
+
+        let mut bs = ByteStream::wrap(b"\r".to_vec());
+        bs.set_partial(true);
+        assert!(bs.apply(newline()).unwrap_err().is_incomplete());
+        bs.set_partial(false);
+        assert!(bs.apply(newline()).is_ok());
+    }
+
+    #[test]
+    fn test_owned_patterns() {
+        let boundary = format!("--{}", "XyZ");
+        let mut bs = ByteStream::wrap(b"--XyZ--XyZ--XyZ!".to_vec());
+        assert_eq!(bs.apply(exact(boundary.clone().into_bytes())).unwrap(), b"--XyZ");
+        assert_eq!(bs.apply(string(boundary.clone())).unwrap(), "--XyZ");
+        assert_eq!(bs.apply(exact([b'-', b'-'])).unwrap(), b"--");
+        assert!(bs.apply(string(boundary)).is_err());
+        assert_eq!(bs.pos(), 12);
+    }
+
+    #[test]
+    fn test_expected_set() {
+        let method = || alt((string("GET"), string("POST"), string("PUT")));
+        assert_eq!(method().expected_set().unwrap(), vec!["GET", "POST", "PUT"]);
+        assert_eq!(method().map(|m| m.len()).context("method").expected_set().unwrap().len(), 3);
+        assert!(get_u8().expected_set().is_none());
+        assert!(string("a").or(get_u8().map(|_| "b".to_string())).expected_set().is_none());
+
+        let mut bs = ByteStream::wrap(b"DELETE".to_vec());
+        let err = bs.apply(method()).unwrap_err();
+        assert_eq!(err.offset(), 0);
+        assert!(err.to_string().contains("one of: GET, POST, PUT"), "{}", err);
+
+        // an alternative failing past the entry point keeps the last error as it is
+        let post = || (string("P"), string("OST")).map(|(p, ost)| p + &ost);
+        let mut patch = ByteStream::wrap(b"PATCH".to_vec());
+        let err = patch.apply(alt((post(), string("DELETE")))).unwrap_err();
+        assert!(!err.to_string().contains("one of"), "{}", err);
+        let err = patch.apply(post().or(string("DELETE"))).unwrap_err();
+        assert!(!err.to_string().contains("one of"), "{}", err);
+        let err = patch.apply(choice(vec![post().boxed(), string("DELETE").boxed()])).unwrap_err();
+        assert!(!err.to_string().contains("one of"), "{}", err);
+
+        let err = bs.apply(exact(b"\r\n").or(exact(b"\n"))).unwrap_err();
+        assert!(err.to_string().contains("got 'D'"), "{}", err);
+
+        // failing past the entry point keeps the error of the alternative
+        let mut bs = ByteStream::wrap(b"PUSH".to_vec());
+        let err = bs.apply((single('P'), method()).map(|(_, m)| m)).unwrap_err();
+        assert!(err.to_string().contains("one of"), "{}", err);
+        let mut bs = ByteStream::wrap(b"PUSH".to_vec());
+        let err = bs.apply(method()).unwrap_err();
+        assert!(!err.to_string().contains("one of"), "{}", err);
+    }
+
+    #[test]
+    fn test_one_of() {
+        let mut bs = ByteStream::wrap(b"+*a,b;".to_vec());
+        assert_eq!(bs.apply(repeat(one_of(b"+-*/"))).unwrap(), vec![b'+', b'*']);
+        let err = bs.apply(one_of(b"+-")).unwrap_err();
+        assert!(err.to_string().contains("expected 'one of '+-'' but got 'a'"), "{}", err);
         assert_eq!(bs.pos(), 2);
+        assert_eq!(bs.apply(repeat(none_of(b";,"))).unwrap(), vec![b'a']);
+        assert_eq!(bs.apply(one_of(b",")).unwrap(), b',');
+        assert_eq!(bs.apply(none_of(vec![b';'])).unwrap(), b'b');
+        assert!(bs.apply(none_of(b";")).is_err());
+        assert_eq!(bs.apply(one_of(";")).unwrap(), b';');
+        assert!(bs.apply(one_of(b";")).unwrap_err().is_incomplete());
+
+        let err = ByteStream::wrap(b"x".to_vec()).apply(one_of(b"+-").or(exact(b"**").map(|_| b'*'))).unwrap_err();
+        assert!(err.to_string().contains("one of: +, -, **"), "{}", err);
     }
 
     #[test]
-    fn list() {
-        let mut bs: ByteStream = "abccc".to_string().into();
+    fn test_quoted_string() {
+        let mut bs = ByteStream::wrap(b"\"a\\\"b\\n\\u\" \"caf\xC3\xA9\" \"open".to_vec());
+        assert_eq!(bs.apply(quoted_string()).unwrap_err().offset(), 8);
+        assert_eq!(bs.pos(), 0);
 
-        let c = single('c');
+        let mut bs = ByteStream::wrap(b"\"a\\\"b\\n\" \"caf\xC3\xA9\",\"open".to_vec());
+        assert_eq!(bs.apply(quoted_string()).unwrap(), "a\"b\n");
+        bs.apply(single(' ')).unwrap();
+        assert_eq!(bs.apply(quoted_string()).unwrap(), "caf\u{e9}");
+        bs.apply(single(',')).unwrap();
+        assert!(bs.apply(quoted_string()).unwrap_err().is_incomplete());
 
-        let abccc = unit(|| vec![])
-            .then_map(single('a'), |(acc, a)| {
-                let mut copy = acc.clone();
-                copy.push(a);
-                copy
-            })
-            .then_map(single('b'), |(acc, b)| {
-                let mut copy = acc.clone();
-                copy.push(b);
-                copy
-            })
-            .then_map(repeat(c), |(acc, vec)| {
-                let mut copy = acc;
-                for item in vec {
-                    copy.push(item);
+        // CSV-ish field: anything up to a comma, with ^ escaping the comma
+        let field = escaped(none_of(b",^"), '^', single(','));
+        let mut bs = ByteStream::wrap(b"1^,5,2".to_vec());
+        assert_eq!(bs.apply(field).unwrap(), "1,5");
+        assert_eq!(bs.pos(), 4);
+
+        // a non-ASCII escape is matched by its UTF-8 encoding, not by its low byte ('\'')
+        let field = escaped(none_of(b","), '\u{127}', single(','));
+        let mut bs = ByteStream::wrap("it's \u{127},ok,x".as_bytes().to_vec());
+        assert_eq!(bs.apply(field).unwrap(), "it's ,ok");
+    }
+
+    #[test]
+    fn test_try_save() {
+        let m = || unit(|| 0i64)
+            .then((string("len=").spanned(), decimal::<i64>()))
+            .try_save(|n, ((_, span), x)| {
+                if x < 0 {
+                    return Err(MatchError::unexpected(span.end, x.to_string(), "non-negative length".to_string()));
                 }
-                copy
-            });
+                *n = x;
+                Ok(())
+            })
+            .map(|n| n as usize);
 
-        assert_eq!(bs.apply(abccc).unwrap(), vec!['a', 'b', 'c', 'c', 'c']);
-        assert_eq!(bs.pos(), 5);
+        let mut bs = ByteStream::wrap(b"len=12".to_vec());
+        assert_eq!(bs.apply(m()).unwrap(), 12);
+
+        let mut bs = ByteStream::wrap(b"len=-1".to_vec());
+        let err = bs.apply(m()).unwrap_err();
+        assert_eq!(err.offset(), 4);
+        assert_eq!(bs.pos(), 0);
     }
 
     #[test]
-    fn until() {
-        let mut bs: ByteStream = "asdasdasdasd1".to_string().into();
+    fn test_whitespace() {
+        let mut bs = ByteStream::wrap(b"  a \t\nb".to_vec());
+        assert!(bs.apply(space1()).is_ok());
+        assert!(bs.apply(space1()).is_err());
+        assert!(bs.apply(space0()).is_ok());
+        assert_eq!(bs.apply(get_u8()).unwrap(), b'a');
+        bs.apply(hspace()).unwrap();
+        assert_eq!(bs.apply(newline()).unwrap(), ());
+        assert_eq!(bs.apply(get_u8()).unwrap(), b'b');
+        assert!(bs.apply(space0()).is_ok());
+        assert!(bs.apply(space1()).unwrap_err().is_incomplete());
 
-        let until1 = unit(|| ())
-            .then_map(before('1'), |(_, vec)| {
-                vec.into_iter().map(|b| b as char).collect::<String>()
-            })
-            .then_map(single('1'), |(vec, one)| (vec, one));
+        let word = || lexeme(many1(one_of(b"abcdefghijklmnopqrstuvwxyz")));
+        let mut bs = ByteStream::wrap(b" let \n x = 1".to_vec());
+        assert_eq!(bs.apply(word()).unwrap(), b"let");
+        assert_eq!(bs.apply(word()).unwrap(), b"x");
+        assert!(bs.apply(word()).is_err());
+        assert_eq!(bs.pos(), 9);
+        assert_eq!(bs.apply(lexeme(single('='))).unwrap(), '=');
 
-        assert_eq!(bs.apply(until1).unwrap(), ("asdasdasdasd".to_string(), '1'));
+        let mut bs = ByteStream::wrap(b"a \n".to_vec());
+        assert_eq!(bs.apply(lexeme_with(hspace(), single('a'))).unwrap(), 'a');
+        assert_eq!(bs.as_ref(), b"\n");
     }
 
     #[test]
-    fn chunks() {
-        let mut bs: ByteStream = "asdasdqqq123123token1 token2\n".to_string().into();
+    fn test_line() {
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"\xEF\xBB\xBF+OK\r\nplain\n\r\na\rb\npart\r");
+        assert_eq!(bs.apply(line()).unwrap(), b"+OK");
+        assert_eq!(bs.apply(line()).unwrap(), b"plain");
+        assert_eq!(bs.apply(line()).unwrap(), b"");
+        assert_eq!(bs.apply(line()).unwrap(), b"a");
+        assert_eq!(bs.apply(line()).unwrap(), b"b");
+        // "\r" may still be followed by "\n"
+        let pos = bs.pos();
+        assert!(bs.apply(line()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), pos);
 
-        let m = unit(|| vec![])
-            .then(exact("asd".as_bytes()))
-            .map(|(mut vec, bs)| {
-                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
-                vec
-            })
-            .then(exact("asdqqq".as_bytes()))
-            .map(|(mut vec, bs)| {
-                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
-                vec
-            })
-            .then(exact("123123".as_bytes()))
-            .map(|(mut vec, bs)| {
-                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
-                vec
+        bs.pull();
+        bs.extend(b"\n\xEF\xBB\xBFlast");
+        assert_eq!(bs.apply(line()).unwrap(), b"part");
+        assert!(bs.apply(line()).unwrap_err().is_incomplete());
+
+        // a byte order mark only counts at the very start of the stream
+        bs.set_partial(false);
+        assert_eq!(bs.apply(line()).unwrap(), b"\xEF\xBB\xBFlast");
+        assert!(bs.apply(line()).unwrap_err().is_incomplete());
+
+        let mut bs = ByteStream::wrap(b"\xEF\xBB\xBFonly".to_vec());
+        assert_eq!(bs.apply(line()).unwrap(), b"only");
+    }
+
+    #[test]
+    fn test_then_into() {
+        // the length-prefixed buffer moves into the continuation instead of being cloned
+        let m = || get_u8()
+            .then_with(|n| bytes(*n as usize))
+            .then_into(|(n, buf)| {
+                let len = buf.len();
+                ((n, buf), bytes(len))
             })
-            .then(token())
-            .map(|(mut vec, bs)| {
-                vec.push(bs);
-                vec
+            .map(|((_, head), tail)| (head, tail));
+        let mut bs = ByteStream::wrap(vec![2, 1, 2, 3, 4, 5]);
+        assert_eq!(bs.apply(m()).unwrap(), (vec![1, 2], vec![3, 4]));
+        assert_eq!(bs.remaining(), 1);
+
+        let mut bs = ByteStream::wrap(vec![2, 1, 2, 3]);
+        assert!(bs.apply(m()).is_err());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn test_repeat_until() {
+        let part = || preceded(exact(b"--b\r\n"), line());
+        let m = || repeat_until(part(), exact(b"--b--"));
+        let mut bs = ByteStream::wrap(b"--b\r\none\n--b\r\ntwo\n--b--tail".to_vec());
+        let (parts, _) = bs.apply(m()).unwrap();
+        assert_eq!(parts, vec![b"one".to_vec(), b"two".to_vec()]);
+        assert_eq!(bs.as_ref(), b"tail");
+
+        let mut bs = ByteStream::wrap(b"--b--".to_vec());
+        assert!(bs.apply(m()).unwrap().0.is_empty());
+
+        let mut bs = ByteStream::wrap(b"--b\r\none\n--x".to_vec());
+        assert!(bs.apply(m()).is_err());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn test_optional_or() {
+        let port = || preceded(single(':'), decimal::<u16>());
+        let m = || before_seq(b"/").then(optional_or(port(), 80)).then(maybe_default(rest()));
+        let mut bs = ByteStream::wrap(b"host/".to_vec());
+        assert_eq!(bs.apply(m()).unwrap(), ((b"host".to_vec(), 80), b"/".to_vec()));
+
+        let mut bs = ByteStream::wrap(b"host:8080".to_vec());
+        let m = before(':').then(optional_or(port(), 80)).then(maybe_default(line()));
+        assert_eq!(bs.apply(m).unwrap(), ((b"host".to_vec(), 8080), vec![]));
+
+        let mut bs = ByteStream::wrap(b":x".to_vec());
+        assert_eq!(bs.apply(optional_or(port(), 80)).unwrap(), 80);
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn test_value() {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Method {
+            Get,
+            Post,
+        }
+        let m = || value(Method::Get, string("GET")).or(string("POST").to(Method::Post));
+        let mut bs = ByteStream::wrap(b"POSTGET".to_vec());
+        assert_eq!(bs.apply(m()).unwrap(), Method::Post);
+        assert_eq!(bs.apply(m()).unwrap(), Method::Get);
+        assert_eq!(m().expected_set(), Some(vec!["GET".to_string(), "POST".to_string()]));
+    }
+
+    #[test]
+    fn test_and_then() {
+        // the opcode picks the message parser and only the message is returned
+        let m = || {
+            get_u8().and_then(|op| match op {
+                1 => get_u16().map(|n| n as u32).boxed(),
+                2 => get_u32().boxed(),
+                _ => (move |bs: &mut ByteStream| {
+                    Err(MatchError::unexpected(bs.pos() - 1, op.to_string(), "opcode".to_string()))
+                }).boxed(),
             })
-            .then(before('\n'))
-            .map(|(mut vec, bs)| {
-                vec.push(bs.into_iter().map(|b| b as char).collect::<String>());
-                vec
-            });
+        };
+        let mut bs = ByteStream::wrap(vec![1, 0, 7, 2, 0, 0, 1, 0, 9]);
+        assert_eq!(bs.apply(m()).unwrap(), 7);
+        assert_eq!(bs.apply(m()).unwrap(), 256);
+        assert_eq!(bs.apply(m()).unwrap_err().offset(), 8);
+    }
 
-        assert_eq!(
-            bs.apply(m).unwrap(),
-            vec![
-                "asd".to_string(),
-                "asdqqq".to_string(),
-                "123123".to_string(),
-                "token1".to_string(),
-                " token2".to_string(),
-            ]
-        );
+    #[test]
+    fn test_either() {
+        let m = || either(string("PING"), get_u16());
+        let mut bs = ByteStream::wrap(b"PING\x01\x02".to_vec());
+        assert_eq!(bs.apply(m()).unwrap(), Either::Left("PING".to_string()));
+        assert_eq!(bs.apply(m()).unwrap(), Either::Right(0x0102));
+        assert!(bs.apply(m()).unwrap_err().is_incomplete());
+
+        let m = alt_map(decimal::<u32>(), |n| n as usize, string("none"), |_| 0);
+        let mut bs = ByteStream::wrap(b"none".to_vec());
+        assert_eq!(bs.apply(m).unwrap(), 0);
     }
 
     #[test]
-    fn test_times_ok() {
-        let mut bs = ByteStream::wrap(b"012301230123".to_vec());
+    fn test_rest_and_eof() {
+        let mut bs = ByteStream::wrap(b"HTTP/1.0 200 OK\r\n\r\nbody".to_vec());
+        bs.apply(before_seq(b"\r\n\r\n")).unwrap();
+        bs.apply(exact(b"\r\n\r\n")).unwrap();
+        let err = bs.apply(eof()).unwrap_err();
+        assert!(err.to_string().contains("4 more bytes"), "{}", err);
+        assert_eq!(bs.apply(terminated(rest(), eof())).unwrap(), b"body");
+        assert_eq!(bs.apply(rest()).unwrap(), b"");
+        assert!(bs.apply(eof()).is_ok());
 
-        let m = unit(|| ())
-            .then(times(3, exact(b"0123")))
-            .map(|(_, times)| times);
+        let mut bs = ByteStream::with_capacity(8);
+        bs.set_partial(true);
+        bs.put(b"ab");
+        assert!(!bs.apply(eof()).unwrap_err().is_incomplete());
+        // more of the body may still arrive
+        assert!(bs.apply(rest()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+        bs.advance(2);
+        assert!(bs.apply(eof()).unwrap_err().is_incomplete());
+        bs.set_partial(false);
+        assert!(bs.apply(eof()).is_ok());
+    }
 
-        assert_eq!(
-            bs.apply(m).unwrap(),
-            vec![
-                b"0123",
-                b"0123",
-                b"0123",
-            ]
-        );
+    #[test]
+    fn test_length_value() {
+        let m = || length_value(get_u8(), (get_u16(), rest()));
+        let mut bs = ByteStream::wrap(vec![4, 0, 1, b'a', b'b', 2, 0, 2, 9]);
+        assert_eq!(bs.apply(m()).unwrap(), (1, b"ab".to_vec()));
+        assert_eq!(bs.apply(m()).unwrap(), (2, vec![]));
+        assert!(bs.apply(m()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 8);
+
+        // inner wants more than the prefix allows, or leaves bytes unread
+        let mut bs = ByteStream::wrap(vec![1, 0, 1]);
+        let err = bs.apply(length_value(get_u8(), get_u16())).unwrap_err();
+        assert!(err.is_incomplete());
+        assert_eq!(err.offset(), 1);
+        assert_eq!(err.context(), ["1 byte value"]);
+        assert_eq!(bs.pos(), 0);
+        let mut bs = ByteStream::wrap(vec![0, 3, 0, 1, 2]);
+        let err = bs.apply(length_value(get_u16(), get_u16())).unwrap_err();
+        assert_eq!(err.offset(), 4);
+        assert_eq!(bs.pos(), 0);
+
+        // the kind and code of the inner error survive
+        let mut bs = ByteStream::wrap(vec![9, 2, b'x', b'y']);
+        let err = bs.apply(preceded(get_u8(), length_value(get_u8(), exact(b"xz")))).unwrap_err();
+        assert_eq!(err.kind(), MatchErrorKind::Unexpected);
+        assert_eq!(err.offset(), 3);
+        assert!(err.to_string().starts_with("in 2 byte value: MatchError at offset 3 "), "{}", err);
+        let mut bs = ByteStream::wrap(vec![2, b'x', b'y']);
+        let err = bs.apply(length_value(get_u8(), limited(1, rest()))).unwrap_err();
+        assert_eq!(err.code(), LIMIT_EXCEEDED);
     }
 
     #[test]
-    fn test_times_mismatch() {
-        let mut bs = ByteStream::wrap(b"0123012301AA".to_vec());
+    fn test_checksummed() {
+        let sum = |bytes: &[u8], c: &u8| bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == *c;
+        let m = || checksummed(get_u8().then_with(|n| bytes(*n as usize)).map(|(_, body)| body), get_u8(), sum);
+        let mut bs = ByteStream::wrap(vec![2, 10, 20, 32, 1, 5, 7]);
+        assert_eq!(bs.apply(m()).unwrap(), vec![10, 20]);
+        let err = bs.apply(m()).unwrap_err();
+        assert_eq!(err.offset(), 6);
+        assert_eq!(bs.pos(), 4);
 
-        let m = unit(|| vec![])
-            .then(times(3, exact(b"0123")))
-            .save(|vec, times| *vec = times);
+        let mut bs = ByteStream::wrap(vec![1, 5]);
+        assert!(bs.apply(m()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn test_or() {
+        let mut bs = ByteStream::wrap(b"GEPOST".to_vec());
+
+        let m = string("GET").or(string("GE"));
+        assert_eq!(bs.apply(m).unwrap(), "GE");
+        assert_eq!(bs.pos(), 2);
+
+        let m = exact(b"PUT").or(exact(b"PATCH"));
+        assert!(bs.apply(m).is_err());
+        assert_eq!(bs.pos(), 2);
 
+        // the second branch consumes "PO" before failing
+        let m = exact(b"PUT").map(|_| ()).or(exact(b"PO").then(exact(b"X")).map(|_| ()));
         assert!(bs.apply(m).is_err());
+        assert_eq!(bs.pos(), 2);
     }
 
+    #[test]
+    fn test_alt() {
+        let mut bs = ByteStream::wrap(b"PUT".to_vec());
+
+        let method = || alt((string("GET"), string("POST"), string("PUT")));
+        assert_eq!(bs.apply(method()).unwrap(), "PUT");
+        assert!(bs.apply(method()).is_err());
+        assert_eq!(bs.pos(), 3);
+    }
 }
@@ -0,0 +1,168 @@
+// Git pkt-line framing: 4 lowercase hex digits of length (counting themselves) and the
+// payload; "0000" is a flush packet, "0001" a delimiter and "0002" a response end (v2).
+use crate::matcher::{MatchError, Matcher};
+use crate::stream::ByteStream;
+
+// largest packet, length prefix included
+pub const MAX_PKT_LEN: usize = 65520;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pkt {
+    Flush,
+    Delim,
+    ResponseEnd,
+    Data(Vec<u8>),
+}
+
+// nothing is consumed when the packet is not complete yet
+pub fn pkt_line() -> impl Matcher<Pkt> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        let head = match bs.as_ref().get(..4) {
+            Some(head) => head,
+            None => return Err(MatchError::incomplete(pos, 4 - bs.remaining())),
+        };
+        // digit by digit, as `from_str_radix` would also take a sign, e.g. "+00a"
+        let len = head.iter().try_fold(0, |acc, b| (*b as char).to_digit(16).map(|d| acc * 16 + d as usize));
+        let len = match len {
+            Some(len) => len,
+            None => {
+                let got = String::from_utf8_lossy(head).to_string();
+                return Err(MatchError::unexpected(pos, got, "4 hex digits of pkt-line length".to_string()));
+            }
+        };
+        let pkt = match len {
+            0 => Pkt::Flush,
+            1 => Pkt::Delim,
+            2 => Pkt::ResponseEnd,
+            3 => return Err(MatchError::unexpected(pos, "length 3".to_string(), "pkt-line length".to_string())),
+            n if n > MAX_PKT_LEN => return Err(MatchError::over_capacity(pos, MAX_PKT_LEN, n)),
+            n if n > bs.remaining() => return Err(MatchError::incomplete(pos, n - bs.remaining())),
+            n => {
+                bs.advance(4);
+                return Ok(Pkt::Data(bs.get(n - 4).unwrap_or_default()));
+            }
+        };
+        bs.advance(4);
+        Ok(pkt)
+    }
+}
+
+// 0 if the payload does not fit into a single packet
+pub fn put_pkt_line(out: &mut Vec<u8>, payload: &[u8]) -> usize {
+    let len = payload.len() + 4;
+    if len > MAX_PKT_LEN {
+        return 0;
+    }
+    out.extend_from_slice(format!("{:04x}", len).as_bytes());
+    out.extend_from_slice(payload);
+    len
+}
+
+pub fn put_flush(out: &mut Vec<u8>) {
+    out.extend_from_slice(b"0000");
+}
+
+// side-band(-64k) multiplexing: the first payload byte of a data packet selects the channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Band {
+    Data(Vec<u8>),
+    Progress(Vec<u8>),
+    Error(Vec<u8>),
+}
+
+impl Band {
+    pub fn demux(payload: &[u8]) -> Result<Band, MatchError> {
+        let rest = payload.get(1..).unwrap_or_default().to_vec();
+        match payload.first() {
+            Some(1) => Ok(Band::Data(rest)),
+            Some(2) => Ok(Band::Progress(rest)),
+            Some(3) => Ok(Band::Error(rest)),
+            Some(b) => Err(MatchError::unexpected(0, format!("band {}", b), "band 1, 2 or 3".to_string())),
+            None => Err(MatchError::unexpected(0, "empty packet".to_string(), "side-band channel".to_string())),
+        }
+    }
+}
+
+// packets up to (and including) the next flush, fails if the flush has not arrived yet
+pub fn pkt_section() -> impl Matcher<Vec<Pkt>> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let mut acc = vec![];
+        loop {
+            match pkt_line().do_match(bs) {
+                Ok(Pkt::Flush) => return Ok(acc),
+                Ok(pkt) => acc.push(pkt),
+                Err(e) => {
+                    bs.reset(mark);
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn parse_packets() {
+        let mut bs = ByteStream::wrap(b"0032want 0a53e9ddeaddad63ad106860237bbf53411d11a7\n000000010009done\n0002".to_vec());
+        let pkts = bs.apply(pkt_section()).unwrap();
+        assert_eq!(pkts, vec![Pkt::Data(b"want 0a53e9ddeaddad63ad106860237bbf53411d11a7\n".to_vec())]);
+        assert_eq!(bs.apply(pkt_line()).unwrap(), Pkt::Delim);
+        assert_eq!(bs.apply(pkt_line()).unwrap(), Pkt::Data(b"done\n".to_vec()));
+        assert_eq!(bs.apply(pkt_line()).unwrap(), Pkt::ResponseEnd);
+    }
+
+    #[test]
+    fn incomplete_and_invalid() {
+        let mut bs = ByteStream::wrap(b"000ahel".to_vec());
+        assert!(bs.apply(pkt_line()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+        let mut bs = ByteStream::wrap(b"00".to_vec());
+        assert!(bs.apply(pkt_line()).unwrap_err().is_incomplete());
+        let mut bs = ByteStream::wrap(b"0003".to_vec());
+        assert!(bs.apply(pkt_line()).is_err());
+        let mut bs = ByteStream::wrap(b"zz01".to_vec());
+        assert!(bs.apply(pkt_line()).is_err());
+        let mut bs = ByteStream::wrap(b"fff1".to_vec());
+        assert!(bs.apply(pkt_line()).is_err());
+        for head in [&b"+00a"[..], b"-00a", b" 00a"] {
+            let mut bs = ByteStream::wrap([head, b"hello!"].concat());
+            assert!(!bs.apply(pkt_line()).unwrap_err().is_incomplete());
+        }
+
+        let mut bs = ByteStream::wrap(b"0006a\n0006b\n".to_vec());
+        assert!(bs.apply(pkt_section()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn side_band() {
+        let mut out = vec![];
+        assert_eq!(put_pkt_line(&mut out, b"\x01PACK"), 9);
+        put_pkt_line(&mut out, b"\x02Counting objects: 3\r");
+        put_pkt_line(&mut out, b"\x03fatal");
+        put_flush(&mut out);
+        assert!(out.starts_with(b"0009\x01PACK"));
+        assert_eq!(put_pkt_line(&mut vec![], &[0u8; MAX_PKT_LEN]), 0);
+
+        let mut bs = ByteStream::wrap(out);
+        let bands = bs.apply(pkt_section()).unwrap().into_iter()
+            .map(|pkt| match pkt {
+                Pkt::Data(payload) => Band::demux(&payload).unwrap(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<Band>>();
+        assert_eq!(bands, vec![
+            Band::Data(b"PACK".to_vec()),
+            Band::Progress(b"Counting objects: 3\r".to_vec()),
+            Band::Error(b"fatal".to_vec()),
+        ]);
+        assert!(Band::demux(b"\x04x").is_err());
+        assert!(Band::demux(b"").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+// The only module allowed to contain unsafe code, everything else in the crate is
+// compiled with `deny(unsafe_code)`. With the `forbid-unsafe` feature each function
+// here falls back to a safe implementation and the crate is built with `forbid(unsafe_code)`.
+//
+// Invariants relied upon by the unsafe blocks:
+// - `align_to_mut::<u64>` is sound for any byte slice: every bit pattern is a valid u64
+//   and the aligned middle part never overlaps the unaligned prefix and suffix.
+
+// XOR the buffer with a repeating 4-byte mask (as used by websocket frames)
+pub fn xor_mask(buf: &mut [u8], mask: [u8; 4]) {
+    xor_words(buf, mask)
+}
+
+fn xor_bytes(buf: &mut [u8], mask: [u8; 4], offset: usize) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b ^= mask[(offset + i) % 4];
+    }
+}
+
+fn word(mask: [u8; 4], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = mask[(offset + i) % 4];
+    }
+    u64::from_ne_bytes(bytes)
+}
+
+#[cfg(not(feature = "forbid-unsafe"))]
+fn xor_words(buf: &mut [u8], mask: [u8; 4]) {
+    let (prefix, words, suffix) = unsafe { buf.align_to_mut::<u64>() };
+    xor_bytes(prefix, mask, 0);
+    let offset = prefix.len();
+    let m = word(mask, offset);
+    for w in words.iter_mut() {
+        *w ^= m;
+    }
+    xor_bytes(suffix, mask, offset + words.len() * 8);
+}
+
+#[cfg(feature = "forbid-unsafe")]
+fn xor_words(buf: &mut [u8], mask: [u8; 4]) {
+    use std::convert::TryInto;
+    let m = word(mask, 0);
+    let mut chunks = buf.chunks_exact_mut(8);
+    for chunk in &mut chunks {
+        let w = u64::from_ne_bytes((&*chunk).try_into().unwrap()) ^ m;
+        chunk.copy_from_slice(&w.to_ne_bytes());
+    }
+    let done = buf.len() - buf.len() % 8;
+    xor_bytes(&mut buf[done..], mask, done);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xor_mask_matches_bytewise() {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let data = (0..100u8).collect::<Vec<u8>>();
+        for start in 0..8 {
+            for end in start..data.len() {
+                let mut expected = data[start..end].to_vec();
+                xor_bytes(&mut expected, mask, 0);
+                let mut actual = data.clone();
+                xor_mask(&mut actual[start..end], mask);
+                assert_eq!(&actual[start..end], expected.as_slice(), "{}..{}", start, end);
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// RTSP 1.0 (RFC 2326) messages on top of `textmsg`.
+use crate::parser::MatchError;
+use crate::stream::ByteStream;
+use crate::textmsg::{parse_message, Dialect, Message};
+
+pub const RTSP: Dialect = Dialect {
+    name: "RTSP",
+    version: "RTSP/",
+    compact: &[],
+};
+
+pub fn parse_rtsp_message(stream: &mut ByteStream) -> Result<Message, MatchError> {
+    parse_message(RTSP, stream)
+}
+
+// sequence number pairing a response with its request
+pub fn cseq(msg: &Message) -> Option<u32> {
+    msg.header("CSeq").and_then(|v| v.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let text = "DESCRIBE rtsp://example.com/media.mp4 RTSP/1.0\r\nCSeq: 2\r\nAccept: application/sdp\r\n\r\n\
+            RTSP/1.0 200 OK\r\nCSeq: 2\r\nContent-Type: application/sdp\r\nContent-Length: 7\r\n\r\nv=0\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_rtsp_message(&mut bs).unwrap();
+        assert_eq!(req.method(), Some("DESCRIBE"));
+        assert_eq!(cseq(&req), Some(2));
+
+        let res = parse_rtsp_message(&mut bs).unwrap();
+        assert_eq!(res.code(), Some(200));
+        assert_eq!(cseq(&res), Some(2));
+        assert_eq!(res.content, b"v=0\r\n\r\n");
+
+        let mut bs: ByteStream = "GET / HTTP/1.1\r\n\r\n".to_string().into();
+        assert!(parse_rtsp_message(&mut bs).is_err());
+    }
+}
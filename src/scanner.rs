@@ -0,0 +1,95 @@
+// Searches a growing buffer for a delimiter without rescanning bytes already known
+// not to start a match. Meant for buffers that are appended at the tail as data arrives
+// and drained from the front as data is consumed (see `consumed`), so a delimiter split
+// across two reads is still found: the last `pattern.len() - 1` bytes are always rescanned.
+#[derive(Debug, Clone)]
+pub struct DelimiterScanner {
+    pattern: Vec<u8>,
+    scanned: usize,
+}
+
+impl DelimiterScanner {
+    pub fn new(pattern: &[u8]) -> DelimiterScanner {
+        assert!(!pattern.is_empty(), "delimiter must not be empty");
+        DelimiterScanner {
+            pattern: pattern.to_vec(),
+            scanned: 0,
+        }
+    }
+
+    pub fn pattern(&self) -> &[u8] {
+        &self.pattern
+    }
+
+    // index of the first occurrence of the delimiter in the buffer
+    pub fn find(&mut self, buf: &[u8]) -> Option<usize> {
+        let start = self.scanned.min(buf.len());
+        match buf[start..]
+            .windows(self.pattern.len())
+            .position(|w| w == self.pattern.as_slice())
+        {
+            Some(idx) => {
+                self.scanned = start + idx;
+                Some(start + idx)
+            }
+            None => {
+                self.scanned = self.safe_len(buf).max(start);
+                None
+            }
+        }
+    }
+
+    // number of leading bytes that can not be part of a (possibly split) delimiter,
+    // valid after `find` returned None
+    pub fn safe_len(&self, buf: &[u8]) -> usize {
+        buf.len().saturating_sub(self.pattern.len() - 1)
+    }
+
+    // must be called after dropping `n` bytes from the front of the buffer
+    pub fn consumed(&mut self, n: usize) {
+        self.scanned = self.scanned.saturating_sub(n);
+    }
+
+    pub fn reset(&mut self) {
+        self.scanned = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_delimiter() {
+        let mut scanner = DelimiterScanner::new(b"\r\n\r\n");
+        let mut buf = b"Host: a\r\n\r".to_vec();
+        assert_eq!(scanner.find(&buf), None);
+        assert_eq!(scanner.safe_len(&buf), 7);
+
+        buf.extend_from_slice(b"\nbody");
+        assert_eq!(scanner.find(&buf), Some(7));
+        assert_eq!(scanner.find(&buf), Some(7));
+    }
+
+    #[test]
+    fn consumed_front() {
+        let mut scanner = DelimiterScanner::new(b"--b");
+        let mut buf = b"aaaaaaa-".to_vec();
+        assert_eq!(scanner.find(&buf), None);
+
+        let n = scanner.safe_len(&buf);
+        buf.drain(..n);
+        scanner.consumed(n);
+        assert_eq!(buf, b"a-");
+
+        buf.extend_from_slice(b"-b");
+        assert_eq!(scanner.find(&buf), Some(1));
+
+        buf.drain(..4);
+        scanner.consumed(4);
+        assert_eq!(scanner.find(&buf), None);
+        scanner.reset();
+        buf.extend_from_slice(b"x--b");
+        assert_eq!(scanner.find(&buf), Some(1));
+    }
+}
@@ -0,0 +1,61 @@
+// SIP (RFC 3261) messages on top of `textmsg`, compact header names are expanded.
+use crate::parser::MatchError;
+use crate::stream::ByteStream;
+use crate::textmsg::{parse_message, Dialect, Message};
+
+// RFC 3261, section 7.3.3
+const COMPACT: &[(&str, &str)] = &[
+    ("i", "Call-ID"),
+    ("m", "Contact"),
+    ("e", "Content-Encoding"),
+    ("l", "Content-Length"),
+    ("c", "Content-Type"),
+    ("f", "From"),
+    ("s", "Subject"),
+    ("k", "Supported"),
+    ("t", "To"),
+    ("v", "Via"),
+];
+
+pub const SIP: Dialect = Dialect {
+    name: "SIP",
+    version: "SIP/",
+    compact: COMPACT,
+};
+
+pub fn parse_sip_message(stream: &mut ByteStream) -> Result<Message, MatchError> {
+    parse_message(SIP, stream)
+}
+
+// CSeq carries the method next to the number: "CSeq: 314159 INVITE"
+pub fn cseq(msg: &Message) -> Option<(u32, &str)> {
+    let mut parts = msg.header("CSeq")?.split_whitespace();
+    let n = parts.next()?.parse().ok()?;
+    Some((n, parts.next()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invite() {
+        let text = "INVITE sip:bob@biloxi.com SIP/2.0\r\n\
+            v: SIP/2.0/UDP pc33.atlanta.com;branch=z9hG4bK776asdhds\r\n\
+            t: Bob <sip:bob@biloxi.com>\r\n\
+            f: Alice <sip:alice@atlanta.com>;tag=1928301774\r\n\
+            i: a84b4c76e66710@pc33.atlanta.com\r\n\
+            CSeq: 314159 INVITE\r\n\
+            l: 4\r\n\r\nv=0\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let msg = parse_sip_message(&mut bs).unwrap();
+        assert_eq!(msg.method(), Some("INVITE"));
+        assert_eq!(msg.header("Call-ID"), Some("a84b4c76e66710@pc33.atlanta.com"));
+        assert_eq!(msg.header("To"), Some("Bob <sip:bob@biloxi.com>"));
+        assert_eq!(cseq(&msg), Some((314159, "INVITE")));
+        assert_eq!(msg.content, b"v=0\n");
+
+        let mut bs: ByteStream = "SIP/2.0 180 Ringing\r\nCSeq: 314159 INVITE\r\n\r\n".to_string().into();
+        assert_eq!(parse_sip_message(&mut bs).unwrap().code(), Some(180));
+    }
+}
@@ -1,3 +1,15 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static EPOCHS: AtomicU64 = AtomicU64::new(0);
+
+fn next_epoch() -> u64 {
+    EPOCHS.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Mark {
     pos: usize,
 }
@@ -6,17 +18,82 @@ pub struct Mark {
 pub struct ByteStream {
     buf: Vec<u8>,
     pos: usize,
+    partial: bool,
+    lines: bool,
+    // renewed whenever bytes already in the buffer change place, so that together with
+    // the length it identifies what a position refers to (see `matcher::memoized`)
+    epoch: u64,
+    // reads stop here while inside `within`
+    end: Option<usize>,
+    // line and column of the first byte in the buffer, moved by `pull` while tracking lines
+    origin: (usize, usize),
+    // whether `pull` dropped bytes from the front since the stream was created or cleared
+    pulled: bool,
 }
 
 impl ByteStream {
     pub fn wrap(buf: Vec<u8>) -> ByteStream {
-        ByteStream { buf, pos: 0 }
+        ByteStream { buf, pos: 0, partial: false, lines: false, epoch: next_epoch(), end: None, origin: (1, 1), pulled: false }
     }
 
     pub fn with_capacity(cap: usize) -> ByteStream {
         ByteStream {
             buf: Vec::with_capacity(cap),
             pos: 0,
+            partial: false,
+            lines: false,
+            epoch: next_epoch(),
+            end: None,
+            origin: (1, 1),
+            pulled: false,
+        }
+    }
+
+    // a partial stream holds only the input received so far: searches that hit the end
+    // of the buffer fail as Incomplete, and such failures are not backtracked over
+    pub fn set_partial(&mut self, partial: bool) {
+        self.partial = partial;
+    }
+
+    pub(crate) fn epoch(&self) -> (u64, usize) {
+        (self.epoch, self.len())
+    }
+
+    // runs `f` with the bytes from `end` on hidden, as if the buffer ended there; that end
+    // is final (not partial) when bytes past it were hidden
+    pub(crate) fn within<T, F: FnOnce(&mut ByteStream) -> T>(&mut self, end: usize, f: F) -> T {
+        let (outer, partial) = (self.end, self.partial);
+        if end < self.len() {
+            self.end = Some(end.max(self.pos));
+            self.partial = false;
+        }
+        let result = f(self);
+        self.end = outer;
+        self.partial = partial;
+        result
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+
+    // errors of parsers applied to a stream tracking lines carry the line and column
+    pub fn set_track_lines(&mut self, lines: bool) {
+        self.lines = lines;
+    }
+
+    pub fn tracks_lines(&self) -> bool {
+        self.lines
+    }
+
+    // 1-based line and column (in bytes) of an offset in the buffer; bytes dropped by `pull`
+    // while tracking lines still count, so the position is the one in the whole input
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let (line, col) = self.origin;
+        let head = &self.buf[..offset.min(self.buf.len())];
+        match head.iter().rposition(|b| *b == b'\n') {
+            Some(idx) => (line + head.iter().filter(|b| **b == b'\n').count(), offset - idx),
+            None => (line, col + offset),
         }
     }
 
@@ -24,8 +101,32 @@ impl ByteStream {
         self.pos
     }
 
+    // nothing has been read yet, e.g. where a byte order mark may be
+    pub(crate) fn at_start(&self) -> bool {
+        self.pos == 0 && !self.pulled
+    }
+
     pub fn len(&self) -> usize {
-        self.buf.len()
+        self.end.unwrap_or(self.buf.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // number of unread bytes, i.e. the length of `as_ref()`
+    pub fn remaining(&self) -> usize {
+        self.len() - self.pos
+    }
+
+    // skips `n` unread bytes without copying them, false (and no change) if there are fewer
+    pub fn advance(&mut self, n: usize) -> bool {
+        if n <= self.remaining() {
+            self.pos += n;
+            true
+        } else {
+            false
+        }
     }
 
     pub fn cap(&self) -> usize {
@@ -48,15 +149,27 @@ impl ByteStream {
     }
 
     pub fn put_u16(&mut self, b: u16) -> bool {
-        self.put(&write_u16(b)) == 16
+        self.put(&write_u16(b)) == 2
+    }
+
+    pub fn put_u16_le(&mut self, b: u16) -> bool {
+        self.put(&b.to_le_bytes()) == 2
     }
 
     pub fn put_u32(&mut self, b: u32) -> bool {
-        self.put(&write_u32(b)) == 32
+        self.put(&write_u32(b)) == 4
+    }
+
+    pub fn put_u32_le(&mut self, b: u32) -> bool {
+        self.put(&b.to_le_bytes()) == 4
     }
 
     pub fn put_u64(&mut self, b: u64) -> bool {
-        self.put(&write_u64(b)) == 64
+        self.put(&write_u64(b)) == 8
+    }
+
+    pub fn put_u64_le(&mut self, b: u64) -> bool {
+        self.put(&b.to_le_bytes()) == 8
     }
 
     pub fn put_16(&mut self, b: [u8; 16]) -> bool {
@@ -67,8 +180,98 @@ impl ByteStream {
         self.put(&b) == 32
     }
 
+    pub fn put_str(&mut self, s: &str) -> usize {
+        self.put(s.as_bytes())
+    }
+
+    // numbers as ASCII text without going through `format!`, 0 if capacity is insufficient
+    pub fn put_decimal_u64(&mut self, n: u64) -> usize {
+        self.put_radix(n, 10, 0)
+    }
+
+    pub fn put_decimal_padded(&mut self, n: u64, width: usize) -> usize {
+        self.put_radix(n, 10, width)
+    }
+
+    // lowercase, e.g. chunk sizes
+    pub fn put_hex(&mut self, n: u64) -> usize {
+        self.put_radix(n, 16, 0)
+    }
+
+    pub fn put_hex_padded(&mut self, n: u64, width: usize) -> usize {
+        self.put_radix(n, 16, width)
+    }
+
+    // unsigned LEB128, see `parser::varint_u64`; up to 10 bytes, 0 if capacity is insufficient
+    pub fn put_varint(&mut self, mut n: u64) -> usize {
+        let mut bytes = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let group = (n & 0x7F) as u8;
+            n >>= 7;
+            if n == 0 {
+                bytes[len] = group;
+                len += 1;
+                break;
+            }
+            bytes[len] = group | 0x80;
+            len += 1;
+        }
+        self.put(&bytes[..len])
+    }
+
+    fn put_radix(&mut self, mut n: u64, radix: u64, width: usize) -> usize {
+        // u64::MAX has 20 decimal digits
+        let mut digits = [0u8; 20];
+        let mut idx = digits.len();
+        loop {
+            idx -= 1;
+            digits[idx] = b"0123456789abcdef"[(n % radix) as usize];
+            n /= radix;
+            if n == 0 {
+                break;
+            }
+        }
+        let len = digits.len() - idx;
+        let pad = width.saturating_sub(len);
+        if self.cap() < pad + len {
+            return 0;
+        }
+        self.buf.resize(self.buf.len() + pad, b'0');
+        self.put(&digits[idx..]) + pad
+    }
+
+    pub fn put_crlf(&mut self) -> bool {
+        self.put(b"\r\n") == 2
+    }
+
+    pub fn put_line(&mut self, s: &str) -> usize {
+        if self.cap() >= s.len() + 2 {
+            self.put_str(s) + self.put(b"\r\n")
+        } else {
+            0
+        }
+    }
+
+    // formats directly into the buffer, nothing is written if capacity is not sufficient
+    pub fn put_fmt(&mut self, args: fmt::Arguments) -> usize {
+        let len = self.buf.len();
+        match fmt::Write::write_fmt(self, args) {
+            Ok(()) => self.buf.len() - len,
+            Err(_) => {
+                self.buf.truncate(len);
+                0
+            }
+        }
+    }
+
+    // bytes of the buffer in the range, read or not, e.g. a span returned by `Matcher::spanned`
+    pub fn slice(&self, range: Range<usize>) -> Option<&[u8]> {
+        self.buf[..self.len()].get(range)
+    }
+
     pub fn get(&mut self, n: usize) -> Option<Vec<u8>> {
-        if self.pos + n <= self.buf.len() {
+        if self.pos + n <= self.len() {
             let mut result = Vec::with_capacity(n);
             let offset = self.pos;
             for i in offset..(offset + n) {
@@ -89,14 +292,58 @@ impl ByteStream {
         self.get(2).map(|v| read_u16(&v))
     }
 
+    pub fn get_u16_le(&mut self) -> Option<u16> {
+        self.get(2).map(|v| read_u16_le(&v))
+    }
+
     pub fn get_u32(&mut self) -> Option<u32> {
         self.get(4).map(|v| read_u32(&v))
     }
 
+    pub fn get_u32_le(&mut self) -> Option<u32> {
+        self.get(4).map(|v| read_u32_le(&v))
+    }
+
     pub fn get_u64(&mut self) -> Option<u64> {
         self.get(8).map(|v| read_u64(&v))
     }
 
+    pub fn get_u64_le(&mut self) -> Option<u64> {
+        self.get(8).map(|v| read_u64_le(&v))
+    }
+
+    pub fn get_i8(&mut self) -> Option<i8> {
+        self.get_u8().map(|x| x as i8)
+    }
+
+    pub fn get_i16(&mut self) -> Option<i16> {
+        self.get_u16().map(|x| x as i16)
+    }
+
+    pub fn get_i32(&mut self) -> Option<i32> {
+        self.get_u32().map(|x| x as i32)
+    }
+
+    pub fn get_i64(&mut self) -> Option<i64> {
+        self.get_u64().map(|x| x as i64)
+    }
+
+    pub fn get_f32(&mut self) -> Option<f32> {
+        self.get_u32().map(f32::from_bits)
+    }
+
+    pub fn get_f64(&mut self) -> Option<f64> {
+        self.get_u64().map(f64::from_bits)
+    }
+
+    pub fn get_f32_le(&mut self) -> Option<f32> {
+        self.get_u32_le().map(f32::from_bits)
+    }
+
+    pub fn get_f64_le(&mut self) -> Option<f64> {
+        self.get_u64_le().map(f64::from_bits)
+    }
+
     pub fn get_16(&mut self) -> Option<[u8; 16]> {
         self.get(16)
             .map(|v| {
@@ -119,6 +366,7 @@ impl ByteStream {
             })
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<u8> {
         self.get(1).map(|ref v| v[0])
     }
@@ -136,38 +384,56 @@ impl ByteStream {
     pub fn clear(&mut self) {
         self.pos = 0;
         self.buf.clear();
+        self.epoch = next_epoch();
+        self.origin = (1, 1);
+        self.pulled = false;
     }
 
     // drop bytes before current read position, allows more bytes to be put into the buffer
     pub fn pull(&mut self) {
-        if self.pos > 0 && self.len() > 0 {
+        if self.pos > 0 && !self.is_empty() {
+            if self.lines {
+                self.origin = self.line_col(self.pos);
+            }
             let len = self.pos;
             let mut buf = Vec::with_capacity(self.buf.capacity());
             buf.append(&mut self.buf[len..].to_vec());
             self.buf = buf;
             self.pos = 0;
+            self.epoch = next_epoch();
+            self.pulled = true;
         }
     }
 
     // find index of a first byte that matches predicate
     pub fn find_single<F: Fn(&u8) -> bool>(&self, f: F) -> Option<usize> {
-        self.buf[self.pos..]
+        self.as_ref()
             .iter()
             .position(f)
             .map(|idx| idx + self.pos)
     }
 
     pub fn find_window<F: Fn(&[u8]) -> bool>(&self, w: usize, f: F) -> Option<usize> {
-        self.buf[self.pos..]
+        self.as_ref()
             .windows(w)
             .position(f)
             .map(|idx| idx + self.pos)
     }
 }
 
+impl fmt::Write for ByteStream {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.put_str(s) == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
 impl AsRef<[u8]> for ByteStream {
     fn as_ref(&self) -> &[u8] {
-        &self.buf[self.pos..]
+        &self.buf[self.pos..self.len()]
     }
 }
 
@@ -177,18 +443,61 @@ impl From<String> for ByteStream {
     }
 }
 
+impl From<Vec<u8>> for ByteStream {
+    fn from(buf: Vec<u8>) -> Self {
+        ByteStream::wrap(buf)
+    }
+}
+
+impl From<&[u8]> for ByteStream {
+    fn from(buf: &[u8]) -> Self {
+        ByteStream::wrap(buf.to_vec())
+    }
+}
+
+impl FromIterator<u8> for ByteStream {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        ByteStream::wrap(iter.into_iter().collect())
+    }
+}
+
+// unlike `put`, extending grows the buffer past its capacity as needed
+impl Extend<u8> for ByteStream {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        self.buf.extend(iter)
+    }
+}
+
+impl<'a> Extend<&'a u8> for ByteStream {
+    fn extend<I: IntoIterator<Item = &'a u8>>(&mut self, iter: I) {
+        self.buf.extend(iter)
+    }
+}
+
 fn read_u16(v: &[u8]) -> u16 {
     v[0..2].iter().fold(0u16, |acc, b| (acc << 8) + (*b as u16))
 }
 
+fn read_u16_le(v: &[u8]) -> u16 {
+    v[0..2].iter().rev().fold(0u16, |acc, b| (acc << 8) + (*b as u16))
+}
+
 fn read_u32(v: &[u8]) -> u32 {
     v[0..4].iter().fold(0u32, |acc, b| (acc << 8) + (*b as u32))
 }
 
+fn read_u32_le(v: &[u8]) -> u32 {
+    v[0..4].iter().rev().fold(0u32, |acc, b| (acc << 8) + (*b as u32))
+}
+
 fn read_u64(v: &[u8]) -> u64 {
     v[0..8].iter().fold(0u64, |acc, b| (acc << 8) + (*b as u64))
 }
 
+fn read_u64_le(v: &[u8]) -> u64 {
+    v[0..8].iter().rev().fold(0u64, |acc, b| (acc << 8) + (*b as u64))
+}
+
 fn write_u16(mut b: u16) -> [u8; 2] {
     let mut r = [0u8; 2];
     for i in (0..2).rev() {
@@ -244,6 +553,151 @@ mod tests {
         QuickCheck::new().quickcheck(f as fn(u32) -> bool);
     }
 
+    #[test]
+    fn test_little_endian() {
+        let mut bs = ByteStream::with_capacity(14);
+        assert!(bs.put_u16_le(0x0102));
+        assert!(bs.put_u32_le(0x0304_0506));
+        assert!(bs.put_u64_le(0x0708_090a_0b0c_0d0e));
+        assert!(!bs.put_u16_le(1));
+        assert_eq!(bs.as_ref(), [2, 1, 6, 5, 4, 3, 14, 13, 12, 11, 10, 9, 8, 7]);
+
+        assert_eq!(bs.get_u16_le(), Some(0x0102));
+        assert_eq!(bs.get_u32_le(), Some(0x0304_0506));
+        assert_eq!(bs.get_u64_le(), Some(0x0708_090a_0b0c_0d0e));
+        assert_eq!(bs.get_u16_le(), None);
+
+        let mut bs = ByteStream::with_capacity(14);
+        assert!(bs.put_u16(1) && bs.put_u32(2) && bs.put_u64(3));
+        assert_eq!((bs.get_u16(), bs.get_u32(), bs.get_u64()), (Some(1), Some(2), Some(3)));
+    }
+
+    #[test]
+    fn test_floats() {
+        let mut bs = ByteStream::with_capacity(24);
+        bs.put(&1.5f32.to_be_bytes());
+        bs.put(&(-0.1f64).to_be_bytes());
+        bs.put(&f32::INFINITY.to_le_bytes());
+        bs.put(&1e300f64.to_le_bytes());
+        assert_eq!(bs.get_f32(), Some(1.5));
+        assert_eq!(bs.get_f64(), Some(-0.1));
+        assert_eq!(bs.get_f32_le(), Some(f32::INFINITY));
+        assert_eq!(bs.get_f64_le(), Some(1e300));
+        assert_eq!(bs.get_f32(), None);
+    }
+
+    #[test]
+    fn test_from_iter() {
+        let mut bs = (b'a'..=b'c').collect::<ByteStream>();
+        assert_eq!(bs.as_ref(), b"abc");
+        assert_eq!(bs.next(), Some(b'a'));
+        bs.extend(std::io::Read::bytes(&b"de"[..]).flatten());
+        bs.extend(b"f");
+        assert_eq!(bs.as_ref(), b"bcdef");
+
+        let bs: ByteStream = (&b"xyz"[..]).into();
+        assert_eq!(bs.len(), 3);
+        let bs = ByteStream::from(vec![1, 2]);
+        assert_eq!(bs.as_ref(), &[1, 2]);
+    }
+
+    #[test]
+    fn test_signed() {
+        let mut bs = ByteStream::wrap(vec![0xff, 0xff, 0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bs.get_i8(), Some(-1));
+        assert_eq!(bs.get_i16(), Some(-2));
+        assert_eq!(bs.get_i32(), Some(i32::MIN));
+        assert_eq!(bs.get_i64(), Some(1));
+        assert_eq!(bs.get_i8(), None);
+    }
+
+    #[test]
+    fn test_line_col() {
+        let bs = ByteStream::wrap(b"ab\ncd\n\nx".to_vec());
+        assert_eq!(bs.line_col(0), (1, 1));
+        assert_eq!(bs.line_col(2), (1, 3));
+        assert_eq!(bs.line_col(3), (2, 1));
+        assert_eq!(bs.line_col(4), (2, 2));
+        assert_eq!(bs.line_col(7), (4, 1));
+        assert_eq!(bs.line_col(8), (4, 2));
+    }
+
+    #[test]
+    fn test_line_col_after_pull() {
+        let mut bs = ByteStream::wrap(b"ab\ncd ef".to_vec());
+        bs.set_track_lines(true);
+        bs.get(5);
+        bs.pull();
+        assert_eq!(bs.line_col(0), (2, 3));
+        assert_eq!(bs.line_col(2), (2, 5));
+        bs.put(b"\nx");
+        bs.get(2);
+        bs.pull();
+        assert_eq!(bs.line_col(2), (3, 1));
+
+        bs.clear();
+        assert_eq!(bs.line_col(0), (1, 1));
+    }
+
+    #[test]
+    fn test_advance() {
+        let mut bs = ByteStream::wrap(b"GET / HTTP/1.1".to_vec());
+        assert_eq!(bs.remaining(), 14);
+        let n = bs.as_ref().iter().position(|b| *b == b' ').unwrap();
+        assert!(bs.advance(n + 1));
+        assert_eq!(bs.as_ref(), b"/ HTTP/1.1");
+        assert_eq!(bs.remaining(), 10);
+
+        assert!(!bs.advance(11));
+        assert_eq!(bs.pos(), 4);
+        assert!(bs.advance(10));
+        assert_eq!(bs.remaining(), 0);
+        assert!(bs.advance(0));
+    }
+
+    #[test]
+    fn test_put_numbers() {
+        let mut bs = ByteStream::with_capacity(64);
+        assert_eq!(bs.put_decimal_u64(0), 1);
+        bs.put_u8(b' ');
+        assert_eq!(bs.put_decimal_u64(u64::MAX), 20);
+        bs.put_u8(b' ');
+        assert_eq!(bs.put_hex(0x1f2e), 4);
+        bs.put_u8(b' ');
+        assert_eq!(bs.put_hex_padded(0xab, 4), 4);
+        bs.put_u8(b' ');
+        assert_eq!(bs.put_decimal_padded(1234, 2), 4);
+        bs.put_u8(b' ');
+        assert_eq!(bs.put_decimal_padded(7, 3), 3);
+        assert_eq!(bs.as_ref(), b"0 18446744073709551615 1f2e 00ab 1234 007");
+
+        let mut bs = ByteStream::with_capacity(3);
+        assert_eq!(bs.put_hex_padded(1, 4), 0);
+        assert_eq!(bs.put_decimal_u64(1000), 0);
+        assert_eq!(bs.put_decimal_u64(999), 3);
+        assert_eq!(bs.as_ref(), b"999");
+    }
+
+    #[test]
+    fn test_put_text() {
+        let mut bs = ByteStream::with_capacity(64);
+        assert_eq!(bs.put_line("HTTP/1.1 200 OK"), 17);
+        assert_eq!(bs.put_fmt(format_args!("{}: {}", "Content-Length", 5)), 17);
+        assert!(bs.put_crlf());
+        assert!(bs.put_crlf());
+        assert_eq!(bs.put_str("hello"), 5);
+        assert_eq!(bs.as_ref(), b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn test_put_fmt_over_capacity() {
+        let mut bs = ByteStream::with_capacity(8);
+        assert_eq!(bs.put_str("abc"), 3);
+        assert_eq!(bs.put_fmt(format_args!("{}-{}", "defgh", "ijk")), 0);
+        assert_eq!(bs.put_line("defghijk"), 0);
+        assert_eq!(bs.as_ref(), b"abc");
+    }
+
     #[test]
     fn test_u16() {
         fn f(x: u16) -> bool {
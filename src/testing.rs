@@ -0,0 +1,347 @@
+use crate::ascii::eq_ignore_case;
+use crate::http::{as_string, Header, Request};
+use crate::stream::ByteStream;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Compare {
+    // headers are compared as a multiset instead of an ordered list
+    pub ignore_header_order: bool,
+    // header names are compared ignoring ASCII case (values are always exact)
+    pub ignore_header_case: bool,
+}
+
+impl Default for Compare {
+    fn default() -> Self {
+        Compare {
+            ignore_header_order: true,
+            ignore_header_case: false,
+        }
+    }
+}
+
+impl Compare {
+    pub fn strict() -> Compare {
+        Compare {
+            ignore_header_order: false,
+            ignore_header_case: false,
+        }
+    }
+
+    pub fn lenient() -> Compare {
+        Compare {
+            ignore_header_order: true,
+            ignore_header_case: true,
+        }
+    }
+
+    fn same_header(&self, a: &Header, b: &Header) -> bool {
+        let name = if self.ignore_header_case {
+            eq_ignore_case(&a.name, &b.name)
+        } else {
+            a.name == b.name
+        };
+        name && a.value == b.value
+    }
+}
+
+// field-by-field differences between two requests, empty when they are equal
+pub fn diff_requests(a: &Request, b: &Request, cmp: Compare) -> Vec<String> {
+    let mut out = vec![];
+    if a.method != b.method {
+        out.push(format!("method: {:?} != {:?}", a.method, b.method));
+    }
+    if a.path != b.path {
+        out.push(format!("path: {:?} != {:?}", a.path, b.path));
+    }
+    if a.protocol != b.protocol {
+        out.push(format!("protocol: {:?} != {:?}", a.protocol, b.protocol));
+    }
+    if cmp.ignore_header_order {
+        diff_header_sets(&a.headers, &b.headers, cmp, &mut out);
+    } else {
+        diff_header_lists(&a.headers, &b.headers, cmp, &mut out);
+    }
+    if let Some(line) = diff_content(&a.content, &b.content) {
+        out.push(line);
+    }
+    out
+}
+
+fn diff_header_lists(a: &[Header], b: &[Header], cmp: Compare, out: &mut Vec<String>) {
+    for i in 0..a.len().max(b.len()) {
+        match (a.get(i), b.get(i)) {
+            (Some(x), Some(y)) if cmp.same_header(x, y) => (),
+            (Some(x), Some(y)) => out.push(format!(
+                "header #{}: {:?}: {:?} != {:?}: {:?}",
+                i, x.name, x.value, y.name, y.value
+            )),
+            (Some(x), None) => out.push(format!("header #{} only on left: {:?}: {:?}", i, x.name, x.value)),
+            (None, Some(y)) => out.push(format!("header #{} only on right: {:?}: {:?}", i, y.name, y.value)),
+            (None, None) => (),
+        }
+    }
+}
+
+fn diff_header_sets(a: &[Header], b: &[Header], cmp: Compare, out: &mut Vec<String>) {
+    let mut unmatched: Vec<&Header> = b.iter().collect();
+    for x in a {
+        match unmatched.iter().position(|y| cmp.same_header(x, y)) {
+            Some(idx) => {
+                unmatched.remove(idx);
+            }
+            None => out.push(format!("header only on left: {:?}: {:?}", x.name, x.value)),
+        }
+    }
+    for y in unmatched {
+        out.push(format!("header only on right: {:?}: {:?}", y.name, y.value));
+    }
+}
+
+fn diff_content(a: &[u8], b: &[u8]) -> Option<String> {
+    if a == b {
+        return None;
+    }
+    let at = a.iter()
+        .zip(b.iter())
+        .position(|(x, y)| x != y)
+        .unwrap_or_else(|| a.len().min(b.len()));
+    Some(format!(
+        "content: {} bytes != {} bytes, first difference at offset {}: {:?} != {:?}",
+        a.len(),
+        b.len(),
+        at,
+        as_string(a[at..].iter().take(16).cloned().collect()),
+        as_string(b[at..].iter().take(16).cloned().collect()),
+    ))
+}
+
+pub fn assert_request_eq(a: &Request, b: &Request) {
+    assert_request_eq_with(a, b, Compare::default())
+}
+
+pub fn assert_request_eq_with(a: &Request, b: &Request, cmp: Compare) {
+    let diff = diff_requests(a, b, cmp);
+    if !diff.is_empty() {
+        panic!("requests differ:\n  {}", diff.join("\n  "));
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+// one end of an in-memory socket pair: what one end writes the other reads, with
+// the size of each read limited to the chunk size and reads that can be stalled,
+// a read with nothing buffered fails with WouldBlock, or returns 0 after the peer shut down
+pub struct FakeConn {
+    rx: Rc<RefCell<Pipe>>,
+    tx: Rc<RefCell<Pipe>>,
+    chunk: usize,
+    stalls: usize,
+}
+
+impl FakeConn {
+    pub fn pair() -> (FakeConn, FakeConn) {
+        let a = Rc::new(RefCell::new(Pipe::default()));
+        let b = Rc::new(RefCell::new(Pipe::default()));
+        let left = FakeConn {
+            rx: a.clone(),
+            tx: b.clone(),
+            chunk: usize::MAX,
+            stalls: 0,
+        };
+        let right = FakeConn {
+            rx: b,
+            tx: a,
+            chunk: usize::MAX,
+            stalls: 0,
+        };
+        (left, right)
+    }
+
+    pub fn with_chunk_size(mut self, chunk: usize) -> FakeConn {
+        self.set_chunk_size(chunk);
+        self
+    }
+
+    pub fn set_chunk_size(&mut self, chunk: usize) {
+        assert!(chunk > 0, "chunk size must be positive");
+        self.chunk = chunk;
+    }
+
+    // the next `reads` reads fail with WouldBlock even if data is buffered
+    pub fn stall(&mut self, reads: usize) {
+        self.stalls += reads;
+    }
+
+    // half-close: the peer reads the remaining data and then EOF, this end can still read
+    pub fn shutdown_write(&mut self) {
+        self.tx.borrow_mut().closed = true;
+    }
+
+    // bytes written by the peer and not read yet
+    pub fn pending(&self) -> usize {
+        self.rx.borrow().data.len()
+    }
+
+    // reads one chunk into the stream (bounded by its capacity)
+    pub fn read_into(&mut self, bs: &mut ByteStream) -> io::Result<usize> {
+        let mut buf = vec![0u8; bs.cap().min(self.chunk).min(64 * 1024)];
+        let n = io::Read::read(self, &mut buf)?;
+        bs.put(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl io::Read for FakeConn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stalls > 0 {
+            self.stalls -= 1;
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let mut pipe = self.rx.borrow_mut();
+        if pipe.data.is_empty() {
+            return if pipe.closed {
+                Ok(0)
+            } else {
+                Err(io::ErrorKind::WouldBlock.into())
+            };
+        }
+        let n = buf.len().min(self.chunk).min(pipe.data.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.data.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl io::Write for FakeConn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut pipe = self.tx.borrow_mut();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.data.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::parse_http_request;
+    use crate::stream::ByteStream;
+
+    fn parse(text: &str) -> Request {
+        let mut bs: ByteStream = text.to_string().into();
+        parse_http_request(&mut bs).unwrap()
+    }
+
+    #[test]
+    fn equal_requests() {
+        let a = parse("GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n");
+        let b = parse("GET / HTTP/1.1\r\nAccept: */*\r\nHost: a\r\n\r\n");
+        assert_request_eq(&a, &b);
+        assert!(!diff_requests(&a, &b, Compare::strict()).is_empty());
+
+        let c = parse("GET / HTTP/1.1\r\nhost: a\r\naccept: */*\r\n\r\n");
+        assert_request_eq_with(&a, &c, Compare::lenient());
+    }
+
+    #[test]
+    fn diff_fields() {
+        let a = parse("GET /a HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc");
+        let b = parse("POST /a HTTP/1.1\r\nHost: b\r\nContent-Length: 3\r\n\r\nabd");
+        let diff = diff_requests(&a, &b, Compare::default());
+        assert_eq!(
+            diff,
+            vec![
+                "method: \"GET\" != \"POST\"".to_string(),
+                "header only on left: \"Host\": \"a\"".to_string(),
+                "header only on right: \"Host\": \"b\"".to_string(),
+                "content: 3 bytes != 3 bytes, first difference at offset 2: \"c\" != \"d\"".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "requests differ")]
+    fn assert_panics() {
+        let a = parse("GET / HTTP/1.1\r\n\r\n");
+        let b = parse("GET / HTTP/1.0\r\n\r\n");
+        assert_request_eq(&a, &b);
+    }
+
+    // reads chunk by chunk until the parser stops asking for more input
+    fn read_request(conn: &mut FakeConn, bs: &mut ByteStream) -> io::Result<Request> {
+        loop {
+            match crate::http::try_parse_http_request(bs) {
+                Ok(req) => return Ok(req),
+                Err(e) if e.is_incomplete() => {}
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            }
+            match conn.read_into(bs) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    #[test]
+    fn fake_conn_keep_alive() {
+        use std::io::Write;
+        let (mut client, server) = FakeConn::pair();
+        let mut server = server.with_chunk_size(5);
+        client.write_all(b"GET /a HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+        client.write_all(b"POST /b HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi").unwrap();
+        client.shutdown_write();
+        server.stall(3);
+
+        let mut bs = ByteStream::with_capacity(1024);
+        bs.set_partial(true);
+        let first = read_request(&mut server, &mut bs).unwrap();
+        assert_eq!(first.path, "/a");
+        let second = read_request(&mut server, &mut bs).unwrap();
+        assert_eq!((second.path.as_str(), second.content.as_slice()), ("/b", &b"hi"[..]));
+        assert_eq!(server.pending(), 0);
+
+        let err = read_request(&mut server, &mut bs).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(client.write(b"x").is_err());
+    }
+
+    #[test]
+    fn fake_conn_upgrade() {
+        use crate::ws::parse_frame;
+        use std::io::Write;
+        let (mut client, mut server) = FakeConn::pair();
+        client.write_all(b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n").unwrap();
+        client.write_all(&[129, 134, 87, 35, 230, 82, 63, 70, 138, 62, 56, 2]).unwrap();
+        server.set_chunk_size(7);
+
+        let mut bs = ByteStream::with_capacity(1024);
+        bs.set_partial(true);
+        let req = read_request(&mut server, &mut bs).unwrap();
+        assert_eq!(req.header("Upgrade"), Some("websocket"));
+
+        server.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n").unwrap();
+        assert_eq!(client.pending(), 36);
+
+        while server.read_into(&mut bs).is_ok() {}
+        let mut frame = parse_frame(&mut bs).unwrap();
+        frame.unmask_in_place();
+        assert_eq!(frame.body, b"hello!");
+    }
+}
@@ -0,0 +1,251 @@
+// Start line, header block and Content-Length body shared by the HTTP-like text protocols
+// (HTTP, RTSP, SIP); a `Dialect` carries what differs between them.
+use crate::ascii::eq_ignore_case;
+use crate::http::{as_string, Header};
+use crate::parser::{before, bytes, exact, maybe, repeat, single, until, Matcher, MatchError, unit, ParserExt};
+use crate::stream::ByteStream;
+
+// stable error codes, see `MatchError::code`
+pub const TEXTMSG_BAD_START_LINE: &str = "TEXTMSG_BAD_START_LINE";
+pub const TEXTMSG_BAD_CONTENT_LENGTH: &str = "TEXTMSG_BAD_CONTENT_LENGTH";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Dialect {
+    pub name: &'static str,
+    // protocol token prefix, e.g. "RTSP/" matching "RTSP/1.0"
+    pub version: &'static str,
+    // compact header forms, expanded to the full name when parsed
+    pub compact: &'static [(&'static str, &'static str)],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartLine {
+    Request { method: String, target: String, protocol: String },
+    Status { protocol: String, code: u16, reason: String },
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub start: StartLine,
+    pub headers: Vec<Header>,
+    pub content: Vec<u8>,
+}
+
+impl Message {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| eq_ignore_case(&h.name, name))
+            .map(|h| h.value.as_str())
+    }
+
+    pub fn method(&self) -> Option<&str> {
+        match &self.start {
+            StartLine::Request { method, .. } => Some(method),
+            _ => None,
+        }
+    }
+
+    pub fn code(&self) -> Option<u16> {
+        match &self.start {
+            StartLine::Status { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+// running out of input keeps its INCOMPLETE code, it says nothing about the message being malformed
+pub(crate) fn coded(code: &'static str) -> impl Fn(MatchError) -> MatchError {
+    move |e| if e.is_incomplete() { e } else { e.with_code(code) }
+}
+
+pub(crate) fn header_parser() -> impl Matcher<Header> {
+    unit(Vec::new)
+        .then(until(|b| b != b':' && b != b'\r' && b != b'\n'))
+        .map(|(mut vec, val)| {
+            vec.push(as_string(val));
+            vec
+        })
+        .then(single(':'))
+        .map(|(vec, _)| vec)
+        .then(maybe(single(' ')))
+        .map(|(vec, _)| vec)
+        .then(before('\r'))
+        .map(|(mut vec, val)| {
+            vec.push(as_string(val));
+            vec
+        })
+        .then(exact(b"\r\n"))
+        .map(|(vec, _)| vec)
+        .map(|vec| Header {
+            name: vec[0].to_owned(),
+            value: vec[1].to_owned(),
+        })
+}
+
+// header lines up to and including the empty line that ends them, each with the offset it starts at
+pub(crate) fn located_headers() -> impl Matcher<Vec<(usize, Header)>> {
+    let located = |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        header_parser().do_match(bs).map(|h| (pos, h))
+    };
+    unit(Vec::new)
+        .then(repeat(located))
+        .save(|acc, vec| *acc = vec)
+        // whatever stopped the headers is not a `name: value` line
+        .then(exact(b"\r\n").context("end of headers"))
+        .skip()
+}
+
+pub(crate) fn unlocated(headers: Vec<(usize, Header)>) -> Vec<Header> {
+    headers.into_iter().map(|(_, h)| h).collect()
+}
+
+// the body announced by the first Content-Length header, none without one;
+// a malformed value is reported at the offset of its header
+pub(crate) fn body(bs: &mut ByteStream, headers: &[(usize, Header)]) -> Result<Vec<u8>, MatchError> {
+    let length = headers.iter().find(|(_, h)| eq_ignore_case(&h.name, "Content-Length"));
+    let n = match length {
+        None => return Ok(vec![]),
+        Some((pos, h)) => h.value.trim().parse::<usize>().map_err(|e| {
+            MatchError::unexpected(*pos, h.value.clone(), "Content-Length".to_string())
+                .with_code(TEXTMSG_BAD_CONTENT_LENGTH)
+                .with_source(e)
+        })?,
+    };
+    bytes(n).context("body").do_match(bs)
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+// a space-separated field of the start line
+fn field() -> impl Matcher<Vec<u8>> {
+    until(|b| b != b' ' && b != b'\r' && b != b'\n')
+}
+
+// None when the line is complete but not one the dialect allows
+fn read_start_line(dialect: &Dialect, bs: &mut ByteStream) -> Result<Option<StartLine>, MatchError> {
+    let first = as_string(field().then(single(' ')).skip().do_match(bs)?);
+    if first.starts_with(dialect.version) {
+        let code = as_string(bytes(3).context("status code").do_match(bs)?);
+        let reason = match maybe(single(' ')).do_match(bs)? {
+            Some(_) => as_string(before('\r').context("reason").do_match(bs)?),
+            None => String::new(),
+        };
+        exact(b"\r\n").do_match(bs)?;
+        let code = code.parse().ok().filter(|_| code.bytes().all(|b| b.is_ascii_digit()));
+        return Ok(code.map(|code| StartLine::Status { protocol: first, code, reason }));
+    }
+    let target = as_string(field().then(single(' ')).skip().context("request target").do_match(bs)?);
+    let protocol = as_string(before('\r').context("protocol").do_match(bs)?);
+    exact(b"\r\n").do_match(bs)?;
+    let valid = is_token(&first)
+        && !target.is_empty()
+        && protocol.starts_with(dialect.version)
+        && !protocol.contains(' ');
+    Ok(Some(StartLine::Request { method: first, target, protocol }).filter(|_| valid))
+}
+
+pub fn start_line(dialect: Dialect) -> impl Matcher<StartLine> {
+    coded_start_line(dialect, TEXTMSG_BAD_START_LINE)
+}
+
+// `start_line` reporting a malformed line under the given code
+pub(crate) fn coded_start_line(dialect: Dialect, code: &'static str) -> impl Matcher<StartLine> {
+    move |bs: &mut ByteStream| {
+        let pos = bs.pos();
+        read_start_line(&dialect, bs).map_err(coded(code))?.ok_or_else(|| {
+            let line = bs.slice(pos..bs.pos() - 2).map(String::from_utf8_lossy).unwrap_or_default();
+            let expected = format!("{} start line", dialect.name);
+            MatchError::unexpected(pos, line.to_string(), expected).with_code(code)
+        })
+    }
+}
+
+fn expand(dialect: &Dialect, mut header: Header) -> Header {
+    if let Some((_, full)) = dialect.compact.iter().find(|(short, _)| eq_ignore_case(short, &header.name)) {
+        header.name = full.to_string();
+    }
+    header
+}
+
+// a missing Content-Length means no body, a malformed one fails the message
+pub fn message(dialect: Dialect) -> impl Matcher<Message> {
+    move |bs: &mut ByteStream| {
+        let start = start_line(dialect).do_match(bs)?;
+        let headers = located_headers()
+            .do_match(bs)?
+            .into_iter()
+            .map(|(pos, h)| (pos, expand(&dialect, h)))
+            .collect::<Vec<_>>();
+        let content = body(bs, &headers)?;
+        Ok(Message { start, headers: unlocated(headers), content })
+    }
+}
+
+// on failure the stream is left where it was
+pub fn parse_message(dialect: Dialect, stream: &mut ByteStream) -> Result<Message, MatchError> {
+    let mark = stream.mark();
+    let result = message(dialect).do_match(stream);
+    if result.is_err() {
+        stream.reset(mark);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST: Dialect = Dialect {
+        name: "TEST",
+        version: "TEST/",
+        compact: &[("l", "Content-Length")],
+    };
+
+    #[test]
+    fn request_and_status() {
+        let text = "PING * TEST/1.0\r\nl: 2\r\n\r\nhiTEST/1.0 200 OK\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let req = parse_message(TEST, &mut bs).unwrap();
+        assert_eq!(req.method(), Some("PING"));
+        assert_eq!(req.header("content-length"), Some("2"));
+        assert_eq!(req.content, b"hi");
+
+        let res = parse_message(TEST, &mut bs).unwrap();
+        assert_eq!(res.start, StartLine::Status {
+            protocol: "TEST/1.0".to_string(),
+            code: 200,
+            reason: "OK".to_string(),
+        });
+        assert!(res.content.is_empty());
+        assert_eq!(bs.remaining(), 0);
+    }
+
+    #[test]
+    fn malformed() {
+        let cases = [
+            ("GET / HTTP/1.1\r\n\r\n", TEXTMSG_BAD_START_LINE),
+            ("P?NG * TEST/1.0\r\n\r\n", TEXTMSG_BAD_START_LINE),
+            ("TEST/1.0 2000 OK\r\n\r\n", TEXTMSG_BAD_START_LINE),
+            ("PING * TEST/1.0\r\nContent-Length: x\r\n\r\n", TEXTMSG_BAD_CONTENT_LENGTH),
+        ];
+        for (text, code) in cases {
+            let mut bs: ByteStream = text.to_string().into();
+            let err = parse_message(TEST, &mut bs).unwrap_err();
+            assert_eq!(err.code(), code, "{}", text);
+            assert_eq!(bs.pos(), 0);
+        }
+    }
+
+    #[test]
+    fn content_length_at_its_header() {
+        let text = "PING * TEST/1.0\r\nCSeq: 1\r\nl: x\r\n\r\n";
+        let mut bs: ByteStream = text.to_string().into();
+        let err = parse_message(TEST, &mut bs).unwrap_err();
+        assert_eq!(err.code(), TEXTMSG_BAD_CONTENT_LENGTH);
+        assert_eq!(err.offset(), text.find("l: x").unwrap());
+    }
+}
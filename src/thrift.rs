@@ -0,0 +1,266 @@
+// Thrift binary and compact protocol decoding into a generic value tree, for inspecting
+// traffic without the IDL. Field names are not on the wire, fields are keyed by id.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{bytes, get_16, get_f64, get_f64_le, get_i16, get_i32, get_i64, get_i8, get_u8, varint_u32, varint_u64};
+use crate::stream::ByteStream;
+
+// nesting limit, so hostile input can not exhaust the stack
+pub const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Byte(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    Uuid([u8; 16]),
+    Struct(Vec<(i16, Value)>),
+    List(Vec<Value>),
+    Set(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value {
+    pub fn field(&self, id: i16) -> Option<&Value> {
+        match self {
+            Value::Struct(fields) => fields.iter().find(|(i, _)| *i == id).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+// a struct in the binary protocol, up to and including its STOP field
+pub fn binary_struct() -> impl Matcher<Value> {
+    move |bs: &mut ByteStream| reset_on_err(bs, |bs| binary_value(bs, 12, 0))
+}
+
+// a struct in the compact protocol, up to and including its STOP field
+pub fn compact_struct() -> impl Matcher<Value> {
+    move |bs: &mut ByteStream| reset_on_err(bs, |bs| compact_value(bs, 12, 0))
+}
+
+fn reset_on_err<T>(bs: &mut ByteStream, f: impl Fn(&mut ByteStream) -> Result<T, MatchError>) -> Result<T, MatchError> {
+    let mark = bs.mark();
+    let result = f(bs);
+    if result.is_err() {
+        bs.reset(mark);
+    }
+    result
+}
+
+fn unknown_type(pos: usize, t: u8) -> MatchError {
+    MatchError::unexpected(pos, format!("type {}", t), "thrift type".to_string())
+}
+
+fn size(pos: usize, n: i32) -> Result<usize, MatchError> {
+    if n < 0 {
+        return Err(MatchError::unexpected(pos, n.to_string(), "non-negative size".to_string()));
+    }
+    Ok(n as usize)
+}
+
+fn check_depth(bs: &ByteStream, depth: usize) -> Result<(), MatchError> {
+    if depth > MAX_DEPTH {
+        return Err(MatchError::custom(bs.pos(), format!("nesting deeper than {}", MAX_DEPTH)));
+    }
+    Ok(())
+}
+
+// Binary protocol: type ids 2 BOOL, 3 BYTE, 4 DOUBLE, 6 I16, 8 I32, 10 I64, 11 STRING,
+// 12 STRUCT, 13 MAP, 14 SET, 15 LIST, 16 UUID; sizes are big-endian i32
+
+fn binary_value(bs: &mut ByteStream, t: u8, depth: usize) -> Result<Value, MatchError> {
+    check_depth(bs, depth)?;
+    let pos = bs.pos();
+    let value = match t {
+        2 => Value::Bool(get_u8().do_match(bs)? != 0),
+        3 => Value::Byte(get_i8().do_match(bs)?),
+        4 => Value::Double(get_f64().do_match(bs)?),
+        6 => Value::I16(get_i16().do_match(bs)?),
+        8 => Value::I32(get_i32().do_match(bs)?),
+        10 => Value::I64(get_i64().do_match(bs)?),
+        11 => {
+            let n = size(pos, get_i32().do_match(bs)?)?;
+            Value::Binary(bytes(n).do_match(bs)?)
+        }
+        12 => {
+            let mut fields = vec![];
+            loop {
+                let ft = get_u8().do_match(bs)?;
+                if ft == 0 {
+                    break;
+                }
+                let id = get_i16().do_match(bs)?;
+                fields.push((id, binary_value(bs, ft, depth + 1)?));
+            }
+            Value::Struct(fields)
+        }
+        13 => {
+            let (kt, vt) = (get_u8().do_match(bs)?, get_u8().do_match(bs)?);
+            let n = size(pos, get_i32().do_match(bs)?)?;
+            let mut entries = vec![];
+            for _ in 0..n {
+                let k = binary_value(bs, kt, depth + 1)?;
+                entries.push((k, binary_value(bs, vt, depth + 1)?));
+            }
+            Value::Map(entries)
+        }
+        14 | 15 => {
+            let et = get_u8().do_match(bs)?;
+            let n = size(pos, get_i32().do_match(bs)?)?;
+            let mut items = vec![];
+            for _ in 0..n {
+                items.push(binary_value(bs, et, depth + 1)?);
+            }
+            if t == 14 { Value::Set(items) } else { Value::List(items) }
+        }
+        16 => Value::Uuid(get_16().do_match(bs)?),
+        t => return Err(unknown_type(pos, t)),
+    };
+    Ok(value)
+}
+
+// Compact protocol: type ids 1 BOOL_TRUE, 2 BOOL_FALSE, 3 BYTE, 4 I16, 5 I32, 6 I64,
+// 7 DOUBLE, 8 BINARY, 9 LIST, 10 SET, 11 MAP, 12 STRUCT, 13 UUID; integers are zigzag
+// varints, doubles little-endian, field ids deltas from the previous field of the struct
+
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn compact_value(bs: &mut ByteStream, t: u8, depth: usize) -> Result<Value, MatchError> {
+    check_depth(bs, depth)?;
+    let pos = bs.pos();
+    let value = match t {
+        // a bool that is not a field carries its value in a byte of its own
+        1 | 2 => Value::Bool(get_u8().do_match(bs)? == 1),
+        3 => Value::Byte(get_i8().do_match(bs)?),
+        4 => Value::I16(zigzag(varint_u32().do_match(bs)? as u64) as i16),
+        5 => Value::I32(zigzag(varint_u32().do_match(bs)? as u64) as i32),
+        6 => Value::I64(zigzag(varint_u64().do_match(bs)?)),
+        7 => Value::Double(get_f64_le().do_match(bs)?),
+        8 => {
+            let n = varint_u32().do_match(bs)? as usize;
+            Value::Binary(bytes(n).do_match(bs)?)
+        }
+        9 | 10 => {
+            let head = get_u8().do_match(bs)?;
+            let n = match head >> 4 {
+                15 => varint_u32().do_match(bs)? as usize,
+                n => n as usize,
+            };
+            let mut items = vec![];
+            for _ in 0..n {
+                items.push(compact_value(bs, head & 0x0F, depth + 1)?);
+            }
+            if t == 10 { Value::Set(items) } else { Value::List(items) }
+        }
+        11 => {
+            let n = varint_u32().do_match(bs)? as usize;
+            let mut entries = vec![];
+            if n > 0 {
+                let types = get_u8().do_match(bs)?;
+                for _ in 0..n {
+                    let k = compact_value(bs, types >> 4, depth + 1)?;
+                    entries.push((k, compact_value(bs, types & 0x0F, depth + 1)?));
+                }
+            }
+            Value::Map(entries)
+        }
+        12 => {
+            let mut fields = vec![];
+            let mut last = 0i16;
+            loop {
+                let head = get_u8().do_match(bs)?;
+                let ft = head & 0x0F;
+                if ft == 0 {
+                    break;
+                }
+                let id = match head >> 4 {
+                    0 => zigzag(varint_u32().do_match(bs)? as u64) as i16,
+                    delta => last.wrapping_add(delta as i16),
+                };
+                last = id;
+                let value = match ft {
+                    1 => Value::Bool(true),
+                    2 => Value::Bool(false),
+                    ft => compact_value(bs, ft, depth + 1)?,
+                };
+                fields.push((id, value));
+            }
+            Value::Struct(fields)
+        }
+        13 => Value::Uuid(get_16().do_match(bs)?),
+        t => return Err(unknown_type(pos, t)),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Applicator;
+
+    #[test]
+    fn binary() {
+        // {1: "hi", 2: [i32 7, i32 -1], 3: {4: true}, 4: {i16 1 -> double 0.5}}
+        let mut data = vec![11, 0, 1, 0, 0, 0, 2, b'h', b'i'];
+        data.extend_from_slice(&[15, 0, 2, 8, 0, 0, 0, 2, 0, 0, 0, 7, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data.extend_from_slice(&[12, 0, 3, 2, 0, 4, 1, 0]);
+        data.extend_from_slice(&[13, 0, 4, 6, 4, 0, 0, 0, 1, 0, 1]);
+        data.extend_from_slice(&0.5f64.to_be_bytes());
+        data.push(0);
+
+        let mut bs = ByteStream::wrap(data.clone());
+        let value = bs.apply(binary_struct()).unwrap();
+        assert_eq!(bs.remaining(), 0);
+        assert_eq!(value.field(1), Some(&Value::Binary(b"hi".to_vec())));
+        assert_eq!(value.field(2), Some(&Value::List(vec![Value::I32(7), Value::I32(-1)])));
+        assert_eq!(value.field(3).and_then(|s| s.field(4)), Some(&Value::Bool(true)));
+        assert_eq!(value.field(4), Some(&Value::Map(vec![(Value::I16(1), Value::Double(0.5))])));
+
+        data.pop();
+        let mut bs = ByteStream::wrap(data);
+        assert!(bs.apply(binary_struct()).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 0);
+    }
+
+    #[test]
+    fn compact() {
+        // {1: i32 -2, 2: true, 5: "ab", 20: [i64 1, i64 300], 21: {1: byte 9}}
+        let data = vec![
+            0x15, 0x03,
+            0x11,
+            0x38, 0x02, b'a', b'b',
+            0x09, 0x28, 0x26, 0x02, 0xD8, 0x04,
+            0x1C, 0x13, 0x09, 0x00,
+            0x00,
+        ];
+        let mut bs = ByteStream::wrap(data);
+        let value = bs.apply(compact_struct()).unwrap();
+        assert_eq!(bs.remaining(), 0);
+        assert_eq!(value, Value::Struct(vec![
+            (1, Value::I32(-2)),
+            (2, Value::Bool(true)),
+            (5, Value::Binary(b"ab".to_vec())),
+            (20, Value::List(vec![Value::I64(1), Value::I64(300)])),
+            (21, Value::Struct(vec![(1, Value::Byte(9))])),
+        ]));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut bs = ByteStream::wrap(vec![7, 0, 1, 0]);
+        assert!(!bs.apply(binary_struct()).unwrap_err().is_incomplete());
+        let mut bs = ByteStream::wrap(vec![11, 0, 1, 0xFF, 0xFF, 0xFF, 0xFF, 0]);
+        assert!(bs.apply(binary_struct()).is_err());
+
+        let nested = [0x1Cu8; MAX_DEPTH + 2];
+        let mut bs = ByteStream::wrap(nested.to_vec());
+        let err = bs.apply(compact_struct()).unwrap_err();
+        assert!(err.to_string().contains("nesting"), "{}", err);
+    }
+}
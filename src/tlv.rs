@@ -0,0 +1,250 @@
+// Type-length-value records (DER, EAP, RADIUS attributes, telemetry): a tag, a length
+// and that many value bytes. Nothing is consumed when a record fails to match.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::match_value;
+use crate::stream::ByteStream;
+use std::convert::TryInto;
+use std::marker::PhantomData;
+
+// raw value bytes with their tag
+pub fn tlv<G, N: TryInto<usize>>(tag: impl Matcher<G>, len: impl Matcher<N>) -> impl Matcher<(G, Vec<u8>)> {
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = record(bs, &tag, &len);
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result.map(|(g, _, value)| (g, value))
+    }
+}
+
+// the value bytes are parsed by the matcher `f` picks for the tag, which must consume all of them
+pub fn tlv_with<G, N, V, M, F>(tag: impl Matcher<G>, len: impl Matcher<N>, f: F) -> impl Matcher<(G, V)>
+where
+    N: TryInto<usize>,
+    M: Matcher<V>,
+    F: Fn(&G) -> M,
+{
+    move |bs: &mut ByteStream| {
+        let mark = bs.mark();
+        let result = record(bs, &tag, &len).and_then(|(g, start, value)| {
+            let v = match_value(&f(&g), value, start)?;
+            Ok((g, v))
+        });
+        if result.is_err() {
+            bs.reset(mark);
+        }
+        result
+    }
+}
+
+// what `walk` does with bytes at the end that do not form a complete record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trailing {
+    // yield the error, e.g. a truncated last record
+    Strict,
+    // stop quietly, e.g. padding after the last record; see `Entries::remainder`
+    Lenient,
+}
+
+// walks a buffer of consecutive records, endianness and widths are those of the `tag`
+// and `len` matchers; a value holding nested records is walked with `entries` again
+pub struct Walk<TM, LM> {
+    tag: TM,
+    len: LM,
+    trailing: Trailing,
+    header_included: bool,
+}
+
+pub fn walk<TM, LM>(tag: TM, len: LM) -> Walk<TM, LM> {
+    Walk {
+        tag,
+        len,
+        trailing: Trailing::Strict,
+        header_included: false,
+    }
+}
+
+impl<TM, LM> Walk<TM, LM> {
+    pub fn trailing(mut self, trailing: Trailing) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    // the length counts the tag and length bytes too (RADIUS, LLDP-style formats)
+    pub fn length_includes_header(mut self) -> Self {
+        self.header_included = true;
+        self
+    }
+
+    pub fn entries<'a, G, N>(&'a self, data: &'a [u8]) -> Entries<'a, TM, LM, G, N>
+    where
+        TM: Matcher<G>,
+        LM: Matcher<N>,
+        N: TryInto<usize>,
+    {
+        Entries {
+            walk: self,
+            data,
+            bs: ByteStream::wrap(data.to_vec()),
+            done: false,
+            phantom: PhantomData,
+        }
+    }
+}
+
+pub struct Entries<'a, TM, LM, G, N> {
+    walk: &'a Walk<TM, LM>,
+    data: &'a [u8],
+    bs: ByteStream,
+    done: bool,
+    phantom: PhantomData<fn() -> (G, N)>,
+}
+
+impl<'a, TM, LM, G, N> Entries<'a, TM, LM, G, N> {
+    // bytes not walked yet, after a lenient stop these are the trailing bytes
+    pub fn remainder(&self) -> &'a [u8] {
+        &self.data[self.bs.pos()..]
+    }
+}
+
+impl<'a, TM, LM, G, N> Iterator for Entries<'a, TM, LM, G, N>
+where
+    TM: Matcher<G>,
+    LM: Matcher<N>,
+    N: TryInto<usize>,
+{
+    type Item = Result<(G, &'a [u8]), MatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.bs.remaining() == 0 {
+            return None;
+        }
+        let (walk, data, bs) = (self.walk, self.data, &mut self.bs);
+        let mark = bs.mark();
+        let pos = bs.pos();
+        let result = header(bs, &walk.tag, &walk.len).and_then(|(g, n)| {
+            let header = bs.pos() - pos;
+            let n = match walk.header_included {
+                true if n < header => {
+                    return Err(MatchError::unexpected(pos, format!("length {}", n), format!("at least {}", header)));
+                }
+                true => n - header,
+                false => n,
+            };
+            let start = bs.pos();
+            if n > bs.remaining() {
+                return Err(MatchError::incomplete(start, n - bs.remaining()));
+            }
+            bs.advance(n);
+            Ok((g, &data[start..start + n]))
+        });
+        if result.is_err() {
+            bs.reset(mark);
+            self.done = true;
+            if walk.trailing == Trailing::Lenient {
+                return None;
+            }
+        }
+        Some(result)
+    }
+}
+
+fn header<G, N: TryInto<usize>>(
+    bs: &mut ByteStream,
+    tag: &impl Matcher<G>,
+    len: &impl Matcher<N>,
+) -> Result<(G, usize), MatchError> {
+    let g = tag.do_match(bs)?;
+    let pos = bs.pos();
+    let n: usize = len.do_match(bs)?
+        .try_into()
+        .map_err(|_| MatchError::custom(pos, "length does not fit into usize".to_string()))?;
+    Ok((g, n))
+}
+
+// tag, offset of the value and the value bytes
+fn record<G, N: TryInto<usize>>(
+    bs: &mut ByteStream,
+    tag: &impl Matcher<G>,
+    len: &impl Matcher<N>,
+) -> Result<(G, usize, Vec<u8>), MatchError> {
+    let (g, n) = header(bs, tag, len)?;
+    let start = bs.pos();
+    match bs.get(n) {
+        Some(value) => Ok((g, start, value)),
+        None => Err(MatchError::incomplete(start, n - bs.remaining())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{get_u16, get_u16_le, get_u32, get_u8, rest, Applicator};
+
+    #[test]
+    fn raw_records() {
+        let mut bs = ByteStream::wrap(vec![1, 3, b'a', b'b', b'c', 2, 0, 9, 2]);
+        assert_eq!(bs.apply(tlv(get_u8(), get_u8())).unwrap(), (1, b"abc".to_vec()));
+        assert_eq!(bs.apply(tlv(get_u8(), get_u8())).unwrap(), (2, vec![]));
+        assert!(bs.apply(tlv(get_u8(), get_u8())).unwrap_err().is_incomplete());
+        assert_eq!(bs.pos(), 7);
+
+        let mut bs = ByteStream::wrap(vec![0, 7, 0, 0, 0, 1, 0xFF]);
+        assert_eq!(bs.apply(tlv(get_u16(), get_u32())).unwrap(), (7, vec![0xFF]));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Attr {
+        Port(u16),
+        Name(Vec<u8>),
+    }
+
+    #[test]
+    fn per_tag_values() {
+        let attr = || tlv_with(get_u8(), get_u8(), |tag| match tag {
+            1 => get_u16().map(Attr::Port).boxed(),
+            _ => rest().map(Attr::Name).boxed(),
+        });
+        let mut bs = ByteStream::wrap(vec![1, 2, 0x1F, 0x90, 5, 2, b'h', b'i', 1, 3, 0, 0, 0]);
+        assert_eq!(bs.apply(attr()).unwrap(), (1, Attr::Port(8080)));
+        assert_eq!(bs.apply(attr()).unwrap(), (5, Attr::Name(b"hi".to_vec())));
+
+        // a port attribute three bytes long
+        let err = bs.apply(attr()).unwrap_err();
+        assert_eq!(err.offset(), 12);
+        assert_eq!(bs.pos(), 8);
+    }
+
+    #[test]
+    fn walk_records() {
+        // little-endian 16-bit tags and lengths, the second record holds two nested ones
+        let data = [1, 0, 1, 0, 0xAA, 2, 0, 8, 0, 3, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0];
+        let w = walk(get_u16_le(), get_u16_le());
+        let top = w.entries(&data).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(top, vec![(1, &[0xAA][..]), (2, &[3, 0, 0, 0, 4, 0, 0, 0][..]), (0, &[][..])]);
+        let nested = w.entries(top[1].1).map(|e| e.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(nested, vec![3, 4]);
+    }
+
+    #[test]
+    fn walk_trailing() {
+        // RADIUS-style attributes, the length counts the two header bytes; then padding
+        let data = [1, 4, b'b', b'o', 2, 2, 5];
+        let w = walk(get_u8(), get_u8()).length_includes_header();
+        let entries = w.entries(&data).collect::<Vec<_>>();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].as_ref().unwrap(), &(1, &b"bo"[..]));
+        assert_eq!(entries[1].as_ref().unwrap(), &(2, &[][..]));
+        assert!(entries[2].as_ref().unwrap_err().is_incomplete());
+
+        let w = w.trailing(Trailing::Lenient);
+        let mut entries = w.entries(&data);
+        assert_eq!(entries.by_ref().count(), 2);
+        assert_eq!(entries.remainder(), &[5]);
+
+        let w = walk(get_u8(), get_u8()).length_includes_header();
+        let err = w.entries(&[1, 1, 0]).next().unwrap().unwrap_err();
+        assert_eq!(err.offset(), 0);
+    }
+}
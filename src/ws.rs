@@ -1,9 +1,14 @@
-use crate::parser::{Matcher, unit, bytes, Applicator, ParserExt};
+use crate::parser::{Matcher, MatchError, unit, bytes, Applicator, ParserExt};
+use crate::raw::xor_mask;
 use crate::stream::ByteStream;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Frame {
     pub fin: bool,
+    pub rsv: u8, // RSV1-3 bits, RSV1 is 0b100
     pub opcode: u8,
     pub len: u32,
     pub mask: Option<[u8; 4]>,
@@ -14,43 +19,167 @@ impl Frame {
     pub fn text(body: &str) -> Frame {
         Frame {
             fin: true,
+            rsv: 0,
             opcode: 1, // 0 - continuation, 1 - text, 2 - binary
             len: body.len() as u32,
             mask: None,
             body: body.as_bytes().to_vec(),
         }
     }
+
+    // unmasks the body without copying it, the frame is left without a mask
+    pub fn unmask_in_place(&mut self) {
+        if let Some(mask) = self.mask.take() {
+            xor_mask(&mut self.body, mask);
+        }
+    }
 }
 
-pub fn decode_frame_body(body: &Vec<u8>, mask: &[u8; 4]) -> Vec<u8> {
-    let mut decoded = body.clone();
-    for i in 0..body.len() {
-        decoded[i] = body[i] ^ mask[i % 4];
+// stable error codes, see `MatchError::code`
+pub const WS_CONTROL_TOO_LONG: &str = "WS_CONTROL_TOO_LONG";
+pub const WS_CONTROL_FRAGMENTED: &str = "WS_CONTROL_FRAGMENTED";
+pub const WS_RESERVED_OPCODE: &str = "WS_RESERVED_OPCODE";
+
+// protocol rules (RFC 6455, section 5.5) that `parse_frame` does not check by itself;
+// `at` is the offset of the frame in the stream, errors point at the header byte at fault:
+// the first one holds FIN and the opcode, the second one the payload length
+pub fn validate_frame(frame: &Frame, at: usize) -> Result<(), MatchError> {
+    match frame.opcode {
+        0..=2 => Ok(()),
+        8..=10 if !frame.fin => Err(MatchError::unexpected(
+            at,
+            "fragmented control frame".to_string(),
+            "FIN bit set".to_string(),
+        ).with_code(WS_CONTROL_FRAGMENTED)),
+        8..=10 if frame.len > 125 => Err(MatchError::unexpected(
+            at + 1,
+            format!("{} bytes of payload", frame.len),
+            "control frame payload of at most 125 bytes".to_string(),
+        ).with_code(WS_CONTROL_TOO_LONG)),
+        8..=10 => Ok(()),
+        code => Err(MatchError::unexpected(
+            at,
+            format!("opcode {}", code),
+            "defined opcode".to_string(),
+        ).with_code(WS_RESERVED_OPCODE)),
     }
+}
+
+pub fn decode_frame_body(body: &[u8], mask: &[u8; 4]) -> Vec<u8> {
+    let mut decoded = body.to_vec();
+    xor_mask(&mut decoded, *mask);
     decoded
 }
 
-impl Into<Vec<u8>> for Frame {
-    fn into(self) -> Vec<u8> {
-        let mut stream = ByteStream::with_capacity(self.body.len() + 26);
-        let byte1 = ((if self.fin { 1u8 } else { 0u8 }) << 7) + self.opcode;
-        stream.put(&[byte1]);
-        if self.body.len() <= 125 {
-            stream.put(&[self.body.len() as u8]);
+impl Frame {
+    // exact number of bytes `encode_into` appends
+    pub fn encoded_len(&self) -> usize {
+        let len = self.body.len();
+        let ext = if len <= 125 { 0 } else if len <= 0xFFFF { 2 } else { 8 };
+        2 + ext + len
+    }
+
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        let byte1 = ((if self.fin { 1u8 } else { 0u8 }) << 7) + (self.rsv << 4) + self.opcode;
+        out.push(byte1);
+        let len = self.body.len();
+        if len <= 125 {
+            out.push(len as u8);
+        } else if len <= 0xFFFF {
+            out.push(126u8);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
         } else {
-            stream.put(&[126u8]);
-            let size = self.body.len() as u16;
-            stream.put(&[(size >> 8) as u8, (size & 255) as u8]);
-        };
-        stream.put(self.body.as_slice());
-        let r: &[u8] = stream.as_ref();
-        r.to_vec()
+            out.push(127u8);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+        out.extend_from_slice(&self.body);
+    }
+
+    // unmasked server frames are identical for every recipient, so a broadcast is encoded once
+    pub fn encode_shared(&self) -> Arc<[u8]> {
+        let mut out = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut out);
+        out.into()
+    }
+}
+
+// allocates only for connections that need bytes of their own in front of the shared frame
+pub fn prefixed<'a>(shared: &'a Arc<[u8]>, prefix: &[u8]) -> Cow<'a, [u8]> {
+    if prefix.is_empty() {
+        Cow::Borrowed(shared)
+    } else {
+        let mut out = Vec::with_capacity(prefix.len() + shared.len());
+        out.extend_from_slice(prefix);
+        out.extend_from_slice(shared);
+        Cow::Owned(out)
+    }
+}
+
+impl From<Frame> for Vec<u8> {
+    fn from(frame: Frame) -> Vec<u8> {
+        let mut out = Vec::with_capacity(frame.encoded_len());
+        frame.encode_into(&mut out);
+        out
+    }
+}
+
+// coalesces encoded frames of several messages into one contiguous buffer
+pub struct BatchEncoder {
+    buf: Vec<u8>,
+    offsets: Vec<Range<usize>>,
+    max_len: usize,
+}
+
+impl BatchEncoder {
+    pub fn new(max_len: usize) -> BatchEncoder {
+        BatchEncoder {
+            buf: Vec::with_capacity(max_len),
+            offsets: vec![],
+            max_len,
+        }
+    }
+
+    // false when the frame does not fit into the batch, a frame larger than
+    // the limit is still accepted into an empty batch
+    pub fn push(&mut self, frame: &Frame) -> bool {
+        let start = self.buf.len();
+        frame.encode_into(&mut self.buf);
+        if self.buf.len() > self.max_len && start > 0 {
+            self.buf.truncate(start);
+            return false;
+        }
+        self.offsets.push(start..self.buf.len());
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    // byte range of each pushed message within the batch, in push order
+    pub fn offsets(&self) -> &[Range<usize>] {
+        &self.offsets
+    }
+
+    // takes the batch out, leaving the encoder empty and ready for the next one
+    pub fn finish(&mut self) -> (Vec<u8>, Vec<Range<usize>>) {
+        let buf = std::mem::replace(&mut self.buf, Vec::with_capacity(self.max_len));
+        let offsets = std::mem::take(&mut self.offsets);
+        (buf, offsets)
     }
 }
 
 fn frame_opts() -> impl Matcher<FrameOpts> {
     bytes(2)
-        .map(|word| FrameOpts::new(word))
+        .map(FrameOpts::new)
 }
 
 pub fn parse_frame(stream: &mut ByteStream) -> Option<Frame> {
@@ -60,7 +189,7 @@ pub fn parse_frame(stream: &mut ByteStream) -> Option<Frame> {
     }
 
     let opts = frame_opts.unwrap();
-    let (fin, code, mask) = (opts.fin, opts.code, opts.mask);
+    let (fin, rsv, code, mask) = (opts.fin, opts.rsv, opts.code, opts.mask);
 
     let p0 = unit(|| ());
     let p1 = match opts.len {
@@ -73,6 +202,7 @@ pub fn parse_frame(stream: &mut ByteStream) -> Option<Frame> {
 
     let p2 = p1.map( move |len| Frame {
         fin,
+        rsv,
         opcode: code,
         mask: None,
         body: Vec::with_capacity(len as usize),
@@ -93,21 +223,22 @@ pub fn parse_frame(stream: &mut ByteStream) -> Option<Frame> {
         .save(|frame, vec| frame.body = vec);
 
     stream.apply(p4)
-        .map(|x| Some(x))
+        .map(Some)
         .unwrap_or_default()
 }
 
 fn build_u16(vec: Vec<u8>) -> u16 {
-    vec.into_iter().fold(0 as u16, |acc, b| acc << 8 + b as u16)
+    vec.into_iter().fold(0u16, |acc, b| (acc << 8) + b as u16)
 }
 
 fn build_u64(vec: Vec<u8>) -> u64 {
-    vec.into_iter().fold(0u64, |acc, b| acc << 8 + b as u64)
+    vec.into_iter().fold(0u64, |acc, b| (acc << 8) + b as u64)
 }
 
 #[derive(Default)]
 struct FrameOpts {
     fin: bool,
+    rsv: u8,
     code: u8,
     len: u8,
     mask: bool,
@@ -117,6 +248,7 @@ impl FrameOpts {
     fn new(word: Vec<u8>) -> FrameOpts {
         FrameOpts {
             fin: (word[0] >> 7) > 0,
+            rsv: (word[0] >> 4) & 0x7u8,
             code: 0xFu8 & word[0],
             len: 127u8 & word[1],
             mask: (word[1] >> 7) > 0,
@@ -126,6 +258,7 @@ impl FrameOpts {
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use crate::stream::ByteStream;
@@ -136,11 +269,48 @@ mod tests {
         let mut stream = ByteStream::wrap(bytes);
         let opts = stream.apply(frame_opts()).unwrap();
         assert_eq!(opts.fin, true);
+        assert_eq!(opts.rsv, 0);
         assert_eq!(opts.code, 3);
         assert_eq!(opts.mask, true);
         assert_eq!(opts.len, 3);
     }
 
+    #[test]
+    fn extended_length() {
+        // big-endian, each byte is added after shifting the ones before it
+        assert_eq!(build_u16(vec![0x01, 0x02]), 0x0102);
+        assert_eq!(build_u64(vec![0, 0, 0, 0, 0x01, 0x02, 0x03, 0x04]), 0x0102_0304);
+    }
+
+    #[test]
+    fn frame_error_codes() {
+        let mut ping = Frame::text("");
+        ping.opcode = 9;
+        assert!(validate_frame(&ping, 0).is_ok());
+        ping.len = 126;
+        let err = validate_frame(&ping, 10).unwrap_err();
+        assert_eq!((err.code(), err.offset()), (WS_CONTROL_TOO_LONG, 11));
+        ping.fin = false;
+        let err = validate_frame(&ping, 10).unwrap_err();
+        assert_eq!((err.code(), err.offset()), (WS_CONTROL_FRAGMENTED, 10));
+
+        let mut frame = Frame::text("x");
+        assert!(validate_frame(&frame, 0).is_ok());
+        frame.opcode = 3;
+        let err = validate_frame(&frame, 10).unwrap_err();
+        assert_eq!((err.code(), err.offset()), (WS_RESERVED_OPCODE, 10));
+    }
+
+    #[test]
+    fn frame_encoded_len() {
+        for len in [0usize, 125, 126, 0xFFFF, 0x10000].iter() {
+            let frame = Frame::text(&"x".repeat(*len));
+            let mut out = vec![];
+            frame.encode_into(&mut out);
+            assert_eq!(frame.encoded_len(), out.len());
+        }
+    }
+
     #[test]
     fn frame1() {
         let bytes: Vec<u8> = vec![128 + 9, 128 + 7, 1, 2, 3, 4, 10, 11, 12, 13, 14, 15, 16];
@@ -169,6 +339,53 @@ mod tests {
         assert_eq!(frame.body, vec![10, 20, 30]);
     }
 
+    #[test]
+    fn frame_encode_lengths() {
+        for len in &[0usize, 125, 126, 65535, 65536] {
+            let body = vec![7u8; *len];
+            let bytes: Vec<u8> = Frame::text(std::str::from_utf8(&body).unwrap()).into();
+            let mut stream = ByteStream::wrap(bytes);
+            let frame = parse_frame(&mut stream).unwrap();
+            assert_eq!(frame.len as usize, *len);
+            assert_eq!(frame.body, body);
+        }
+    }
+
+    #[test]
+    fn shared_frame() {
+        let frame = Frame::text("tick");
+        let shared = frame.encode_shared();
+        let copy = shared.clone();
+        assert_eq!(&*shared, &[129, 4, b't', b'i', b'c', b'k']);
+        assert_eq!(Arc::strong_count(&shared), 2);
+
+        assert!(matches!(prefixed(&copy, &[]), Cow::Borrowed(_)));
+        let out = prefixed(&copy, &[1, 2]);
+        assert_eq!(&*out, &[1, 2, 129, 4, b't', b'i', b'c', b'k']);
+    }
+
+    #[test]
+    fn batch_encoder() {
+        let mut batch = BatchEncoder::new(16);
+        assert!(batch.push(&Frame::text("hello")));
+        assert!(batch.push(&Frame::text("world")));
+        assert!(!batch.push(&Frame::text("!")));
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch.offsets(), &[0..7, 7..14]);
+        assert_eq!(&batch.as_bytes()[7..], &[129, 5, b'w', b'o', b'r', b'l', b'd']);
+
+        let (bytes, offsets) = batch.finish();
+        assert_eq!(bytes.len(), 14);
+        assert_eq!(offsets.len(), 2);
+        assert!(batch.is_empty());
+
+        let large = "x".repeat(100);
+        assert!(batch.push(&Frame::text(&large)));
+        assert!(!batch.push(&Frame::text("!")));
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.offsets()[0], 0..102);
+    }
+
     #[test]
     fn frame_hello() {
         let expected = "hello!";
@@ -182,5 +399,12 @@ mod tests {
         assert_eq!(frame.len, expected.len() as u32);
         assert_eq!(frame.mask, Some([87, 35, 230, 82]));
         assert_eq!(decode_frame_body(&frame.body, &frame.mask.unwrap()), expected.as_bytes());
+
+        let mut frame = frame;
+        frame.unmask_in_place();
+        assert_eq!(frame.body, expected.as_bytes());
+        assert_eq!(frame.mask, None);
+        frame.unmask_in_place();
+        assert_eq!(frame.body, expected.as_bytes());
     }
 }
@@ -0,0 +1,419 @@
+// Pull parser for the element/attribute/text subset of XML 1.0 used by RSS and SOAP-style
+// payloads, with namespace prefixes resolved; DTDs are skipped, not interpreted.
+use crate::matcher::{MatchError, Matcher};
+use crate::parser::{before, before_seq, rest, single, space0, until};
+use crate::stream::ByteStream;
+
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QName {
+    pub prefix: Option<String>,
+    pub local: String,
+    // resolved from the xmlns declarations in scope, unprefixed attributes have none
+    pub namespace: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: QName,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    // an empty-element tag `<a/>` yields Start immediately followed by End
+    Start { name: QName, attributes: Vec<Attribute> },
+    End { name: QName },
+    // entities and character references decoded, whitespace-only text is skipped
+    Text(String),
+    CData(String),
+    Comment(String),
+}
+
+impl Event {
+    pub fn attribute(&self, local: &str) -> Option<&str> {
+        match self {
+            Event::Start { attributes, .. } => attributes
+                .iter()
+                .find(|a| a.name.local == local)
+                .map(|a| a.value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+type Bindings = Vec<(Option<String>, String)>;
+
+// events are read from the owned stream on demand; on a partial stream `None` means the
+// next event is not buffered yet (nothing is consumed), so the reader can be polled again
+// after more bytes were put through `stream_mut`, the open elements and namespaces in
+// scope are kept; after an error the reader yields nothing more
+pub struct XmlReader {
+    stream: ByteStream,
+    // raw names of the open elements, with the namespaces each of them declared
+    open: Vec<(String, Bindings)>,
+    pending_end: Option<QName>,
+    failed: bool,
+}
+
+impl XmlReader {
+    pub fn new(stream: ByteStream) -> XmlReader {
+        XmlReader {
+            stream,
+            open: vec![],
+            pending_end: None,
+            failed: false,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn stream_mut(&mut self) -> &mut ByteStream {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> ByteStream {
+        self.stream
+    }
+
+    fn event(&mut self) -> Result<Option<Event>, MatchError> {
+        let bs = &mut self.stream;
+        let pos = bs.pos();
+        let input = bs.as_ref();
+        if input.starts_with(b"<!--") {
+            bs.advance(4);
+            let text = before_seq(b"-->").do_match(bs)?;
+            bs.advance(3);
+            return text_of(pos, text).map(|s| Some(Event::Comment(s)));
+        }
+        if input.starts_with(b"<![CDATA[") {
+            bs.advance(9);
+            let text = before_seq(b"]]>").do_match(bs)?;
+            bs.advance(3);
+            return text_of(pos, text).map(|s| Some(Event::CData(s)));
+        }
+        if input.starts_with(b"<?") {
+            before_seq(b"?>").do_match(bs)?;
+            bs.advance(2);
+            return Ok(None);
+        }
+        if input.starts_with(b"<!") {
+            // <!DOCTYPE ...>, an internal subset with markup declarations is not supported
+            before('>').do_match(bs)?;
+            bs.advance(1);
+            return Ok(None);
+        }
+        if input.starts_with(b"</") {
+            bs.advance(2);
+            let raw = text_of(pos, before('>').do_match(bs)?)?.trim_end().to_string();
+            bs.advance(1);
+            let name = match self.open.last() {
+                Some((open, _)) if *open == raw => self.qname(pos, &raw, true)?,
+                Some((open, _)) => {
+                    let expected = format!("</{}>", open);
+                    return Err(MatchError::unexpected(pos, format!("</{}>", raw), expected));
+                }
+                None => return Err(MatchError::unexpected(pos, format!("</{}>", raw), "start tag".to_string())),
+            };
+            self.open.pop();
+            return Ok(Some(Event::End { name }));
+        }
+        if input.starts_with(b"<") {
+            return self.start_tag().map(Some);
+        }
+
+        let raw = match before('<').do_match(bs) {
+            Ok(raw) => raw,
+            Err(e) if e.is_incomplete() => return Err(e),
+            Err(_) => rest().do_match(bs)?,
+        };
+        let text = decode(pos, &raw)?;
+        if text.chars().all(char::is_whitespace) {
+            return Ok(None);
+        }
+        Ok(Some(Event::Text(text)))
+    }
+
+    fn start_tag(&mut self) -> Result<Event, MatchError> {
+        let bs = &mut self.stream;
+        let pos = bs.pos();
+        single('<').do_match(bs)?;
+        let raw = text_of(pos, name().do_match(bs)?)?;
+        let mut raw_attrs = vec![];
+        let empty = loop {
+            space0().do_match(bs)?;
+            match bs.as_ref() {
+                [b'>', ..] => {
+                    bs.advance(1);
+                    break false;
+                }
+                [b'/', b'>', ..] => {
+                    bs.advance(2);
+                    break true;
+                }
+                [] | [b'/'] => return Err(MatchError::incomplete(bs.len(), 1)),
+                _ => (),
+            }
+            let at = bs.pos();
+            let attr = text_of(at, name().do_match(bs)?)?;
+            space0().do_match(bs)?;
+            single('=').do_match(bs)?;
+            space0().do_match(bs)?;
+            let quote = match bs.as_ref().first() {
+                Some(q) if *q == b'"' || *q == b'\'' => *q as char,
+                Some(b) => return Err(MatchError::unexpected(bs.pos(), (*b as char).to_string(), "quote".to_string())),
+                None => return Err(MatchError::incomplete(bs.len(), 1)),
+            };
+            bs.advance(1);
+            let value = decode(at, &before(quote).do_match(bs)?)?;
+            bs.advance(1);
+            raw_attrs.push((at, attr, value));
+        };
+
+        let mut bindings = vec![];
+        for (_, attr, value) in raw_attrs.iter() {
+            if attr == "xmlns" {
+                bindings.push((None, value.clone()));
+            } else if let Some(prefix) = attr.strip_prefix("xmlns:") {
+                bindings.push((Some(prefix.to_string()), value.clone()));
+            }
+        }
+        self.open.push((raw.clone(), bindings));
+        let result = self.resolve(pos, &raw, raw_attrs);
+        let (name, attributes) = match result {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.open.pop();
+                return Err(e);
+            }
+        };
+        if empty {
+            self.open.pop();
+            self.pending_end = Some(name.clone());
+        }
+        Ok(Event::Start { name, attributes })
+    }
+
+    fn resolve(&self, pos: usize, raw: &str, raw_attrs: Vec<(usize, String, String)>) -> Result<(QName, Vec<Attribute>), MatchError> {
+        let name = self.qname(pos, raw, true)?;
+        let mut attributes = vec![];
+        for (at, attr, value) in raw_attrs {
+            if attr == "xmlns" || attr.starts_with("xmlns:") {
+                continue;
+            }
+            attributes.push(Attribute { name: self.qname(at, &attr, false)?, value });
+        }
+        Ok((name, attributes))
+    }
+
+    // the default namespace applies to element names only
+    fn qname(&self, pos: usize, raw: &str, element: bool) -> Result<QName, MatchError> {
+        let (prefix, local) = match raw.find(':') {
+            Some(idx) => (Some(&raw[..idx]), &raw[idx + 1..]),
+            None => (None, raw),
+        };
+        let namespace = match prefix {
+            Some("xml") => Some(XML_NS.to_string()),
+            None if !element => None,
+            _ => {
+                let found = self.open.iter().rev()
+                    .flat_map(|(_, bindings)| bindings.iter().rev())
+                    .find(|(p, _)| p.as_deref() == prefix)
+                    .map(|(_, ns)| ns.clone());
+                if found.is_none() && prefix.is_some() {
+                    return Err(MatchError::unexpected(pos, raw.to_string(), "declared namespace prefix".to_string()));
+                }
+                found.filter(|ns| !ns.is_empty())
+            }
+        };
+        Ok(QName {
+            prefix: prefix.map(str::to_string),
+            local: local.to_string(),
+            namespace,
+        })
+    }
+}
+
+impl Iterator for XmlReader {
+    type Item = Result<Event, MatchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(name) = self.pending_end.take() {
+            return Some(Ok(Event::End { name }));
+        }
+        while !self.failed {
+            if self.stream.remaining() == 0 {
+                if self.stream.is_partial() || self.open.is_empty() {
+                    return None;
+                }
+                self.failed = true;
+                let (open, _) = self.open.last()?;
+                let e = MatchError::unexpected(self.stream.pos(), "EOF".to_string(), format!("</{}>", open));
+                return Some(Err(e));
+            }
+            let mark = self.stream.mark();
+            match self.event() {
+                Ok(Some(event)) => return Some(Ok(event)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.stream.reset(mark);
+                    if e.is_incomplete() && self.stream.is_partial() {
+                        return None;
+                    }
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn name() -> impl Matcher<Vec<u8>> {
+    until(|b| !b.is_ascii_whitespace() && !b"/>=<\"'".contains(&b)).verify(|name| !name.is_empty())
+}
+
+fn text_of(pos: usize, raw: Vec<u8>) -> Result<String, MatchError> {
+    String::from_utf8(raw)
+        .map_err(|e| MatchError::unexpected(pos, "invalid UTF-8".to_string(), "text".to_string()).with_source(e))
+}
+
+// the predefined entities and character references
+fn decode(pos: usize, raw: &[u8]) -> Result<String, MatchError> {
+    let text = text_of(pos, raw.to_vec())?;
+    if !text.contains('&') {
+        return Ok(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text.as_str();
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        let end = rest[idx..].find(';')
+            .ok_or_else(|| MatchError::unexpected(pos, rest[idx..].to_string(), "';'".to_string()))?;
+        let entity = &rest[idx + 1..idx + end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        let c = c.ok_or_else(|| MatchError::unexpected(pos, format!("&{};", entity), "entity".to_string()))?;
+        out.push(c);
+        rest = &rest[idx + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE rss>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>Tom &amp; Jerry &#x263A;</title>
+    <atom:link href="https://example.com/feed" rel='self'/>
+    <!-- items follow -->
+    <description><![CDATA[<b>bold</b>]]></description>
+  </channel>
+</rss>"#;
+
+    fn events(text: &str) -> Vec<Result<Event, MatchError>> {
+        XmlReader::new(ByteStream::wrap(text.as_bytes().to_vec())).collect()
+    }
+
+    #[test]
+    fn rss() {
+        let events = events(FEED).into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(events.len(), 13);
+        assert_eq!(events[0].attribute("version"), Some("2.0"));
+        assert_eq!(events[2], Event::Start {
+            name: QName { prefix: None, local: "title".to_string(), namespace: None },
+            attributes: vec![],
+        });
+        assert_eq!(events[3], Event::Text("Tom & Jerry \u{263A}".to_string()));
+        match &events[5] {
+            Event::Start { name, attributes } => {
+                assert_eq!(name.prefix.as_deref(), Some("atom"));
+                assert_eq!(name.namespace.as_deref(), Some("http://www.w3.org/2005/Atom"));
+                assert_eq!(attributes[1].value, "self");
+                assert_eq!(attributes[1].name.namespace, None);
+            }
+            e => panic!("{:?}", e),
+        }
+        assert!(matches!(&events[6], Event::End { name } if name.local == "link"));
+        assert_eq!(events[7], Event::Comment(" items follow ".to_string()));
+        assert_eq!(events[9], Event::CData("<b>bold</b>".to_string()));
+    }
+
+    #[test]
+    fn default_namespace() {
+        let text = r#"<Envelope xmlns="urn:soap"><Body xmlns=""><x:Fault xmlns:x="urn:x"/></Body></Envelope>"#;
+        let names = events(text)
+            .into_iter()
+            .filter_map(|e| match e.unwrap() {
+                Event::Start { name, .. } => Some((name.local, name.namespace)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![
+            ("Envelope".to_string(), Some("urn:soap".to_string())),
+            ("Body".to_string(), None),
+            ("Fault".to_string(), Some("urn:x".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn malformed() {
+        for text in ["<a><b></a>", "<a>", "</a>", "<a x=1/>", "<p:a/>", "<a>&nbsp;</a>"] {
+            let events = events(text);
+            assert!(events.last().unwrap().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn partial() {
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"<a href=\"x");
+        let mut reader = XmlReader::new(bs);
+        assert!(reader.next().is_none());
+        reader.stream_mut().put(b"\">te");
+        assert_eq!(reader.next().unwrap().unwrap().attribute("href"), Some("x"));
+        assert!(reader.next().is_none());
+        assert_eq!(reader.depth(), 1);
+    }
+
+    #[test]
+    fn resume() {
+        let mut bs = ByteStream::with_capacity(64);
+        bs.set_partial(true);
+        bs.put(b"<a xmlns:p=\"urn:p\"><p:b>");
+        let mut reader = XmlReader::new(bs);
+        assert!(matches!(reader.next(), Some(Ok(Event::Start { .. }))));
+        assert!(matches!(reader.next(), Some(Ok(Event::Start { .. }))));
+        assert!(reader.next().is_none());
+        assert_eq!(reader.depth(), 2);
+
+        reader.stream_mut().put(b"</p:b></a>");
+        let ends = reader
+            .map(|e| match e.unwrap() {
+                Event::End { name } => (name.local, name.namespace),
+                e => panic!("{:?}", e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ends, vec![
+            ("b".to_string(), Some("urn:p".to_string())),
+            ("a".to_string(), None),
+        ]);
+    }
+}